@@ -1,4 +1,4 @@
-use std::{io, result, fmt};
+use std::{io, result};
 
 use crate::{MAGIC_LEN, ColTy, LitTy, CLit, AggOp, BinOp, CmpOp};
 
@@ -16,6 +16,12 @@ pub enum ParserErrorKind<'a> {
   InvalidTypeSize(&'a str),
   InvalidInt(&'a str),
   InvalidFloat(&'a str),
+  // a hex literal with odd number of digits
+  InvalidHex(&'a str),
+  // the delimiter of `copy` is not a single character, or is one of '"', '\r' and '\n'
+  InvalidDelimiter(&'a str),
+  // the format of `copy to` is not one of csv, jsonl and tsv
+  InvalidFormat(&'a str),
 }
 
 #[derive(Debug)]
@@ -23,7 +29,28 @@ pub enum Error<'a> {
   ParserErrors(Box<[ParserError<'a>]>),
   InvalidSize { size: usize, expect_multiply_of: usize },
   InvalidMagic([u8; MAGIC_LEN]),
+  // `<db>.sum` has a wrong size, so the checksums cannot be trusted; deleting it makes all pages trusted
+  InvalidSumSize { size: usize, expect: usize },
+  // the file is written by a newer version of this crate
+  UnsupportedVersion { version: u8, current: u8 },
+  // the file uses optional structures unknown to this version, `.0` is the unknown bits
+  UnsupportedFeatures(u8),
+  // the checksum of page `page` doesn't match, `kind` is the type of the page (e.g.: "DataPage")
+  PageCorrupted { page: u32, kind: &'static str },
   NoDbInUse,
+  // the database is being written by another session (possibly in another process), or being read when this session wants to write
+  DatabaseLocked,
+  // modify a database opened by `Db::open_read_only`, or open a database that needs recovery or upgrading in that way
+  ReadOnlyDatabase,
+  // `begin` inside a transaction
+  NestedTransaction,
+  // `backup` inside a transaction, whose uncommitted changes would be in the copy
+  BackupInTransaction,
+  // `commit` or `rollback` outside a transaction
+  NoTransaction,
+  // a modifying statement failed because of `.0` after some rows were changed, all its changes are rolled back, so 0 rows are changed
+  // an error raised before any row is changed is returned as it is (the statement has no effect either)
+  RolledBack(Box<Error<'a>>),
   TableExhausted,
   ColTooMany(usize),
   // not support table with 0 col
@@ -32,6 +59,12 @@ pub enum Error<'a> {
   TableNameTooLong(&'a str),
   ColNameTooLong(&'a str),
   IndexNameTooLong(&'a str),
+  // a composite index has more than `MAX_INDEX_COL` cols
+  IndexColTooMany(usize),
+  // the data part of a composite key (see `IndexInfo::key_layout`) is larger than `MAX_MULTI_KEY`
+  IndexKeyTooBig(usize),
+  // a table has more than `MAX_MULTI_INDEX` composite indexes
+  IndexTooMany(usize),
   DupTable(&'a str),
   DupCol(&'a str),
   DupIndex(&'a str),
@@ -51,7 +84,7 @@ pub enum Error<'a> {
   InvalidLike { like: &'a str, reason: Box<regex::Error> },
   InvalidLikeTy(ColTy),
   InvalidLikeTy1(LitTy),
-  // some operation on Varchar is not supported, e.g., create index (thus primary/foreign/unique are not supported, either)
+  // some operation on Varchar (also Text and Blob) is not supported, e.g., create index (thus primary/foreign/unique are not supported, either)
   UnsupportedVarcharOp(&'a str),
   // require them to be exactly the same (including BareTy and size, in order to search each other in index page)
   IncompatibleForeignTy { foreign: ColTy, own: ColTy },
@@ -76,23 +109,12 @@ pub enum Error<'a> {
   IncompatibleBin { op: BinOp, ty: LitTy },
   IncompatibleCmp { op: CmpOp, l: LitTy, r: LitTy },
   IncompatibleLogic(LitTy),
+  // `copy from` fails at the record starting from line `line` of the file,
+  // `reason` is formatted from the error, because the error may refer to the file content; no record in the file is inserted
+  CopyFailed { line: usize, reason: String },
   IO(io::Error),
 }
 
-// after modifying `self.0` columns, a `self.1` error occurs
-pub struct ModifyError<'a>(pub u32, pub Error<'a>);
-
 pub type Result<'a, T> = result::Result<T, Error<'a>>;
-pub type ModifyResult<'a, T> = result::Result<T, ModifyError<'a>>;
 
 impl From<io::Error> for Error<'_> { fn from(e: io::Error) -> Self { Error::IO(e) } }
-
-impl From<io::Error> for ModifyError<'_> { fn from(e: io::Error) -> Self { Self(0, e.into()) } }
-
-impl<'a> From<Error<'a>> for ModifyError<'a> { fn from(e: Error<'a>) -> Self { Self(0, e) } }
-
-impl fmt::Debug for ModifyError<'_> {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}; {} column(s) affected", self.1, self.0)
-  }
-}
\ No newline at end of file
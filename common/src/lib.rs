@@ -10,6 +10,10 @@ pub use crate::{unsafe_helper::*, errors::*, ty::*};
 pub const MAGIC_LEN: usize = 18;
 pub const MAGIC: &[u8; MAGIC_LEN] = b"MashPlant-DataBase";
 pub const LOB_SUFFIX: &str = "lob";
+pub const JOURNAL_SUFFIX: &str = "journal";
+pub const SUM_SUFFIX: &str = "sum";
+// the name of a database that lives only in memory, e.g.: `create database :memory:`
+pub const IN_MEMORY: &str = ":memory:";
 pub const LOG_MAX_SLOT: usize = 9;
 pub const MAX_PAGE: usize = 1 << (32 - LOG_MAX_SLOT);
 pub const MAX_SLOT: usize = 1 << LOG_MAX_SLOT; // 512 (actually can hold up to MAX_DATA_BYTE / MIN_SLOT_SIZE = 507)
@@ -1,6 +1,6 @@
-use std::{fmt, cmp::Ordering, mem, marker::PhantomData};
+use std::{fmt, cmp::Ordering, mem, slice, marker::PhantomData};
 use chrono::NaiveDate;
-use crate::{impossible, varchar, VARCHAR_SLOT_SIZE};
+use crate::{impossible, VARCHAR_SLOT_SIZE};
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -13,9 +13,10 @@ pub struct FixTy {
   pub size: u8,
 }
 
+// `Text` and `Blob` have no size limit (except u32), `Text` is like `Varchar`, `Blob` holds arbitrary bytes
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum ColTy { FixTy(FixTy), Varchar(u16) }
+pub enum ColTy { FixTy(FixTy), Varchar(u16), Text, Blob }
 
 impl ColTy {
   #[cfg_attr(tarpaulin, ignore)]
@@ -25,17 +26,21 @@ impl ColTy {
     assert_eq_size!(NaiveDate, u32);
   }
 
-  pub fn is_varchar(self) -> bool { match self { ColTy::FixTy(_) => false, varchar!() => true } }
+  // varchar, text and blob are all stored in lob, the data slot only keeps where it is; most operations treat them in the same way
+  pub fn is_varchar(self) -> bool { match self { ColTy::FixTy(_) => false, _ => true } }
+
+  // text and blob, they have different data slot layout from varchar
+  pub fn is_long(self) -> bool { match self { ColTy::Text | ColTy::Blob => true, _ => false } }
 
   // guarantee: !self.is_varchar() <=> self.fix_ty() is safe
-  pub unsafe fn fix_ty(self) -> FixTy { match self { ColTy::FixTy(x) => x, varchar!() => impossible!() } }
+  pub unsafe fn fix_ty(self) -> FixTy { match self { ColTy::FixTy(x) => x, _ => impossible!() } }
 
   // char and varchar can have size = 255 + 1, so u16 is necessary
   pub fn size(self) -> u16 {
     use BareTy::*;
     match self {
       ColTy::FixTy(ty) => match ty.ty { Bool => 1, Int | Float => 4, Date => 4, Char => ty.size as u16 + 1 }
-      varchar!() => VARCHAR_SLOT_SIZE as u16,
+      _ => VARCHAR_SLOT_SIZE as u16,
     }
   }
 
@@ -43,25 +48,26 @@ impl ColTy {
     use BareTy::*;
     match self {
       ColTy::FixTy(ty) => match ty.ty { Bool | Char => false, Int | Float | Date => true }
-      varchar!() => true,
+      _ => true,
     }
   }
 }
 
 // `Date` can not be produced by parser, but can be used to pass the result of select
+// `Blob` comes from hex literals (x'0aff') or blob cols
 #[derive(Copy, Clone)]
-pub enum Lit<'a> { Null, Bool(bool), Number(f64), Date(NaiveDate), Str(&'a str) }
+pub enum Lit<'a> { Null, Bool(bool), Number(f64), Date(NaiveDate), Str(&'a str), Blob(&'a [u8]) }
 
 // the discriminant of Lit
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum LitTy { Null, Bool, Number, Date, Str }
+pub enum LitTy { Null, Bool, Number, Date, Str, Blob }
 
-impl Lit<'_> {
+impl<'a> Lit<'a> {
   pub fn is_null(&self) -> bool { match self { Lit::Null => true, _ => false, } }
 
   pub fn ty(&self) -> LitTy {
     use Lit::*;
-    match self { Null => LitTy::Null, Bool(_) => LitTy::Bool, Number(_) => LitTy::Number, Date(_) => LitTy::Date, Str(_) => LitTy::Str }
+    match self { Null => LitTy::Null, Bool(_) => LitTy::Bool, Number(_) => LitTy::Number, Date(_) => LitTy::Date, Str(_) => LitTy::Str, Blob(_) => LitTy::Blob }
   }
 
   // only accept the same variant to compare,
@@ -72,9 +78,15 @@ impl Lit<'_> {
       (&Lit::Number(l), &Lit::Number(r)) => fcmp(l, r),
       (Lit::Date(l), Lit::Date(r)) => l.cmp(r),
       (Lit::Str(l), Lit::Str(r)) => l.cmp(r),
+      (Lit::Blob(l), Lit::Blob(r)) => l.cmp(r),
       _ => impossible!(),
     }
   }
+
+  // the content to store in lob, only for `Str` and `Blob`
+  pub unsafe fn bytes(self) -> &'a [u8] {
+    match self { Lit::Str(x) => x.as_bytes(), Lit::Blob(x) => x, _ => impossible!() }
+  }
 }
 
 // this can be used for the comparison between non-nan float (in the database we always guarantee float is not-nan)
@@ -92,16 +104,22 @@ impl fmt::Debug for Lit<'_> {
     use Lit::*;
     match *self {
       Null => write!(f, "null"), Bool(x) => write!(f, "{}", x), Number(x) => write!(f, "{}", x),
-      Date(x) => write!(f, "{}", x), Str(x) => write!(f, "'{}'", x)
+      Date(x) => write!(f, "{}", x), Str(x) => write!(f, "'{}'", x),
+      Blob(x) => (f.write_str("x'")?, x.iter().try_for_each(|b| write!(f, "{:02X}", b))?, f.write_str("'")).2,
     }
   }
 }
 
 // C for Compressed: Lit takes 24 bytes of space, which is not efficient enough
 // Lit is used in functions to implement logic, CLit is used in data structures to save space
+// `Str` and `Blob` are stored as (ptr, len), a valid ptr is never less than 5, and `Blob` sets the highest bit of len
+// the ptr of an empty slice may dangle (e.g. it is 1 for an empty `Vec<u8>`), so it is replaced by `EMPTY`
 #[derive(Copy, Clone)]
 pub struct CLit<'a>(u64, u64, PhantomData<&'a str>);
 
+const BLOB_BIT: u64 = 1 << 63;
+const EMPTY: u64 = 4;
+
 impl<'a> CLit<'a> {
   // I don't expect it to work on a 32-bit system
   #[cfg_attr(tarpaulin, ignore)]
@@ -114,7 +132,8 @@ impl<'a> CLit<'a> {
         Lit::Bool(x) => Self(1, x as u64, PhantomData),
         Lit::Number(x) => Self(2, mem::transmute(x), PhantomData),
         Lit::Date(x) => Self(3, mem::transmute::<_, u32>(x) as u64, PhantomData),
-        Lit::Str(x) => mem::transmute(x),
+        Lit::Str(x) => if x.is_empty() { Self(EMPTY, 0, PhantomData) } else { mem::transmute(x) },
+        Lit::Blob(x) => Self(if x.is_empty() { EMPTY } else { x.as_ptr() as u64 }, x.len() as u64 | BLOB_BIT, PhantomData),
       }
     }
   }
@@ -126,6 +145,8 @@ impl<'a> CLit<'a> {
         1 => Lit::Bool(self.1 != 0),
         2 => Lit::Number(mem::transmute(self.1)),
         3 => Lit::Date(mem::transmute(self.1 as u32)),
+        EMPTY => if self.1 & BLOB_BIT != 0 { Lit::Blob(&[]) } else { Lit::Str("") },
+        _ if self.1 & BLOB_BIT != 0 => Lit::Blob(slice::from_raw_parts(self.0 as *const u8, (self.1 & !BLOB_BIT) as usize)),
         _ => Lit::Str(mem::transmute(self))
       }
     }
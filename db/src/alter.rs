@@ -1,3 +1,5 @@
+use unchecked_unwrap::UncheckedUnwrap;
+
 use common::{*, Error::*};
 use physics::*;
 use crate::{Db, is_null};
@@ -5,32 +7,85 @@ use crate::{Db, is_null};
 impl Db {
   // only alloc one index page for ci, records are not inserted into index (this is done by `index` crate)
   // `index` may be an empty string, this means it is an internal index (no extra operation needed)
-  pub unsafe fn alloc_index<'a>(&mut self, ci: &mut ColInfo, index: &'a str) -> Result<'a, ()> {
+  // `ci` should belong to the table `tp_id`; a named index may move the TablePage (see `set_table_names`),
+  // so return its id, and `ci` is invalid after that
+  pub unsafe fn alloc_index(&mut self, tp_id: u32, ci: &mut ColInfo, index: &str) -> u32 {
     debug_assert!(!ci.ty.is_varchar());
-    if index.len() > MAX_IDX_NAME { return Err(IndexNameTooLong(index)); }
-    ci.idx_name_len = index.len() as u8;
-    ci.idx_name.as_mut_ptr().copy_from_nonoverlapping(index.as_ptr(), index.len());
     let (id, ip) = self.alloc_page::<IndexPage>();
-    ci.index = id;
+    (ci.index = id, ci.idx_name_len = 0);
     ip.init(true, ci.ty.size()); // it is the root, but also a leaf
+    if index.is_empty() { return tp_id; }
+    let tp = self.page::<TablePage>(tp_id);
+    let ci_id = ci.idx(tp.cols());
+    let (table, mut cols) = tp.names();
+    cols.get_unchecked_mut(ci_id as usize).1 = index.to_owned();
+    let tp_id = self.set_table_names(tp_id, &table, &cols);
+    self.catalog().add_index(index, tp_id, ci_id as u16);
+    tp_id
+  }
+
+  // add a composite index on `cols` (col ids, which should be fixed-size) with only one index page for its root,
+  // records are not inserted into index (this is done by `index` crate); return its position in the IndexListPage
+  // `index` is empty for the index of a primary key, see `alloc_primary_index`
+  pub unsafe fn alloc_multi_index<'a>(&mut self, tp_id: u32, index: &'a str, cols: &[u16]) -> Result<'a, u32> {
+    debug_assert!(cols.len() <= MAX_INDEX_COL);
+    let tp = self.get_page::<TablePage>(tp_id);
+    let size = key_layout(cols.iter().map(|&ci_id| tp.cols().get_unchecked(ci_id as usize).ty)).1 as usize + 1;
+    if size > MAX_MULTI_KEY { return Err(IndexKeyTooBig(size)); }
+    if tp.indexes == !0 {
+      let (id, list) = self.alloc_page::<IndexListPage>();
+      (tp.indexes = id, list.count = 0, list.page_num = 1, list._rsv = [0; 56]);
+    }
+    let list = self.get_page::<IndexListPage>(tp.indexes);
+    if list.count as usize == MAX_MULTI_INDEX { return Err(IndexTooMany(MAX_MULTI_INDEX + 1)); }
+    // the new IndexInfo may overwrite the name area, so names are copied out first and rewritten after it
+    let mut names = list.names();
+    names.push(index.to_owned());
+    let idx = (list.count, list.count += 1).0;
+    let info = list.indexes.get_unchecked_mut(idx as usize);
+    (info.col_num = cols.len() as u8, info.flags = IndexFlags::empty(), info._rsv = 0);
+    info.cols.get_unchecked_mut(..cols.len()).copy_from_slice(cols);
+    let (id, ip) = self.alloc_page::<IndexPage>();
+    info.index = id;
+    ip.init(true, size as u16);
+    self.set_index_names(tp_id, &names);
+    if !index.is_empty() { self.catalog().add_multi_index(index, tp_id, idx as u8); }
+    Ok(idx)
+  }
+
+  // a primary key on more than one col is backed by a composite index, so that checking duplicate keys needs no scan
+  // `cols` should be the primary cols in the order of TablePage::cols, and should have passed `primary_index_ck`
+  pub unsafe fn alloc_primary_index(&mut self, tp_id: u32, cols: &[u16]) -> u32 {
+    let idx = self.alloc_multi_index(tp_id, "", cols).unchecked_unwrap();
+    self.get_page::<IndexListPage>(self.page::<TablePage>(tp_id).indexes).indexes.get_unchecked_mut(idx as usize).flags = IndexFlags::PRIMARY;
+    idx
+  }
+
+  // check whether a primary key on cols of types `tys` can be backed by a composite index, `tys` should have more than one element
+  // the IndexListPage may be full, which is checked by the caller
+  pub fn primary_index_ck<'a>(tys: &[ColTy]) -> Result<'a, ()> {
+    if tys.len() > MAX_INDEX_COL { return Err(IndexColTooMany(tys.len())); }
+    let size = key_layout(tys.iter().copied()).1 as usize + 1;
+    if size > MAX_MULTI_KEY { return Err(IndexKeyTooBig(size)); }
     Ok(())
   }
 
   pub fn drop_index<'a>(&mut self, index: &'a str, table: Option<&'a str>) -> Result<'a, ()> {
     unsafe {
-      for &tp_id in self.dp().tables() {
-        let tp = self.get_page::<TablePage>(tp_id);
-        for ci in tp.cols() {
-          if ci.idx_name().filter(|&x| !x.is_empty() && x == index).is_some() {
-            // `table` is only for error checking
-            match table { Some(t) if t != tp.name() => return Err(NoSuchIndex(index)), _ => {} };
-            self.dealloc_index(ci.index);
-            ci.pr().index = !0;
-            return Ok(());
-          }
-        }
+      if let Some((tp_id, idx)) = self.multi_index_owner(index) {
+        match table { Some(t) if t != self.page::<TablePage>(tp_id).name() => return Err(NoSuchIndex(index)), _ => {} };
+        self.dealloc_multi_index(tp_id, idx as u32);
+        return Ok(());
       }
-      return Err(NoSuchIndex(index));
+      let (tp_id, ci_id) = self.index_owner(index).ok_or(NoSuchIndex(index))?;
+      let tp = self.get_page::<TablePage>(tp_id);
+      // `table` is only for error checking
+      match table { Some(t) if t != tp.name() => return Err(NoSuchIndex(index)), _ => {} };
+      let ci = tp.cols_mut().get_unchecked_mut(ci_id as usize);
+      self.dealloc_index(ci.index);
+      ci.index = !0;
+      self.catalog().remove_index(index);
+      Ok(())
     }
   }
 
@@ -45,14 +100,31 @@ impl Db {
     }
     dfs(self, root);
   }
+
+  // deallocate the composite index, and remove it from the IndexListPage (which is deallocated if it becomes empty)
+  // the positions of the indexes after it are changed
+  pub unsafe fn dealloc_multi_index(&mut self, tp_id: u32, idx: u32) {
+    let tp = self.get_page::<TablePage>(tp_id);
+    let list = self.get_page::<IndexListPage>(tp.indexes);
+    self.dealloc_index(list.indexes.get_unchecked(idx as usize).index);
+    let mut names = list.names();
+    names.remove(idx as usize);
+    list.count -= 1;
+    let p = list.indexes.as_mut_ptr();
+    p.add(idx as usize).copy_from(p.add(idx as usize + 1), (list.count - idx) as usize);
+    if list.count == 0 { (self.dealloc_pages(tp.indexes, list.page_num), tp.indexes = !0); } else { list.set_names(&names); }
+    self.invalidate_catalog();
+  }
 }
 
 impl Db {
   // unfortunately we don't know whether the index introduced by foreign constraint can be dropped or not, so just leave it here
   pub fn drop_foreign<'a>(&mut self, table: &'a str, col: &'a str) -> Result<'a, ()> {
     unsafe {
-      let ci = self.get_tp(table)?.1.get_ci(col)?;
+      let (tp_id, tp) = self.get_tp(table)?;
+      let ci = tp.get_ci(col)?;
       if ci.f_table == !0 { return Err(NoSuchForeign(col)); }
+      self.catalog().remove_foreign(tp_id, ci.idx(tp.cols()) as u16, ci.f_table);
       ci.f_table = !0;
       Ok(())
     }
@@ -60,10 +132,11 @@ impl Db {
 
   pub fn rename_table<'a>(&mut self, old: &'a str, new: &'a str) -> Result<'a, ()> {
     unsafe {
-      let tp = self.get_tp(old)?.1;
-      if new.len() > MAX_TABLE_NAME { return Err(TableNameTooLong(new)); }
-      tp.name_len = new.len() as u8;
-      tp.name.as_mut_ptr().copy_from_nonoverlapping(new.as_ptr(), new.len());
+      let (tp_id, tp) = self.get_tp(old)?;
+      if new != old && self.get_tp(new).is_ok() { return Err(DupTable(new)); }
+      let cols = tp.names().1;
+      self.set_table_names(tp_id, new, &cols);
+      self.catalog().rename_table(old, new);
       Ok(())
     }
   }
@@ -72,32 +145,36 @@ impl Db {
 impl Db {
   pub fn drop_table<'a>(&mut self, table: &'a str) -> Result<'a, ()> {
     unsafe {
-      let dp = self.dp();
-      for (idx, &tp_id) in dp.tables().iter().enumerate() {
-        let tp = self.get_page::<TablePage>(tp_id);
-        if tp.name() == table {
-          if self.foreign_links_to(tp_id).next().is_some() { return Err(ModifyTableWithForeignLink(table)); }
-          let tables = dp.tables.as_mut_ptr();
-          tables.add(idx).swap(tables.add(dp.table_num as usize - 1));
-          dp.table_num -= 1;
-          for ci in tp.cols() {
-            if ci.index != !0 { self.dealloc_index(ci.index); }
-            if ci.check != !0 { self.dealloc_page(ci.check >> 1); }
-          }
-          if tp.cols().iter().any(|ci| ci.ty.is_varchar()) {
-            for (data, _) in self.record_iter(tp) {
-              for (ci_id, ci) in tp.cols().iter().enumerate() {
-                if !is_null(data, ci_id as u32) && ci.ty.is_varchar() {
-                  self.free_varchar(data.add(ci.off as usize));
-                }
-              }
+      let (tp_id, tp) = self.get_tp(table)?;
+      if self.foreign_links_to(tp_id).next().is_some() { return Err(ModifyTableWithForeignLink(table)); }
+      let mut tables = self.tables();
+      let idx = tables.iter().position(|&x| x == tp_id).unchecked_unwrap();
+      tables.swap_remove(idx);
+      self.set_tables(&tables);
+      self.catalog().remove_table(tp_id, tp);
+      self.forget_table(tp_id);
+      for ci in tp.cols() {
+        if ci.index != !0 { self.dealloc_index(ci.index); }
+        if ci.check != !0 { self.dealloc_page(ci.check >> 1); }
+      }
+      if tp.indexes != !0 {
+        let list = self.page::<IndexListPage>(tp.indexes);
+        for info in list.indexes() { self.dealloc_index(info.index); }
+        self.dealloc_pages(tp.indexes, list.page_num);
+      }
+      if tp.cols().iter().any(|ci| ci.ty.is_varchar()) {
+        let rows = self.record_iter(tp).map(|(data, _)| data).collect::<Vec<_>>();
+        for data in rows {
+          for (ci_id, ci) in tp.cols().iter().enumerate() {
+            if !is_null(data, ci_id as u32) && ci.ty.is_varchar() {
+              self.free_varchar(data.add(ci.off as usize), ci.ty);
             }
           }
-          self.drop_list(tp.first);
-          return Ok(());
         }
       }
-      Err(NoSuchTable(table))
+      self.drop_list(tp.first);
+      self.dealloc_pages(tp_id, tp.page_num);
+      Ok(())
     }
   }
 
@@ -109,4 +186,63 @@ impl Db {
       first = next;
     }
   }
-}
\ No newline at end of file
+}
+
+impl Db {
+  // move records in the last data pages to the holes in the first data pages (ordered by page id), and deallocate empty pages
+  // rids are changed, so the caller should rebuild all indexes of this table
+  pub unsafe fn compact_data(&mut self, tp: &mut TablePage) {
+    let mut pages = Vec::new();
+    let mut cur = tp.first;
+    while cur != !0 { (pages.push(cur), cur = self.get_page::<DataPage>(cur).next); }
+    pages.sort_unstable();
+    let (size, cap) = (tp.size as usize, tp.cap as usize);
+    // pages[..lo] are full, pages[hi..] are empty, `d` and `s` are the slots to start searching in pages[lo] and pages[hi - 1]
+    let (mut lo, mut hi, mut d, mut s) = (0, pages.len(), 0, 0);
+    while lo + 1 < hi {
+      let dst = self.get_page::<DataPage>(*pages.get_unchecked(lo));
+      let src = self.get_page::<DataPage>(*pages.get_unchecked(hi - 1));
+      if dst.count as usize == cap { (lo += 1, d = 0); continue; }
+      if src.count == 0 { (hi -= 1, s = 0); continue; }
+      while bsget(dst.used.as_ptr(), d) { d += 1; }
+      while !bsget(src.used.as_ptr(), s) { s += 1; }
+      dst.data.as_mut_ptr().add(d * size).copy_from_nonoverlapping(src.data.as_ptr().add(s * size), size);
+      (bsset(dst.used.as_mut_ptr(), d), dst.count += 1);
+      (bsdel(src.used.as_mut_ptr(), s), src.count -= 1);
+    }
+    // the loop stops at `lo + 1 == hi` without looking at pages[lo], which may be empty too
+    while hi > 0 && self.get_page::<DataPage>(*pages.get_unchecked(hi - 1)).count == 0 { hi -= 1; }
+    for &page in pages.get_unchecked(hi..) { self.dealloc_page(page); }
+    let mut next = !0;
+    for &page in pages.get_unchecked(..hi).iter().rev() {
+      let dp = self.get_page::<DataPage>(page);
+      (dp.next = next, dp.next_free = !0, next = page);
+    }
+    tp.first = next;
+    // only the last page may be not full
+    tp.first_free = match pages.get_unchecked(..hi).last() {
+      Some(&last) if (self.get_page::<DataPage>(last).count as usize) < cap => last,
+      _ => !0,
+    };
+  }
+
+  // sort the free list by page id, and truncate trailing free pages from the file
+  pub unsafe fn shrink<'a>(&mut self) -> Result<'a, ()> {
+    let mut free = Vec::new();
+    // all free pages are touched by `get_page` before truncation, so they can be restored on rollback
+    let mut cur = self.dp().first_free;
+    while cur != !0 { (free.push(cur), cur = *self.get_page::<u32>(cur)); }
+    free.sort_unstable();
+    let mut pages = self.pages;
+    while free.last() == Some(&(pages - 1)) { (free.pop(), pages -= 1); }
+    if pages != self.pages {
+      self.pager.set_pages(pages)?;
+      self.pages = pages;
+      self.resize_sums(pages)?;
+    }
+    let dp = self.dp();
+    dp.first_free = !0;
+    for &page in free.iter().rev() { (*self.get_page::<u32>(page) = dp.first_free, dp.first_free = page); }
+    Ok(())
+  }
+}
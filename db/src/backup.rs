@@ -0,0 +1,49 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Write}, path::Path, slice};
+
+use common::{*, Error::*};
+use physics::*;
+use crate::{Db, db::lock};
+
+// open an existing file of the target for writing and lock it, a missing one is not created yet
+fn open_locked<'a>(path: &Path) -> Result<'a, Option<File>> {
+  match OpenOptions::new().write(true).open(path) {
+    Ok(file) => (lock(&file, false)?, Ok(Some(file))).1,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(e.into()),
+  }
+}
+
+impl Db {
+  // write a copy of the database to `path`, it only needs `&self`, so it can run together with other readers (see `SharedDb`)
+  // no writer can run at the same time (this session has `&self`, other processes are blocked by the file lock), so the copy is consistent
+  // the copy is opened once, so that the free pages at the end of its page file are truncated
+  pub fn backup_to<'a>(&self, path: impl AsRef<Path>) -> Result<'a, ()> {
+    // the changes of an unfinished transaction cannot be excluded from the copy
+    if self.in_transaction() { return Err(BackupInTransaction); }
+    let path = path.as_ref();
+    // all pages are verified before the target is touched, a corrupted page is not spread to the copy
+    let sums = (0..self.pages).map(|page| unsafe { self.page_sum(page, "page") }).collect::<Result<Vec<_>>>()?;
+    // the existing files are locked before any file is created or truncated, they may be a database in use (including this one)
+    let (file, lob_file) = (open_locked(path)?, open_locked(&path.with_extension(LOB_SUFFIX))?);
+    let mut opt = OpenOptions::new().write(true).create(true).clone();
+    let (mut file, mut lob_file) = (file.map_or_else(|| opt.open(path), Ok)?, lob_file.map_or_else(|| opt.open(path.with_extension(LOB_SUFFIX)), Ok)?);
+    (lock(&file, false)?, lock(&lob_file, false)?); // the new files are locked too
+    (file.set_len(0)?, lob_file.set_len(0)?);
+    // a journal left by an old database with the same name would be applied to the copy
+    if let Err(e) = fs::remove_file(path.with_extension(JOURNAL_SUFFIX)) {
+      if e.kind() != io::ErrorKind::NotFound { return Err(e.into()); }
+    }
+    let mut sum_file = opt.truncate(true).open(path.with_extension(SUM_SUFFIX))?;
+    unsafe {
+      // pages are not contiguous in memory for all pagers, lob slots are
+      for page in 0..self.pages { file.write_all(slice::from_raw_parts(self.pager.page(page), PAGE_SIZE))?; }
+      lob_file.write_all(slice::from_raw_parts(self.pager.lob(0), self.lob_slots as usize * LOB_SLOT_SIZE))?;
+      sum_file.write_all(slice::from_raw_parts(sums.as_ptr() as *const u8, sums.len() * 4))?;
+    }
+    (file.sync_all()?, lob_file.sync_all()?, sum_file.sync_all()?);
+    (drop(file), drop(lob_file));
+    let mut copy = Db::open(path)?;
+    copy.transaction(|db| unsafe { db.shrink() })?;
+    copy.sync()
+  }
+}
@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+use unchecked_unwrap::UncheckedUnwrap;
+
+use physics::*;
+use crate::{Db, Unzipped, is_null};
+
+// the slot counts of the pages in one level with `total` slots, each page is filled to `fill_factor`% of `cap` if possible
+// a page never has `cap` slots, and each page has at least `cap / 2` slots if there are more than one pages (see `Index::debug_check`)
+fn page_counts(total: usize, cap: usize, fill_factor: u8) -> Vec<usize> {
+  let n = (cap * fill_factor as usize / 100).min(cap - 1).max(cap / 2).max(1);
+  let (mut counts, r) = (vec![n; total / n], total % n);
+  if counts.is_empty() || r >= cap / 2 { counts.push(r); } else if r != 0 {
+    let last = unsafe { counts.last_mut().unchecked_unwrap() };
+    // the last page takes the rest, or the last 2 pages share them evenly (then each one has at least `cap / 2` slots)
+    if *last + r < cap { *last += r; } else {
+      let tot = *last + r;
+      *last = tot - tot / 2;
+      counts.push(tot / 2);
+    }
+  }
+  counts
+}
+
+// the keys (see `key_layout`) of the records `rows` in the composite index `info`, records whose first col is null are skipped
+// return the keys (u32 for alignment) with their rids, and the offset of the rid in a slot
+pub(crate) unsafe fn multi_keys(tp: &TablePage, info: &IndexInfo, rows: &[(*const u8, Rid)]) -> (Vec<(Vec<u32>, Rid)>, usize) {
+  let (offs, null_off) = info.key_layout(tp);
+  let (null_off, rid_off) = (null_off as usize, (null_off as usize + 4) & !3);
+  let first = *info.cols().get_unchecked(0) as u32;
+  let keys = rows.iter().filter(|&&(data, _)| !is_null(data, first)).map(|&(data, rid)| {
+    let mut key = vec![0u32; rid_off / 4];
+    let p = key.as_mut_ptr() as *mut u8;
+    for (i, (&ci_id, &off)) in info.cols().iter().zip(&offs).enumerate() {
+      let ci = tp.cols().get_unchecked(ci_id as usize);
+      if is_null(data, ci_id as u32) { *p.add(null_off) |= 1 << i; } else { p.add(off as usize).copy_from_nonoverlapping(data.add(ci.off as usize), ci.ty.size() as usize); }
+    }
+    (key, rid)
+  }).collect();
+  (keys, rid_off)
+}
+
+// compare the data part of two keys of the composite index `info` by their values, a null col is less than any value
+// it agrees with `MultiCmp` in `index` crate
+pub(crate) unsafe fn multi_key_cmp(tp: &TablePage, info: &IndexInfo) -> impl Fn(&Db, *const u8, *const u8) -> Ordering {
+  let (offs, null_off) = info.key_layout(tp);
+  let null_off = null_off as usize;
+  let cols = info.cols().iter().zip(offs).map(|(&ci_id, off)| (tp.cols().get_unchecked(ci_id as usize).ty, off as usize)).collect::<Vec<_>>();
+  move |db, l, r| {
+    for (i, &(ty, off)) in cols.iter().enumerate() {
+      let (l_null, r_null) = ((*l.add(null_off) >> i) & 1 != 0, (*r.add(null_off) >> i) & 1 != 0);
+      let ord = if l_null || r_null { r_null.cmp(&l_null) } else { db.ptr2lit(l.add(off), ty, &Unzipped::default()).cmp(db.ptr2lit(r.add(off), ty, &Unzipped::default())) };
+      if ord != Ordering::Equal { return ord; }
+    }
+    Ordering::Equal
+  }
+}
+
+impl Db {
+  // build a B+ tree bottom-up from `entries` (pointers to the data part of the keys and their rids, sorted by the keys) instead of
+  // inserting them one by one; `root` must be an empty leaf, it is reused as the first leaf, and other pages are all newly allocated
+  // `fill_factor` is in percentage, pages (except the last ones in each level) are not full, so that later insertions don't split them at once
+  // return the new root
+  pub unsafe fn bulk_build(&mut self, root: u32, entries: &[(*const u8, Rid)], fill_factor: u8) -> u32 {
+    let root_ip = self.get_page::<IndexPage>(root);
+    debug_assert!(root_ip.leaf && root_ip.count == 0);
+    let (rid_off, leaf_cap) = (root_ip.rid_off as usize, root_ip.cap);
+    // (page id, pointer to its first key) of the pages in the level built last
+    let mut pages = Vec::new();
+    let mut it = entries.iter();
+    let mut prev: Option<&mut IndexPage> = None;
+    for (i, count) in page_counts(entries.len(), leaf_cap as usize, fill_factor).into_iter().enumerate() {
+      let (id, ip) = if i == 0 { (root, self.get_page::<IndexPage>(root)) } else { self.alloc_page::<IndexPage>() };
+      (ip.init(true, rid_off as u16), ip.cap = leaf_cap); // leaves share the cap of the root (it may be modified in tests)
+      let slot_size = ip.slot_size() as usize;
+      for j in 0..count {
+        let &(data, rid) = it.next().unchecked_unwrap();
+        let p = ip.data.as_mut_ptr().add(j * slot_size);
+        p.copy_from_nonoverlapping(data, rid_off);
+        *(p.add(rid_off) as *mut Rid) = rid;
+      }
+      ip.count = count as u16;
+      if let Some(prev) = prev { prev.next = id; }
+      pages.push((id, ip.data.as_ptr()));
+      prev = Some(ip);
+    }
+    while pages.len() > 1 {
+      let (key_size, mut parents, mut prev) = (rid_off + 4, Vec::new(), None::<&mut IndexPage>);
+      let mut it = pages.iter();
+      for count in page_counts(pages.len(), MAX_INDEX_BYTES as usize / (key_size + 4), fill_factor) {
+        let (id, ip) = self.alloc_page::<IndexPage>();
+        ip.init(false, rid_off as u16);
+        for j in 0..count {
+          let &(child, key) = it.next().unchecked_unwrap();
+          let p = ip.data.as_mut_ptr().add(j * (key_size + 4));
+          p.copy_from_nonoverlapping(key, key_size);
+          *(p.add(key_size) as *mut u32) = child;
+        }
+        ip.count = count as u16;
+        if let Some(prev) = prev { prev.next = id; }
+        parents.push((id, ip.data.as_ptr()));
+        prev = Some(ip);
+      }
+      pages = parents;
+    }
+    pages.get_unchecked(0).0
+  }
+}
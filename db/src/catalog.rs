@@ -0,0 +1,106 @@
+use common::*;
+use physics::*;
+use crate::Db;
+
+// an in-memory cache of the schema, so that looking up a table, an index or foreign links needs no scan over all TablePages
+// it is built when the database is opened and updated by DDL; a rollback may restore TablePages to any earlier state,
+// so it is simply rebuilt after that
+#[derive(Default)]
+pub struct Catalog {
+  valid: bool,
+  // table name -> TablePage id
+  pub(crate) tables: HashMap<Box<str>, u32>,
+  // index name -> (TablePage id, col id), anonymous indexes are not included
+  indexes: HashMap<Box<str>, (u32, u16)>,
+  // composite index name -> (TablePage id, position in its IndexListPage), indexes of primary keys are not included
+  multi_indexes: HashMap<Box<str>, (u32, u8)>,
+  // TablePage id -> all the (tp_id1, ci_id1, ci_id), where tp_id1.ci_id1 has foreign link to tp_id.ci_id
+  foreigns: HashMap<u32, Vec<(u32, u16, u16)>>,
+}
+
+impl Catalog {
+  pub(crate) fn invalidate(&mut self) { self.valid = false; }
+
+  // `list` is the IndexListPage of this table, if any
+  pub(crate) unsafe fn add_table(&mut self, tp_id: u32, tp: &TablePage, list: Option<&IndexListPage>) {
+    self.tables.insert(tp.name().into(), tp_id);
+    for (ci_id, ci) in tp.cols().iter().enumerate() {
+      if let Some(index) = ci.idx_name().filter(|x| !x.is_empty()) { self.indexes.insert(index.into(), (tp_id, ci_id as u16)); }
+      if ci.f_table != !0 { self.add_foreign(tp_id, ci_id as u16, ci.f_table, ci.f_col); }
+    }
+    for (idx, info) in list.map(|x| x.indexes()).unwrap_or(&[]).iter().enumerate() {
+      if !info.name().is_empty() { self.multi_indexes.insert(info.name().into(), (tp_id, idx as u8)); }
+    }
+  }
+
+  // foreign links to this table should have been checked by the caller, except those from itself
+  pub(crate) unsafe fn remove_table(&mut self, tp_id: u32, tp: &TablePage) {
+    self.tables.remove(tp.name());
+    for (ci_id, ci) in tp.cols().iter().enumerate() {
+      if let Some(index) = ci.idx_name().filter(|x| !x.is_empty()) { self.indexes.remove(index); }
+      if ci.f_table != !0 { self.remove_foreign(tp_id, ci_id as u16, ci.f_table); }
+    }
+    self.foreigns.remove(&tp_id);
+    self.multi_indexes.retain(|_, x| x.0 != tp_id);
+  }
+
+  pub(crate) fn rename_table(&mut self, old: &str, new: &str) {
+    if let Some(tp_id) = self.tables.remove(old) { self.tables.insert(new.into(), tp_id); }
+  }
+
+  pub(crate) fn add_index(&mut self, index: &str, tp_id: u32, ci_id: u16) { self.indexes.insert(index.into(), (tp_id, ci_id)); }
+
+  pub(crate) fn remove_index(&mut self, index: &str) { self.indexes.remove(index); }
+
+  pub(crate) fn add_multi_index(&mut self, index: &str, tp_id: u32, idx: u8) { self.multi_indexes.insert(index.into(), (tp_id, idx)); }
+
+  pub(crate) fn add_foreign(&mut self, tp_id: u32, ci_id: u16, f_table: u32, f_col: u16) {
+    self.foreigns.entry(f_table).or_default().push((tp_id, ci_id, f_col));
+  }
+
+  pub(crate) fn remove_foreign(&mut self, tp_id: u32, ci_id: u16, f_table: u32) {
+    if let Some(links) = self.foreigns.get_mut(&f_table) {
+      links.retain(|&(tp_id1, ci_id1, _)| (tp_id1, ci_id1) != (tp_id, ci_id));
+    }
+  }
+}
+
+impl Db {
+  // read the TablePages directly instead of by `get_page`, which is not necessary for reading and would journal them
+  pub(crate) unsafe fn load_catalog(&mut self) {
+    let mut catalog = Catalog::default();
+    for tp_id in self.tables() {
+      let tp = (self.pager.page(tp_id) as *const TablePage).r();
+      catalog.add_table(tp_id, tp, if tp.indexes != !0 { Some((self.pager.page(tp.indexes) as *const IndexListPage).r()) } else { None });
+    }
+    catalog.valid = true;
+    self.catalog = catalog;
+  }
+
+  pub(crate) unsafe fn catalog<'a>(&mut self) -> &'a mut Catalog {
+    if !self.catalog.valid { self.load_catalog(); }
+    (&mut self.catalog).pr()
+  }
+
+  // the `index` crate modifies TablePages directly, so it needs these two to keep the catalog up to date
+  pub fn add_foreign_link(&mut self, tp_id: u32, ci_id: u16, f_table: u32, f_col: u16) { unsafe { self.catalog().add_foreign(tp_id, ci_id, f_table, f_col); } }
+
+  pub fn invalidate_catalog(&mut self) { self.catalog.invalidate(); }
+
+  // readers cannot reload the catalog, so TablePages are searched directly if it is invalid
+  pub(crate) unsafe fn table_id(&self, table: &str) -> Option<u32> {
+    if self.catalog.valid { return self.catalog.tables.get(table).copied(); }
+    self.tables().into_iter().find(|&tp_id| self.page::<TablePage>(tp_id).name() == table)
+  }
+
+  // return (TablePage id, col id) of the index named `index`
+  pub fn index_owner(&mut self, index: &str) -> Option<(u32, u16)> { unsafe { self.catalog().indexes.get(index).copied() } }
+
+  // return (TablePage id, position in its IndexListPage) of the composite index named `index`
+  pub fn multi_index_owner(&mut self, index: &str) -> Option<(u32, u8)> { unsafe { self.catalog().multi_indexes.get(index).copied() } }
+
+  // return all the (tp_id1, ci_id1, ci_id), where tp_id1.ci_id1 has foreign link to tp_id.ci_id
+  pub unsafe fn foreign_links_to<'a>(&'a mut self, tp_id: u32) -> impl Iterator<Item=(u32, u16, u16)> + 'a {
+    self.catalog().foreigns.get(&tp_id).map(|x| x.as_slice()).unwrap_or(&[]).iter().copied()
+  }
+}
@@ -0,0 +1,387 @@
+use std::{cmp::Ordering, slice};
+use unchecked_unwrap::UncheckedUnwrap;
+
+use common::{*, BareTy::*};
+use physics::*;
+use crate::{Db, Unzipped, is_null, bulk::{multi_keys, multi_key_cmp}};
+
+impl Db {
+  // check the consistency of the whole database, each problem found is described by a message, so empty result means ok
+  // pages are read directly instead of by `get_page`, nothing is modified, and a corrupted page is reported instead of causing panic
+  pub fn check(&mut self) -> Vec<String> {
+    unsafe {
+      let owner = vec![""; self.pages as usize];
+      Checker { db: self, owner, rows: HashMap::default(), lobs: Vec::new(), problems: Vec::new() }.run()
+    }
+  }
+
+  // rebuild the free list of pages, the count and free list of data pages of each table, and the free list of lob slots,
+  // all from the structures reachable from DbPage; a data page chain is cut at its first invalid link
+  // corrupted pages are accepted as they are; `ColInfo::index` of all cols and `IndexInfo::index` of all composite indexes
+  // should be !0, so their old pages are freed
+  pub unsafe fn rebuild_free_lists(&mut self) {
+    self.accept_all_pages();
+    let mut used = vec![false; self.pages as usize];
+    *used.get_unchecked_mut(0) = true;
+    for page in self.table_lists() { *used.get_unchecked_mut(page as usize) = true; }
+    let mut lobs = Vec::new();
+    for tp_id in self.tables() {
+      // `page_num` of TablePages is checked by `verify_catalog` when opening
+      let tp = self.get_page::<TablePage>(tp_id);
+      for page in tp_id..tp_id + tp.page_num { *used.get_unchecked_mut(page as usize) = true; }
+      debug_assert!(tp.cols().iter().all(|ci| ci.index == !0));
+      if tp.indexes != !0 {
+        let list = tp.indexes;
+        let page_num = if list < self.pages { self.page::<IndexListPage>(list).page_num } else { 0 };
+        if page_num != 0 && page_num <= self.pages - list && (list..list + page_num).all(|page| !*used.get_unchecked(page as usize)) {
+          for page in list..list + page_num { *used.get_unchecked_mut(page as usize) = true; }
+        } else { tp.indexes = !0; }
+      }
+      let (mut prev, mut cur) = (None::<&mut DataPage>, tp.first);
+      (tp.first_free = !0, tp.count = 0);
+      while cur != !0 {
+        if cur >= self.pages || *used.get_unchecked(cur as usize) {
+          match prev { Some(dp) => dp.next = !0, None => tp.first = !0 }
+          break;
+        }
+        *used.get_unchecked_mut(cur as usize) = true;
+        let dp = self.get_page::<DataPage>(cur);
+        for i in tp.cap as usize..MAX_SLOT { bsdel(dp.used.as_mut_ptr(), i); }
+        dp.count = dp.used.iter().map(|x| x.count_ones()).sum::<u32>() as u16;
+        tp.count += dp.count as u32;
+        if dp.count < tp.cap { (dp.next_free = tp.first_free, tp.first_free = cur); }
+        (cur = dp.next, prev = Some(dp));
+      }
+      for ci in tp.cols() {
+        if ci.check != !0 {
+          let cp = ci.check >> 1;
+          if cp < self.pages && !*used.get_unchecked(cp as usize) { *used.get_unchecked_mut(cp as usize) = true; } else { ci.pr().check = !0; }
+        }
+      }
+      for (data, _) in self.record_iter(tp) {
+        for (ci_id, ci) in tp.cols().iter().enumerate() {
+          if ci.ty.is_varchar() && !is_null(data, ci_id as u32) {
+            // an invalid varchar cannot be fixed here, at least its slots are not freed
+            for (lob_id, count) in self.lob_extents(data.add(ci.off as usize), ci.ty) {
+              if lob_id != 0 && lob_id.checked_add(count).map(|end| end <= self.lob_slots).unwrap_or(false) { lobs.push((lob_id, count)); }
+            }
+          }
+        }
+      }
+    }
+    let dp = self.dp();
+    dp.first_free = !0;
+    for page in (1..self.pages).rev() {
+      if !*used.get_unchecked(page as usize) { (*self.get_page::<u32>(page) = dp.first_free, dp.first_free = page); }
+    }
+    self.rebuild_lob_list(lobs);
+  }
+}
+
+struct Checker<'a> {
+  db: &'a mut Db,
+  // the kind of the structure using each page, "" for none
+  owner: Vec<&'static str>,
+  // all records of each table
+  rows: HashMap<u32, Vec<(*const u8, Rid)>>,
+  // (lob id, slot count) of all varchars and extents of texts / blobs
+  lobs: Vec<(u32, u32)>,
+  problems: Vec<String>,
+}
+
+// the bytes that determine the value in `ptr`, for hashing
+unsafe fn key<'a>(ptr: *const u8, ty: ColTy) -> &'a [u8] {
+  match ty { char!() => str_from_db(ptr).as_bytes(), _ => slice::from_raw_parts(ptr, ty.size() as usize) }
+}
+
+impl Checker<'_> {
+  unsafe fn page<'a, P>(&self, page: u32) -> &'a P { (self.db.pager.page(page) as *const P).r() }
+
+  fn report(&mut self, ctx: &str, msg: String) { self.problems.push(format!("{}: {}", ctx, msg)); }
+
+  // mark `page` as used by `kind`, return false if it is invalid or already used, in which case it should not be followed
+  unsafe fn claim(&mut self, page: u32, kind: &'static str, ctx: &str) -> bool {
+    if page >= self.db.pages { return (self.report(ctx, format!("{} {} is out of range", kind, page)), false).1; }
+    let owner = *self.owner.get_unchecked(page as usize);
+    if !owner.is_empty() { return (self.report(ctx, format!("{} {} is already used as {}", kind, page, owner)), false).1; }
+    *self.owner.get_unchecked_mut(page as usize) = kind;
+    if self.db.verify_page(page, kind).is_err() { self.report(ctx, format!("{} {} has wrong checksum", kind, page)); }
+    true
+  }
+
+  // the extents of the text / blob in `ptr` are all valid, the chain ends at the last one,
+  // and they hold `len` bytes with only the last one not full (see `TextExtent`)
+  unsafe fn text_valid(&self, ptr: *const u8) -> bool {
+    let v = (ptr as *const TextSlot).r();
+    let exts = self.db.text_extents(v.lob_id);
+    let (len, cap) = (v.len as usize, |count: u32| count as usize * LOB_SLOT_SIZE - TEXT_EXTENT_HEAD);
+    match exts.last() {
+      Some(&(last, count)) => {
+        let before_last = exts.iter().map(|&(_, count)| cap(count)).sum::<usize>() - cap(count);
+        (self.db.get_lob(last) as *const TextExtent).r().next == 0 && len <= MAX_TEXT_LEN && before_last < len.max(1) && len <= before_last + cap(count)
+      }
+      None => false,
+    }
+  }
+
+  unsafe fn run(mut self) -> Vec<String> {
+    self.claim(0, "DbPage", "database");
+    for page in self.db.table_lists() { self.claim(page, "TableListPage", "database"); }
+    let tables = self.db.tables();
+    for &tp_id in &tables {
+      if self.claim(tp_id, "TablePage", "database") {
+        // `page_num` is checked by `verify_catalog` when opening
+        for page in tp_id + 1..tp_id + self.page::<TablePage>(tp_id).page_num { self.claim(page, "TablePage", "database"); }
+        self.table(tp_id);
+      }
+    }
+    for &tp_id in &tables {
+      if self.rows.contains_key(&tp_id) { self.foreign(tp_id); }
+    }
+    let mut cur = self.page::<DbPage>(0).first_free;
+    while cur != !0 && self.claim(cur, "free page", "database") { cur = *self.page::<u32>(cur); }
+    let leaked = (1..self.db.pages).filter(|&page| self.owner.get_unchecked(page as usize).is_empty()).collect::<Vec<_>>();
+    if let Some(&first) = leaked.first() {
+      self.report("database", format!("{} page(s) are neither used nor free, the first is {}", leaked.len(), first));
+    }
+    self.lob();
+    self.problems
+  }
+
+  unsafe fn table(&mut self, tp_id: u32) {
+    let tp = self.page::<TablePage>(tp_id);
+    let ctx = format!("table `{}`", tp.name());
+    let (size, cap) = (tp.size as usize, tp.cap as usize);
+    let (mut pages, mut cur) = (Vec::new(), tp.first);
+    while cur != !0 && self.claim(cur, "DataPage", &ctx) { (pages.push(cur), cur = self.page::<DataPage>(cur).next); }
+    let mut rows = Vec::new();
+    for &page in &pages {
+      let dp = self.page::<DataPage>(page);
+      let used = (0..MAX_SLOT).filter(|&i| bsget(dp.used.as_ptr(), i)).collect::<Vec<_>>();
+      if used.last().map(|&i| i >= cap).unwrap_or(false) { self.report(&ctx, format!("DataPage {} uses slots beyond its capacity {}", page, cap)); }
+      if used.len() != dp.count as usize { self.report(&ctx, format!("DataPage {} has {} used slots, but its count is {}", page, used.len(), dp.count)); }
+      rows.extend(used.into_iter().filter(|&i| i < cap).map(|i| (dp.data.as_ptr().add(i * size), Rid::new(page, i as u32))));
+    }
+    if rows.len() != tp.count as usize { self.report(&ctx, format!("there are {} records, but its count is {}", rows.len(), tp.count)); }
+
+    // a data page is in the free list iff it is not full
+    let (mut free, mut cur) = (HashSet::default(), tp.first_free);
+    while cur != !0 {
+      if !pages.contains(&cur) {
+        self.report(&ctx, format!("page {} in the free list is not its DataPage", cur));
+        break;
+      }
+      if !free.insert(cur) {
+        self.report(&ctx, "the free list of DataPages has a cycle".into());
+        break;
+      }
+      cur = self.page::<DataPage>(cur).next_free;
+    }
+    for &page in &pages {
+      let count = self.page::<DataPage>(page).count as usize;
+      if (count < cap) != free.contains(&page) {
+        self.report(&ctx, format!("DataPage {} has {} records, but it is {}in the free list", page, count, if count < cap { "not " } else { "" }));
+      }
+    }
+
+    let primary_cnt = tp.primary_cols().count();
+    for (ci_id, ci) in tp.cols().iter().enumerate() {
+      let ctx = format!("column `{}.{}`", tp.name(), ci.name());
+      let vals = rows.iter().filter(|&&(data, _)| !is_null(data, ci_id as u32)).map(|&(data, rid)| (data.add(ci.off as usize), rid)).collect::<Vec<_>>();
+      if ci.flags.intersects(ColFlags::NOTNULL1) && vals.len() != rows.len() {
+        self.report(&ctx, format!("{} record(s) are null in a not null column", rows.len() - vals.len()));
+      }
+      if ci.unique(primary_cnt) {
+        let mut set = HashSet::default();
+        let dup = vals.iter().filter(|&&(ptr, _)| !set.insert(key(ptr, ci.ty))).count();
+        if dup != 0 { self.report(&ctx, format!("{} record(s) have duplicate values in a unique column", dup)); }
+      }
+      if ci.ty.is_varchar() {
+        let (mut invalid, mut corrupted) = (0, 0);
+        for &(ptr, _) in &vals {
+          if ci.ty.is_long() {
+            if self.text_valid(ptr) { self.lobs.extend(self.db.lob_extents(ptr, ci.ty)); } else { invalid += 1; }
+            continue;
+          }
+          let (lob_id, count) = match lob_extent(ptr) {
+            Some(x) => x,
+            None => {
+              if (ptr as *const VarcharSlot).r().inline_len() > INLINE_VARCHAR { invalid += 1; }
+              continue;
+            }
+          };
+          let v = (ptr as *const VarcharSlot).r();
+          // only the compressed flag can be set in the low bits
+          let bad_size = v.cap() == 0 || (v.cap as usize % LOB_SLOT_SIZE) & !0b10 != 0 || v.len > v.cap();
+          if lob_id == 0 || bad_size || lob_id.checked_add(count).map(|end| end > self.db.lob_slots).unwrap_or(true) { invalid += 1; } else {
+            self.lobs.push((lob_id, count));
+            if v.is_compressed() && self.db.varchar_corrupted(ptr) { corrupted += 1; }
+          }
+        }
+        if invalid != 0 { self.report(&ctx, format!("{} varchar(s) point to invalid lob slots", invalid)); }
+        if corrupted != 0 { self.report(&ctx, format!("{} compressed varchar(s) cannot be decompressed", corrupted)); }
+      }
+      if ci.check != !0 && self.claim(ci.check >> 1, "CheckPage", &ctx) {
+        let cp = self.page::<CheckPage>(ci.check >> 1);
+        let (count, size) = (cp.count as usize, ci.ty.size() as usize);
+        if (count + (ci.check & 1) as usize) * size > MAX_CHECK_BYTES {
+          self.report(&ctx, format!("CheckPage {} has too many values", ci.check >> 1));
+        } else if count != 0 {
+          let z = Unzipped::default();
+          let list = (0..count).map(|i| self.db.ptr2lit(cp.data.as_ptr().add(i * size), ci.ty, &z)).collect::<Vec<_>>();
+          let bad = vals.iter().filter(|&&(ptr, _)| {
+            let z = Unzipped::default();
+            let x = self.db.ptr2lit(ptr, ci.ty, &z);
+            !list.iter().any(|&y| x.cmp(y) == Ordering::Equal)
+          }).count();
+          if bad != 0 { self.report(&ctx, format!("{} record(s) are not in the check list", bad)); }
+        }
+      }
+      if ci.index != !0 {
+        let ty = ci.ty;
+        self.index(ci.index, (ty.size() as usize + 3) & !3, &ctx, &vals, |db, l, r| db.ptr2lit(l, ty, &Unzipped::default()).cmp(db.ptr2lit(r, ty, &Unzipped::default())));
+      }
+    }
+    let pks = tp.primary_cols().map(|ci| ci.idx(tp.cols()) as u16).collect::<Vec<_>>();
+    // a primary key on more than one col should have exactly one index, which is on the primary cols
+    let mut primary_ok = pks.len() < 2;
+    if tp.indexes != !0 && self.claim(tp.indexes, "IndexListPage", &ctx) {
+      let list = self.page::<IndexListPage>(tp.indexes);
+      let page_num_ok = list.page_num != 0 && list.page_num <= self.db.pages - tp.indexes;
+      if page_num_ok { for page in tp.indexes + 1..tp.indexes + list.page_num { self.claim(page, "IndexListPage", &ctx); } }
+      if !page_num_ok || list.count as usize > MAX_MULTI_INDEX || !list.check_names() {
+        (self.report(&ctx, format!("IndexListPage {} is invalid", tp.indexes)), primary_ok = true);
+      } else {
+        for info in list.indexes() { self.multi_index(tp, info, &rows); }
+        let primary = list.indexes().iter().filter(|info| info.flags.contains(IndexFlags::PRIMARY)).collect::<Vec<_>>();
+        primary_ok = if let [info] = &primary[..] { info.cols() == &pks[..] } else { primary.is_empty() && primary_ok };
+      }
+    }
+    if !primary_ok { self.report(&ctx, "the index of the primary key doesn't match the primary columns".into()); }
+    if primary_cnt > 1 {
+      let mut set = HashSet::default();
+      let dup = rows.iter().filter(|&&(data, _)| {
+        let pk = pks.iter().map(|&ci_id| { let ci = tp.cols().get_unchecked(ci_id as usize); key(data.add(ci.off as usize), ci.ty) }).collect::<Vec<_>>();
+        !set.insert(pk)
+      }).count();
+      if dup != 0 { self.report(&ctx, format!("{} record(s) have duplicate primary keys", dup)); }
+    }
+    self.rows.insert(tp_id, rows);
+  }
+
+  // check the structure of the B+ tree, and that its entries are exactly `vals` (pointers to the data part of the keys, and their rids)
+  // `cmp` compares the data part of two keys
+  unsafe fn index(&mut self, root: u32, rid_off: usize, ctx: &str, vals: &[(*const u8, Rid)], cmp: impl Fn(&Db, *const u8, *const u8) -> Ordering) {
+    let full = |db: &Db, l: *const u8, r: *const u8| cmp(db, l, r)
+      .then((*(l.add(rid_off) as *const Rid)).cmp(&*(r.add(rid_off) as *const Rid)));
+    // (page, depth, the key that its first key should be equal to)
+    let (mut stack, mut leaves, mut depth) = (vec![(root, 0, None::<*const u8>)], Vec::new(), None);
+    while let Some((page, d, lb)) = stack.pop() {
+      if !self.claim(page, "IndexPage", ctx) { continue; }
+      let ip = self.page::<IndexPage>(page);
+      let (slot_size, key_size) = (ip.slot_size() as usize, ip.key_size() as usize);
+      if ip.rid_off as usize != rid_off || ip.count >= ip.cap || ip.count as usize * slot_size > MAX_INDEX_BYTES as usize
+        || (d != 0 && (ip.count == 0 || ip.count < ip.cap / 2)) {
+        self.report(ctx, format!("IndexPage {} has invalid size", page));
+        continue;
+      }
+      let at = |i: usize| ip.data.as_ptr().add(i * slot_size);
+      if lb.map(|lb| full(self.db, lb, at(0)) != Ordering::Equal).unwrap_or(false) { self.report(ctx, format!("IndexPage {} doesn't match its parent", page)); }
+      if (1..ip.count as usize).any(|i| full(self.db, at(i - 1), at(i)) != Ordering::Less) { self.report(ctx, format!("IndexPage {} is not sorted", page)); }
+      if ip.leaf {
+        if *depth.get_or_insert(d) != d { self.report(ctx, format!("leaf IndexPage {} has different depth from others", page)); }
+        leaves.push(page);
+      } else {
+        // reversed, so that leaves are popped in order
+        for i in (0..ip.count as usize).rev() { stack.push((*(at(i).add(key_size) as *const u32), d + 1, Some(at(i)))); }
+      }
+    }
+    for (idx, &page) in leaves.iter().enumerate() {
+      let next = self.page::<IndexPage>(page).next;
+      if next != leaves.get(idx + 1).cloned().unwrap_or(!0) { self.report(ctx, format!("leaf IndexPage {} has wrong next link", page)); }
+    }
+
+    let mut records = vals.iter().map(|&(ptr, rid)| (rid, ptr)).collect::<HashMap<_, _>>();
+    let mut stale = 0;
+    for &page in &leaves {
+      let ip = self.page::<IndexPage>(page);
+      for i in 0..ip.count as usize {
+        let entry = ip.data.as_ptr().add(i * ip.slot_size() as usize);
+        match records.remove(&*(entry.add(rid_off) as *const Rid)) {
+          Some(ptr) if cmp(self.db, ptr, entry) == Ordering::Equal => {}
+          _ => stale += 1,
+        }
+      }
+    }
+    if stale != 0 { self.report(ctx, format!("{} index entries don't match any record", stale)); }
+    if !records.is_empty() { self.report(ctx, format!("{} record(s) are missing from the index", records.len())); }
+  }
+
+  // a composite index should have the keys (see `key_layout`) of all records whose first col is not null
+  unsafe fn multi_index(&mut self, tp: &TablePage, info: &IndexInfo, rows: &[(*const u8, Rid)]) {
+    let ctx = if info.flags.contains(IndexFlags::PRIMARY) { format!("primary key index of `{}`", tp.name()) } else { format!("index `{}.{}`", tp.name(), info.name()) };
+    if IndexFlags::from_bits(info.flags.bits()).is_none() { self.report(&ctx, "its flags are invalid".into()); }
+    if info.col_num < 2 || info.col_num as usize > MAX_INDEX_COL || info.cols().iter().any(|&ci_id| ci_id >= tp.col_num || tp.cols().get_unchecked(ci_id as usize).ty.is_varchar()) {
+      return self.report(&ctx, "its columns are invalid".into());
+    }
+    let (keys, rid_off) = multi_keys(tp, info, rows);
+    let vals = keys.iter().map(|(key, rid)| (key.as_ptr() as *const u8, *rid)).collect::<Vec<_>>();
+    self.index(info.index, rid_off, &ctx, &vals, multi_key_cmp(tp, info));
+  }
+
+  unsafe fn foreign(&mut self, tp_id: u32) {
+    let tp = self.page::<TablePage>(tp_id);
+    for (ci_id, ci) in tp.cols().iter().enumerate() {
+      if ci.f_table == !0 { continue; }
+      let ctx = format!("column `{}.{}`", tp.name(), ci.name());
+      if !self.rows.contains_key(&ci.f_table) {
+        self.report(&ctx, format!("foreign table {} doesn't exist", ci.f_table));
+        continue;
+      }
+      let f_tp = self.page::<TablePage>(ci.f_table);
+      if ci.f_col >= f_tp.col_num || f_tp.cols().get_unchecked(ci.f_col as usize).ty != ci.ty {
+        self.report(&ctx, "foreign column is invalid".into());
+        continue;
+      }
+      let f_ci = f_tp.cols().get_unchecked(ci.f_col as usize);
+      let f_vals = self.rows.get(&ci.f_table).unchecked_unwrap().iter().filter(|&&(data, _)| !is_null(data, ci.f_col as u32)).map(|&(data, _)| key(data.add(f_ci.off as usize), f_ci.ty)).collect::<HashSet<_>>();
+      let bad = self.rows.get(&tp_id).unchecked_unwrap().iter()
+        .filter(|&&(data, _)| !is_null(data, ci_id as u32) && !f_vals.contains(key(data.add(ci.off as usize), ci.ty))).count();
+      if bad != 0 { self.report(&ctx, format!("{} record(s) refer to nonexistent values in `{}.{}`", bad, f_tp.name(), f_ci.name())); }
+    }
+  }
+
+  // the free list of lob slots and all varchars should cover all slots except the nil node exactly once
+  unsafe fn lob(&mut self) {
+    let lob_slots = self.db.lob_slots;
+    let base = self.db.pager.lob(0) as *const FreeLobSlot;
+    let mut ranges = self.lobs.iter().map(|&(id, count)| (id, count, false)).collect::<Vec<_>>();
+    let (mut prev, mut cur, mut n) = (0, base.r().next, 0);
+    while cur != 0 {
+      if cur >= lob_slots {
+        self.report("lob", format!("free node {} is out of range", cur));
+        break;
+      }
+      if n == lob_slots {
+        self.report("lob", "the free list has a cycle".into());
+        break;
+      }
+      let x = base.add(cur as usize).r();
+      if x.prev != prev { self.report("lob", format!("free node {} has prev link {}, but {} is expected", cur, x.prev, prev)); }
+      if x.count == 0 || cur.checked_add(x.count).map(|end| end > lob_slots).unwrap_or(true) {
+        self.report("lob", format!("free node {} has invalid size {}", cur, x.count));
+      } else { ranges.push((cur, x.count, true)); }
+      (prev = cur, cur = x.next, n += 1);
+    }
+    if cur == 0 && base.r().prev != prev { self.report("lob", format!("nil node has prev link {}, but {} is expected", base.r().prev, prev)); }
+    ranges.sort_unstable();
+    let (mut end, mut leaked, mut overlapped) = (1, 0, 0);
+    for &(id, count, _) in &ranges {
+      if id < end { overlapped += 1; } else { leaked += id - end; }
+      end = end.max(id + count);
+    }
+    leaked += lob_slots.saturating_sub(end);
+    if overlapped != 0 { self.report("lob", format!("{} varchar(s) or free node(s) overlap with others", overlapped)); }
+    if leaked != 0 { self.report("lob", format!("{} slot(s) are neither used nor free", leaked)); }
+  }
+}
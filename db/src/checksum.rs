@@ -0,0 +1,263 @@
+use std::{io::{self, Read, Write, Seek, SeekFrom}, mem, slice, sync::{Mutex, atomic::{AtomicU8, Ordering::Relaxed}}};
+use unchecked_unwrap::UncheckedUnwrap;
+
+use common::{*, Error::*};
+use physics::*;
+use crate::{Db, pager::Store};
+
+// the crc32 of each page is stored in `<db>.sum`, and loaded into memory when the database is opened
+// a page is verified when it is used for the first time in a session; once returned by `get_page` it may be modified,
+// so its checksum is recomputed later, when the transaction commits (every modification is in a transaction, maybe an
+// implicit one, see `journal`), so the sum file is updated together with the pages, and a crash before that undoes both
+// readers sharing a `Db` verify pages through `&self`, so the states and verified tables can be updated without `&mut`;
+// only writers make pages DIRTY, and they never run together with readers
+
+const UNCHECKED: u8 = 0;
+const CHECKED: u8 = 1;
+const DIRTY: u8 = 2;
+
+pub struct Sums {
+  pub(crate) store: Box<dyn Store>,
+  sums: Vec<u32>,
+  // UNCHECKED, CHECKED or DIRTY for each page
+  state: Vec<AtomicU8>,
+  // pages whose checksum need to be recomputed, may contain pages that are already truncated
+  dirty: Vec<u32>,
+  // tables whose pages are all verified
+  tables: Mutex<HashSet<u32>>,
+  // (page, kind) of the pages `get_page` failed to verify in the current transaction, see `touch_page`
+  corrupted: Vec<(u32, &'static str)>,
+}
+
+fn states(state: u8, pages: u32) -> Vec<AtomicU8> { (0..pages).map(|_| AtomicU8::new(state)).collect() }
+
+impl Sums {
+  // an empty sum file means the database has no checksums (e.g., it is created by an older version without them, or the file
+  // is deleted on purpose), then all pages are trusted and their checksums are recomputed
+  // a sum file of any other wrong size is refused, unless the journal is `hot`: a crash may happen between resizing the
+  // page file and the sum file, and all pages the crashed transaction modified are restored and recomputed anyway
+  // a read-only session never writes the store, so a stale one is left to the next writer
+  pub(crate) fn new<'a>(mut store: Box<dyn Store>, pages: u32, read_only: bool, hot: bool) -> Result<'a, Sums> {
+    unsafe {
+      let (size, expect) = (store.size()? as usize, pages as usize * 4);
+      if size != 0 && size != expect && !hot { return Err(InvalidSumSize { size, expect }); }
+      let stale = size != expect;
+      let mut sums = vec![0; pages as usize];
+      if stale { if !read_only { store.set_len(pages as u64 * 4)?; } } else {
+        store.seek(SeekFrom::Start(0))?;
+        store.read_exact(slice::from_raw_parts_mut(sums.as_mut_ptr() as *mut u8, pages as usize * 4))?;
+      }
+      let (state, dirty) = if stale { (states(DIRTY, pages), (0..pages).collect()) } else { (states(UNCHECKED, pages), vec![]) };
+      Ok(Sums { store, sums, state, dirty, tables: Mutex::default(), corrupted: vec![] })
+    }
+  }
+
+  // called by `Db::apply`, the restored pages may belong to any table, so all tables are verified again
+  pub(crate) fn forget_tables(&mut self) { unsafe { self.tables.get_mut().unchecked_unwrap().clear(); } }
+
+  // called by `Db::apply` after the image of `page` is copied back
+  // a corrupted page is restored to its image on disk, whose checksum in the sum file is kept, so it is still corrupted
+  pub(crate) unsafe fn restored(&mut self, page: u32) {
+    if self.corrupted.iter().any(|&(p, _)| p == page) { return; }
+    let state = self.state.get_unchecked_mut(page as usize).get_mut();
+    if *state != DIRTY {
+      *state = DIRTY;
+      self.dirty.push(page);
+    }
+  }
+}
+
+impl Db {
+  unsafe fn crc(&self, page: u32) -> u32 {
+    crc32fast::hash(slice::from_raw_parts(self.pager.page(page), PAGE_SIZE))
+  }
+
+  pub(crate) unsafe fn is_dirty(&self, page: u32) -> bool { self.sums.state.get_unchecked(page as usize).load(Relaxed) == DIRTY }
+
+  // called by `get_page` if `page` is not DIRTY
+  // callers should have verified the pages they use by `verify_table` (and the other pages are verified by `verify_catalog`),
+  // so failure here is not expected; `get_page` cannot return an error, so a corrupted page is recorded and stays UNCHECKED,
+  // then the statement or transaction modifying it fails with `PageCorrupted` and is rolled back, see `check_corrupted`
+  pub(crate) unsafe fn touch_page(&mut self, page: u32, kind: &'static str) {
+    if self.verify_page(page, kind).is_err() {
+      if !self.sums.corrupted.iter().any(|&(p, _)| p == page) { self.sums.corrupted.push((page, kind)); }
+      return;
+    }
+    *self.sums.state.get_unchecked_mut(page as usize).get_mut() = DIRTY;
+    self.sums.dirty.push(page);
+  }
+
+  // the error for the first page `touch_page` failed to verify, the caller should roll back and then call `clear_corrupted`
+  pub(crate) fn check_corrupted<'a>(&self) -> Result<'a, ()> {
+    match self.sums.corrupted.first() { Some(&(page, kind)) => Err(PageCorrupted { page, kind }), None => Ok(()) }
+  }
+
+  pub(crate) fn clear_corrupted(&mut self) { self.sums.corrupted.clear(); }
+
+  // regard all pages not yet verified as correct, so that `get_page` accepts a corrupted page (e.g., when repairing it)
+  pub(crate) fn accept_all_pages(&mut self) {
+    for state in &mut self.sums.state { if *state.get_mut() == UNCHECKED { *state.get_mut() = CHECKED; } }
+  }
+
+  // two readers may verify the same page at the same time, which is harmless
+  pub unsafe fn verify_page<'a>(&self, page: u32, kind: &'static str) -> Result<'a, ()> {
+    let state = self.sums.state.get_unchecked(page as usize);
+    if state.load(Relaxed) == UNCHECKED {
+      if self.crc(page) != *self.sums.sums.get_unchecked(page as usize) { return Err(PageCorrupted { page, kind }); }
+      state.store(CHECKED, Relaxed);
+    }
+    Ok(())
+  }
+
+  // the checksum of `page` to be written together with a copy of it, the page is verified first
+  // a DIRTY page has no valid checksum until it is committed, so it is recomputed
+  pub(crate) unsafe fn page_sum<'a>(&self, page: u32, kind: &'static str) -> Result<'a, u32> {
+    self.verify_page(page, kind)?;
+    Ok(if self.is_dirty(page) { self.crc(page) } else { *self.sums.sums.get_unchecked(page as usize) })
+  }
+
+  // verify the TablePage, check pages, data pages and index pages of this table
+  // pages are read directly instead of by `get_page`, and pointers in a page are followed only after it is verified
+  pub unsafe fn verify_table<'a>(&self, tp_id: u32) -> Result<'a, ()> {
+    if self.sums.tables.lock().unchecked_unwrap().contains(&tp_id) { return Ok(()); }
+    macro_rules! page {
+      ($id: expr, $ty: ident, $from: expr, $from_kind: expr) => {{
+        let id = $id;
+        if id >= self.pages { return Err(PageCorrupted { page: $from, kind: $from_kind }); }
+        self.verify_page(id, $ty::KIND)?;
+        (self.pager.page(id) as *const $ty).r()
+      }};
+    }
+    let tp = page!(tp_id, TablePage, 0, "DbPage");
+    // the other pages of the TablePage are verified by `verify_catalog`
+    let mut dp = tp.first;
+    while dp != !0 { dp = page!(dp, DataPage, tp_id, "TablePage").next; }
+    // (root, the page pointing to it, the kind of that page) of all indexes
+    let mut roots = Vec::new();
+    for ci in tp.cols() {
+      if ci.check != !0 { page!(ci.check >> 1, CheckPage, tp_id, "TablePage"); }
+      if ci.index != !0 { roots.push((ci.index, tp_id, "TablePage")); }
+    }
+    if tp.indexes != !0 {
+      let list = page!(tp.indexes, IndexListPage, tp_id, "TablePage");
+      let corrupted = PageCorrupted { page: tp.indexes, kind: "IndexListPage" };
+      if list.page_num == 0 || list.page_num > self.pages - tp.indexes { return Err(corrupted); }
+      for page in tp.indexes + 1..tp.indexes + list.page_num { self.verify_page(page, "IndexListPage")?; }
+      if list.count as usize > MAX_MULTI_INDEX || !list.check_names() { return Err(corrupted); }
+      for info in list.indexes() { roots.push((info.index, tp.indexes, "IndexListPage")); }
+    }
+    for root in roots {
+      let mut stack = vec![root];
+      while let Some((ip_id, from, from_kind)) = stack.pop() {
+        let ip = page!(ip_id, IndexPage, from, from_kind);
+        if !ip.leaf {
+          let (slot_size, key_size) = (ip.slot_size() as usize, ip.key_size() as usize);
+          if ip.count as usize * slot_size > MAX_INDEX_BYTES as usize { return Err(PageCorrupted { page: ip_id, kind: "IndexPage" }); }
+          for i in 0..ip.count as usize {
+            stack.push((*(ip.data.as_ptr().add(i * slot_size + key_size) as *const u32), ip_id, "IndexPage"));
+          }
+        }
+      }
+    }
+    self.sums.tables.lock().unchecked_unwrap().insert(tp_id);
+    Ok(())
+  }
+
+  // called when the table `tp_id` is dropped or moved, so that a new table reusing the page id is verified again
+  pub(crate) fn forget_table(&self, tp_id: u32) {
+    unsafe { self.sums.tables.lock().unchecked_unwrap().remove(&tp_id); }
+  }
+
+  // verify DbPage, and TablePage & CheckPage of all tables, other pages are verified when the table is used
+  pub(crate) unsafe fn verify_catalog<'a>(&self) -> Result<'a, ()> {
+    self.verify_page(0, "DbPage")?;
+    let dp = (self.pager.page(0) as *const DbPage).r();
+    // the TableListPages must hold exactly `table_num - (MAX_TABLE - 1)` ids, and have no cycle (bounded by `pages`)
+    let (mut from, mut cur) = (0, dp.table_list().unwrap_or(!0));
+    let mut rest = if dp.table_list().is_some() { dp.table_num as usize - (MAX_TABLE - 1) } else { 0 };
+    let corrupted = |from: u32| PageCorrupted { page: from, kind: if from == 0 { "DbPage" } else { "TableListPage" } };
+    while cur != !0 {
+      if cur >= self.pages || rest == 0 { return Err(corrupted(from)); }
+      self.verify_page(cur, "TableListPage")?;
+      rest = rest.saturating_sub(TABLE_LIST_CAP);
+      (from = cur, cur = (self.pager.page(cur) as *const TableListPage).r().next);
+    }
+    if rest != 0 { return Err(corrupted(from)); }
+    // `alloc_page` takes free pages by `get_page`, which cannot report an error, so they are verified here;
+    // the free list can have at most `pages` pages, otherwise it has a cycle
+    let (mut from, mut cur, mut rest) = (0, dp.first_free, self.pages);
+    while cur != !0 {
+      if cur >= self.pages || rest == 0 { return Err(PageCorrupted { page: from, kind: if from == 0 { "DbPage" } else { "free page" } }); }
+      self.verify_page(cur, "free page")?;
+      (from = cur, cur = *(self.pager.page(cur) as *const u32), rest -= 1);
+    }
+    for tp_id in self.tables() {
+      if tp_id >= self.pages { return Err(PageCorrupted { page: 0, kind: "DbPage" }); }
+      self.verify_page(tp_id, "TablePage")?;
+      // an older layout is checked by `upgrade`, which calls this again after upgrading
+      if dp.version < FORMAT_VERSION { continue; }
+      let tp = (self.pager.page(tp_id) as *const TablePage).r();
+      if tp.page_num == 0 || tp.page_num > self.pages - tp_id { return Err(PageCorrupted { page: tp_id, kind: "TablePage" }); }
+      for page in tp_id + 1..tp_id + tp.page_num { self.verify_page(page, "TablePage")?; }
+      if !tp.check_names() { return Err(PageCorrupted { page: tp_id, kind: "TablePage" }); }
+      for ci in tp.cols() {
+        if ci.check != !0 {
+          if ci.check >> 1 >= self.pages { return Err(PageCorrupted { page: tp_id, kind: "TablePage" }); }
+          self.verify_page(ci.check >> 1, "CheckPage")?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // called when the page file is resized, new pages have no valid checksum, so they are DIRTY
+  pub(crate) fn resize_sums<'a>(&mut self, pages: u32) -> Result<'a, ()> {
+    self.sums.store.set_len(pages as u64 * 4)?;
+    self.sums.sums.resize(pages as usize, 0);
+    let old = self.sums.state.len() as u32;
+    self.sums.state.resize_with(pages as usize, || AtomicU8::new(DIRTY));
+    self.sums.dirty.extend(old..pages);
+    Ok(())
+  }
+
+  // the same as `resize_sums`, but only for adding pages in `alloc_page`
+  pub(crate) unsafe fn push_sum(&mut self) {
+    self.sums.store.set_len(self.pages as u64 * 4).expect("Failed to allocate page. The database may already be in an invalid state.");
+    self.sums.sums.push(0);
+    self.sums.dirty.push(self.sums.state.len() as u32);
+    self.sums.state.push(AtomicU8::new(DIRTY));
+  }
+
+  // recompute checksums of all pages that may be modified, and write them to the store
+  pub(crate) unsafe fn flush_sums(&mut self) -> io::Result<()> {
+    // pages are only read in a read-only session, and other readers may be using the store
+    if self.read_only { return Ok(()); }
+    let mut dirty = mem::take(&mut self.sums.dirty);
+    dirty.sort_unstable();
+    dirty.dedup();
+    while dirty.last().map(|&page| page >= self.pages).unwrap_or(false) { dirty.pop(); }
+    for &page in &dirty {
+      *self.sums.sums.get_unchecked_mut(page as usize) = self.crc(page);
+      *self.sums.state.get_unchecked_mut(page as usize).get_mut() = CHECKED;
+    }
+    // write each run of consecutive pages at once
+    let mut i = 0;
+    while i < dirty.len() {
+      let (start, mut end) = (dirty[i], dirty[i] + 1);
+      while (i += 1, i < dirty.len() && dirty[i] == end).1 { end += 1; }
+      let sums = self.sums.sums.as_ptr().add(start as usize) as *const u8;
+      self.sums.store.seek(SeekFrom::Start(start as u64 * 4))?;
+      self.sums.store.write_all(slice::from_raw_parts(sums, (end - start) as usize * 4))?;
+    }
+    dirty.clear();
+    self.sums.dirty = dirty;
+    Ok(())
+  }
+}
+
+impl Drop for Db {
+  // modifications outside a transaction are committed; errors cannot be reported here, call `sync` before dropping to be sure
+  fn drop(&mut self) {
+    if self.journal.implicit { let _ = self.finish(self.journal.sync != crate::SyncMode::Off); } else { unsafe { let _ = self.flush_sums(); } }
+  }
+}
@@ -0,0 +1,67 @@
+use std::{cell::RefCell, slice};
+
+use common::*;
+use physics::*;
+use crate::Db;
+
+// a compressed varchar is stored in lob as its original length (u16) followed by an lz4 block,
+// `VarcharSlot::len` is the whole length in lob, which is never larger than the original length
+
+// None if compression doesn't save space, then it is stored as it is
+pub fn compress(s: &[u8]) -> Option<Vec<u8>> {
+  let mut z = Vec::with_capacity(s.len());
+  z.extend_from_slice(&(s.len() as u16).to_ne_bytes());
+  z.extend_from_slice(&lz4_flex::compress(s));
+  // a compressed varchar is never inlined
+  if z.len() < s.len() && z.len() > INLINE_VARCHAR { Some(z) } else { None }
+}
+
+// `z` is the content in lob, return false if it is corrupted
+pub fn decompress(z: &[u8], out: &mut Vec<u8>) -> bool {
+  if z.len() < 2 { return false; }
+  let len = u16::from_ne_bytes([z[0], z[1]]) as usize;
+  out.resize(len, 0);
+  lz4_flex::decompress_into(&z[2..], out).map(|n| n == len).unwrap_or(false)
+}
+
+// decompressed varchars and texts / blobs in more than one extent, `Db::ptr2lit` returns references into them,
+// so they are valid until it is dropped or `reset`
+// it is owned by the reader instead of `Db`: a comparison uses a new one which is dropped at once,
+// and a result keeps one for the values in it (see `SelectResult`), so only the values in use are kept
+#[derive(Default)]
+pub struct Unzipped(RefCell<(Vec<Vec<u8>>, usize)>);
+
+impl Unzipped {
+  // the values read into it are invalid after this, and the buffers are reused
+  pub fn reset(&mut self) { self.0.get_mut().1 = 0; }
+
+  pub(crate) unsafe fn unzip<'a>(&self, z: &[u8]) -> &'a [u8] {
+    // a corrupted one is read as empty, `Db::check` reports it
+    self.fill(|buf| if !decompress(z, buf) { buf.clear(); })
+  }
+
+  // the content written by `f` into an empty buffer
+  pub(crate) unsafe fn fill<'a>(&self, f: impl FnOnce(&mut Vec<u8>)) -> &'a [u8] {
+    let mut bufs = self.0.borrow_mut();
+    let (bufs, used) = &mut *bufs;
+    if *used == bufs.len() { bufs.push(vec![]); }
+    let buf = bufs.get_unchecked_mut(*used);
+    *used += 1;
+    (buf.clear(), f(buf));
+    slice::from_raw_parts(buf.as_ptr(), buf.len())
+  }
+}
+
+impl Db {
+  // `v` is not inline
+  pub(crate) unsafe fn varchar_bytes<'a>(&self, v: &VarcharSlot, z: &Unzipped) -> &'a [u8] {
+    let s = slice::from_raw_parts(self.get_lob(v.lob_id), v.len as usize);
+    if v.is_compressed() { z.unzip(s) } else { s }
+  }
+
+  // `ptr` points to a compressed VarcharSlot in valid lob slots
+  pub(crate) unsafe fn varchar_corrupted(&self, ptr: *const u8) -> bool {
+    let v = (ptr as *const VarcharSlot).r();
+    !decompress(slice::from_raw_parts(self.get_lob(v.lob_id), v.len as usize), &mut vec![])
+  }
+}
@@ -1,57 +1,128 @@
-use std::{fs::{File, OpenOptions}, path::Path};
-use memmap::{MmapOptions, MmapMut};
+use std::{fs::{File, OpenOptions}, path::Path, io::{self, Cursor}, slice, str};
 use unchecked_unwrap::UncheckedUnwrap;
+use fs2::FileExt;
 use chrono::NaiveDate;
 
 use physics::*;
 use common::{*, Error::*, BareTy::*};
 use syntax::ast::*;
+use crate::{journal::Journal, checksum::Sums, pager::*, catalog::Catalog, lob::FreeLobs, compress::{self, Unzipped}};
+
+pub const DEFAULT_FILL_FACTOR: u8 = 90;
 
 pub struct Db {
-  pub(crate) mmap: MmapMut,
-  pub(crate) file: File,
-  pub(crate) lob_mmap: MmapMut,
-  pub(crate) lob_file: File,
+  pub(crate) pager: Box<dyn Pager>,
   pub(crate) pages: u32,
   pub(crate) lob_slots: u32,
+  pub(crate) journal: Journal,
+  pub(crate) sums: Sums,
+  pub(crate) catalog: Catalog,
+  pub(crate) free_lobs: FreeLobs,
+  // the percentage of slots used in each index page built by `index::bulk`, see `set_fill_factor`
+  pub(crate) fill_factor: u8,
+  // opened by `open_read_only`, no transaction can begin
+  pub(crate) read_only: bool,
+}
+
+// take an advisory lock on `file`, shared for reading and exclusive for writing, it is released when `file` is closed
+// it only prevents other sessions which also use this function, not any other programs
+pub(crate) fn lock<'a>(file: &File, read_only: bool) -> Result<'a, ()> {
+  let res = if read_only { file.try_lock_shared() } else { file.try_lock_exclusive() };
+  res.map_err(|e| if e.kind() == fs2::lock_contended_error().kind() { DatabaseLocked } else { e.into() })
+}
+
+// open the journal or sum file of a database, a writer creates it if it doesn't exist
+// a read-only session never creates or writes it, and regards a missing one as empty
+fn open_sidecar<'a>(path: &Path, read_only: bool) -> Result<'a, Box<dyn Store>> {
+  if !read_only { return Ok(box OpenOptions::new().read(true).write(true).create(true).open(path)?); }
+  match File::open(path) {
+    Ok(file) => Ok(box file),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(box Cursor::new(vec![])),
+    Err(e) => Err(e.into()),
+  }
 }
 
 impl Db {
   pub fn create<'a>(path: impl AsRef<Path>) -> Result<'a, Db> {
+    let path = path.as_ref();
+    let opt = OpenOptions::new().read(true).write(true).create(true).append(true).clone();
+    let (file, lob_file) = (opt.open(path)?, opt.open(path.with_extension(LOB_SUFFIX))?);
+    // an old database with the same name may be in use
+    (lock(&file, false)?, lock(&lob_file, false)?);
+    let pager = FilePager::new(file, lob_file, false)?;
+    // a journal left by an old database with the same name is meaningless now
+    let opt = OpenOptions::new().read(true).write(true).create(true).truncate(true).clone();
+    Db::init(box pager, box opt.open(path.with_extension(JOURNAL_SUFFIX))?, box opt.open(path.with_extension(SUM_SUFFIX))?)
+  }
+
+  // nothing is written to the filesystem, all data is lost when it is dropped
+  pub fn in_memory() -> Db {
+    // no io operation of memory pager and memory store can fail
+    unsafe { Db::init(box MemPager::default(), box Cursor::new(vec![]), box Cursor::new(vec![])).unchecked_unwrap() }
+  }
+
+  fn init<'a>(mut pager: Box<dyn Pager>, journal: Box<dyn Store>, sums: Box<dyn Store>) -> Result<'a, Db> {
     unsafe {
-      let opt = OpenOptions::new().read(true).write(true).create(true).append(true).clone();
-      let file = opt.open(path.as_ref())?;
-      file.set_len(PAGE_SIZE as u64)?;
-      // this is 64G, the maximum capacity of this db; mmap will not allocate memory unless accessed
-      let mut mmap = MmapOptions::new().len(PAGE_SIZE * MAX_PAGE).map_mut(&file)?;
-      (mmap.as_mut_ptr() as *mut DbPage).r().init();
-      let lob_file = opt.open(path.as_ref().with_extension(LOB_SUFFIX))?;
-      lob_file.set_len(LOB_SLOT_SIZE as u64)?;
-      // lob file can use all the 32 bits addr space, each addr for 32 bytes, in all 128G
-      let mut lob_mmap = MmapOptions::new().len(!0u32 as usize * LOB_SLOT_SIZE).map_mut(&lob_file)?;
-      (lob_mmap.as_mut_ptr() as *mut FreeLobSlot).r().init_nil();
-      Ok(Db { mmap, file, lob_mmap, lob_file, pages: 1, lob_slots: 1 })
+      pager.set_pages(1)?;
+      (pager.page(0) as *mut DbPage).r().init();
+      pager.set_lob_slots(1)?;
+      (pager.lob(0) as *mut FreeLobSlot).r().init_nil();
+      let sums = Sums::new(sums, 1, false, false)?;
+      let mut db = Db { pager, pages: 1, lob_slots: 1, journal: Journal::new(journal), sums, catalog: Catalog::default(), free_lobs: FreeLobs::default(), fill_factor: DEFAULT_FILL_FACTOR, read_only: false };
+      db.flush_sums()?;
+      db.load_catalog();
+      Ok(db)
     }
   }
 
-  pub fn open<'a>(path: impl AsRef<Path>) -> Result<'a, Db> {
+  pub fn open<'a>(path: impl AsRef<Path>) -> Result<'a, Db> { Db::open_with(path.as_ref(), false) }
+
+  // other read-only sessions can open the database at the same time, but no session can write it
+  // the database is not modified through the API of transaction (including statements in `driver`),
+  // but modifying functions like `create_table` can still be called outside a transaction, which is not checked
+  pub fn open_read_only<'a>(path: impl AsRef<Path>) -> Result<'a, Db> { Db::open_with(path.as_ref(), true) }
+
+  fn open_with<'a>(path: &Path, read_only: bool) -> Result<'a, Db> {
     unsafe {
-      let opt = OpenOptions::new().read(true).write(true).append(true).clone();
-      let file = opt.open(path.as_ref())?;
+      // a read-only session has no write access to any file, so it cannot modify the database even by mistake
+      let opt = if read_only { OpenOptions::new().read(true).clone() } else { OpenOptions::new().read(true).write(true).append(true).clone() };
+      let file = opt.open(path)?;
+      let lob_file = opt.open(path.with_extension(LOB_SUFFIX))?;
+      // lock before checking sizes, a writer may be changing them
+      (lock(&file, read_only)?, lock(&lob_file, read_only)?);
       let size = file.metadata()?.len() as usize;
       if size == 0 || size % PAGE_SIZE != 0 { return Err(InvalidSize { size, expect_multiply_of: PAGE_SIZE }); }
-      let mmap = MmapOptions::new().len(PAGE_SIZE * MAX_PAGE).map_mut(&file)?;
-      let dp = &*(mmap.as_ptr() as *const DbPage);
-      if &dp.magic != MAGIC { return Err(InvalidMagic(dp.magic)); }
-      let lob_file = opt.open(path.as_ref().with_extension(LOB_SUFFIX))?;
       let lob_size = lob_file.metadata()?.len() as usize;
       if lob_size == 0 || lob_size % LOB_SLOT_SIZE != 0 { return Err(InvalidSize { size: lob_size, expect_multiply_of: LOB_SLOT_SIZE }); }
-      let lob_mmap = MmapOptions::new().len(!0u32 as usize * LOB_SLOT_SIZE).map_mut(&lob_file)?;
-      Ok(Db { mmap, file, lob_file, lob_mmap, pages: (size / PAGE_SIZE) as u32, lob_slots: (lob_size / LOB_SLOT_SIZE) as u32 })
+      let pager = FilePager::new(file, lob_file, read_only)?;
+      let dp = (pager.page(0) as *const DbPage).r();
+      if &dp.magic != MAGIC { return Err(InvalidMagic(dp.magic)); }
+      Db::check_version(dp)?;
+      let (pages, lob_slots) = ((size / PAGE_SIZE) as u32, (lob_size / LOB_SLOT_SIZE) as u32);
+      let journal = Journal::new(open_sidecar(&path.with_extension(JOURNAL_SUFFIX), read_only)?);
+      // the last transaction didn't finish, undo all its changes; only a writer can do this
+      let hot = journal.is_hot()?;
+      if hot && read_only { return Err(ReadOnlyDatabase); }
+      let sums = Sums::new(open_sidecar(&path.with_extension(SUM_SUFFIX), read_only)?, pages, read_only, hot)?;
+      let mut db = Db { pager: box pager, pages, lob_slots, journal, sums, catalog: Catalog::default(), free_lobs: FreeLobs::default(), fill_factor: DEFAULT_FILL_FACTOR, read_only };
+      if hot { db.restore()?; }
+      db.verify_catalog()?;
+      // an older version has a different TablePage layout, only a writer can upgrade it
+      if !read_only { db.upgrade()?; } else if dp.version != FORMAT_VERSION { return Err(ReadOnlyDatabase); }
+      db.load_catalog();
+      Ok(db)
     }
   }
 }
 
+impl Db {
+  pub fn fill_factor(&self) -> u8 { self.fill_factor }
+
+  // a smaller one leaves more space for later insertions, and a larger one makes the index smaller; it is clamped to 50..=100
+  // (a page except the root is at least half full, and a full page is always split)
+  pub fn set_fill_factor(&mut self, percent: u8) { self.fill_factor = percent.max(50).min(100); }
+}
+
 impl Db {
   // like `lit2ptr`, but only do type check
   pub fn lit2ptr_ck(ty: FixTy, val: CLit) -> Result<()> {
@@ -66,19 +137,21 @@ impl Db {
     }
   }
 
-  // ignore non-varchar case
+  // ignore non-varchar case (text and blob are checked here)
   pub fn varchar_ck(ty: ColTy, val: CLit) -> Result<()> {
     match (ty, val.lit()) {
       (varchar!(size), Lit::Str(v)) if v.len() <= size as usize => Ok(()),
-      (varchar!(), _) => Err(ColLitMismatch { ty, val }),
-      _ => Ok(())
+      (ColTy::Text, Lit::Str(v)) if v.len() <= MAX_TEXT_LEN => Ok(()),
+      (ColTy::Blob, Lit::Blob(v)) if v.len() <= MAX_TEXT_LEN => Ok(()),
+      (ColTy::FixTy(_), _) => Ok(()),
+      _ => Err(ColLitMismatch { ty, val }),
     }
   }
 
   // `ptr` points to the location in this record where `val` should locate, not the start address of data slot
   // if `val` is null, it is always regarded as illegal
   // Varchar case is not handled here, use `lit2varchar` to write varchar to ptr
-  pub unsafe fn lit2ptr<'a>(&mut self, ptr: *mut u8, ty: FixTy, val: CLit<'a>) -> Result<'a, ()> {
+  pub unsafe fn lit2ptr<'a>(&self, ptr: *mut u8, ty: FixTy, val: CLit<'a>) -> Result<'a, ()> {
     Ok(match (ty.ty, val.lit()) {
       (Bool, Lit::Bool(v)) => *(ptr as *mut bool) = v,
       (Int, Lit::Number(v)) => *(ptr as *mut i32) = v as i32,
@@ -94,71 +167,147 @@ impl Db {
   }
 
   // if `ptr`'s content doesn't have initial value (e.g.: insert), set initialized = false, otherwise set initialized = true; this helps handling varchar
-  pub unsafe fn lit2varchar(&mut self, ptr: *mut u8, s: &str, initialized: bool) {
-    let write_varchar = |db: &mut Db| {
-      let (lob_id, cap, ptr1) = db.alloc_lob(s.len() as u32);
-      ptr1.copy_from_nonoverlapping(s.as_ptr(), s.len());
-      *(ptr as *mut VarcharSlot) = VarcharSlot { lob_id, len: s.len() as u16, cap: cap as u16 };
-    };
-    if initialized {
-      let old = (ptr as *mut VarcharSlot).r();
-      if s.len() <= old.cap as usize {
-        old.len = s.len() as u16;
-        self.get_lob(old.lob_id).copy_from_nonoverlapping(s.as_ptr(), s.len());
-      } else {
-        self.dealloc_lob(old.lob_id, old.cap as u32);
-        write_varchar(self);
+  // `s` is the content of a varchar / text / blob (see `Lit::bytes`), `ci` is the col
+  pub unsafe fn lit2varchar(&mut self, ptr: *mut u8, ci: &ColInfo, s: &[u8], initialized: bool) {
+    if ci.ty.is_long() { return self.lit2text(ptr, s, initialized); }
+    let old = (ptr as *mut VarcharSlot).r();
+    // a short string is inlined and never compressed
+    let z = if ci.flags.contains(ColFlags::COMPRESSED) && s.len() > INLINE_VARCHAR { compress::compress(s) } else { None };
+    let s = z.as_ref().map(|z| z.as_slice()).unwrap_or(s);
+    if initialized && !old.is_inline() {
+      // a short string is inlined even if the old lob can hold it
+      if s.len() > INLINE_VARCHAR && s.len() <= old.cap() as usize {
+        (old.len = s.len() as u16, old.set_compressed(z.is_some()));
+        return self.get_lob_mut(old.lob_id, (s.len() as u32 + LOB_SLOT_SIZE as u32 - 1) / LOB_SLOT_SIZE as u32).copy_from_nonoverlapping(s.as_ptr(), s.len());
       }
-    } else { write_varchar(self); }
+      self.dealloc_lob(old.lob_id, old.cap() as u32);
+    }
+    if s.len() <= INLINE_VARCHAR {
+      self.use_feature(DbFeatures::INLINE_VARCHAR);
+      old.set_inline(s);
+    } else {
+      if z.is_some() { self.use_feature(DbFeatures::COMPRESSED_VARCHAR); }
+      let (lob_id, cap, ptr1) = self.alloc_lob(s.len() as u32);
+      ptr1.copy_from_nonoverlapping(s.as_ptr(), s.len());
+      *old = VarcharSlot { lob_id, len: s.len() as u16, cap: cap as u16 };
+      old.set_compressed(z.is_some());
+    }
+  }
+
+  // the old value is freed first, so that its slots can be reused by the new one
+  unsafe fn lit2text(&mut self, ptr: *mut u8, s: &[u8], initialized: bool) {
+    let old = (ptr as *mut TextSlot).r();
+    // `s` may be in the old value (e.g. `set t = t`), which is overwritten by freeing it
+    let lob = self.get_lob(0) as *const u8..self.get_lob(self.lob_slots) as *const u8;
+    let copy = if initialized && lob.contains(&s.as_ptr()) { Some(s.to_vec()) } else { None };
+    let s = copy.as_ref().map(|s| s.as_slice()).unwrap_or(s);
+    if initialized { self.free_varchar(ptr, ColTy::Text); }
+    *old = TextSlot { lob_id: self.alloc_text(s), len: s.len() as u32 };
   }
 
   // input the whole data slot, result may be null
-  pub unsafe fn data2lit<'a>(&self, data: *const u8, ci_id: u32, ci: &ColInfo) -> CLit<'a> {
+  pub unsafe fn data2lit<'a>(&self, data: *const u8, ci_id: u32, ci: &ColInfo, z: &Unzipped) -> CLit<'a> {
     if bsget(data as *const u32, ci_id as usize) { return CLit::new(Lit::Null); };
-    self.ptr2lit(data.add(ci.off as usize), ci.ty)
+    self.ptr2lit(data.add(ci.off as usize), ci.ty, z)
   }
 
   // input the data ptr, result is never null
-  pub unsafe fn ptr2lit<'a>(&self, ptr: *const u8, ty: ColTy) -> CLit<'a> {
+  // a compressed varchar or a text / blob in more than one extent is copied into `z`, so the result lives until `z` is dropped or reset;
+  // a col which is never compressed or long (e.g. a col with an index) can use a temporary one
+  pub unsafe fn ptr2lit<'a>(&self, ptr: *const u8, ty: ColTy, z: &Unzipped) -> CLit<'a> {
     CLit::new(match ty {
       bool!() => Lit::Bool(*(ptr as *const bool)),
       int!() => Lit::Number(*(ptr as *const i32) as f64),
       float!() => Lit::Number(*(ptr as *const f32) as f64),
       date!() => Lit::Date(*(ptr as *const NaiveDate)),
       char!() => Lit::Str(str_from_db(ptr)),
-      varchar!() => Lit::Str(self.varchar(ptr)),
+      varchar!() => Lit::Str(self.varchar(ptr, z)),
+      ColTy::Text => Lit::Str(self.text(ptr, z)),
+      ColTy::Blob => Lit::Blob(self.blob(ptr, z)),
     })
   }
 
-  pub unsafe fn varchar<'a>(&self, ptr: *const u8) -> &'a str {
+  // an inline varchar is in the data slot, so the result lives as long as the record
+  // a compressed varchar is decompressed into `z`, so the result lives until `z` is dropped or reset
+  pub unsafe fn varchar<'a>(&self, ptr: *const u8, z: &Unzipped) -> &'a str {
     let v = (ptr as *const VarcharSlot).r();
-    str_from_parts(self.pr().get_lob(v.lob_id), v.len as usize)
+    if v.is_inline() { str_from_parts(ptr, v.inline_len()) } else { str::from_utf8_unchecked(self.varchar_bytes(v, z)) }
   }
 
-  pub unsafe fn free_varchar(&mut self, ptr: *const u8) {
-    let v = (ptr as *const VarcharSlot).r();
-    self.dealloc_lob(v.lob_id, v.cap as u32);
+  // a text / blob in one extent is read in place, so the result lives as long as the record
+  // one in more extents is copied into `z`, so the result lives until `z` is dropped or reset
+  pub unsafe fn text<'a>(&self, ptr: *const u8, z: &Unzipped) -> &'a str {
+    str::from_utf8_unchecked(self.blob(ptr, z))
+  }
+
+  pub unsafe fn blob<'a>(&self, ptr: *const u8, z: &Unzipped) -> &'a [u8] {
+    let v = (ptr as *const TextSlot).r();
+    let (first, len) = (self.get_lob(v.lob_id), v.len as usize);
+    let e = (first as *const TextExtent).r();
+    if e.next == 0 && e.cap() >= len { return slice::from_raw_parts(first.add(TEXT_EXTENT_HEAD), len); }
+    z.fill(|buf| for (id, count) in self.text_extents(v.lob_id) {
+      let n = (len - buf.len()).min(count as usize * LOB_SLOT_SIZE - TEXT_EXTENT_HEAD);
+      buf.extend_from_slice(slice::from_raw_parts(self.get_lob(id).add(TEXT_EXTENT_HEAD), n));
+    })
+  }
+
+  // `ty` is the type of col, varchar / text / blob
+  pub unsafe fn free_varchar(&mut self, ptr: *const u8, ty: ColTy) {
+    for (lob_id, count) in self.lob_extents(ptr, ty) { self.dealloc_lob(lob_id, count * LOB_SLOT_SIZE as u32); }
   }
 }
 
 impl Db {
   pub unsafe fn dp<'a>(&mut self) -> &'a mut DbPage { self.get_page::<DbPage>(0) }
 
+  // ids of all TablePages, read without `get_page`, DbPage and TableListPages are verified when the database is opened
+  pub unsafe fn tables(&self) -> Vec<u32> {
+    let dp = (self.pager.page(0) as *const DbPage).r();
+    let n = dp.table_num as usize;
+    if n <= MAX_TABLE { return dp.tables.get_unchecked(..n).to_vec(); }
+    let mut tables = dp.tables.get_unchecked(..MAX_TABLE - 1).to_vec();
+    for page in self.table_lists() {
+      let tlp = (self.pager.page(page) as *const TableListPage).r();
+      tables.extend_from_slice(tlp.tables.get_unchecked(..(n - tables.len()).min(TABLE_LIST_CAP)));
+    }
+    tables
+  }
+
+  pub(crate) unsafe fn table_lists(&self) -> Vec<u32> {
+    let mut lists = Vec::new();
+    let mut cur = (self.pager.page(0) as *const DbPage).r().table_list().unwrap_or(!0);
+    while cur != !0 { (lists.push(cur), cur = (self.pager.page(cur) as *const TableListPage).r().next); }
+    lists
+  }
+
+  // replace the ids of all TablePages, TableListPages are allocated or deallocated as needed
+  pub(crate) unsafe fn set_tables(&mut self, tables: &[u32]) {
+    let mut lists = self.table_lists();
+    let (inline, rest) = if tables.len() <= MAX_TABLE { (tables, &[][..]) } else { tables.split_at(MAX_TABLE - 1) };
+    let need = (rest.len() + TABLE_LIST_CAP - 1) / TABLE_LIST_CAP;
+    while lists.len() > need { self.dealloc_page(lists.pop().unchecked_unwrap()); }
+    while lists.len() < need { lists.push(self.alloc_page::<TableListPage>().0); }
+    if need != 0 { self.use_feature(DbFeatures::TABLE_LIST); }
+    for (idx, chunk) in rest.chunks(TABLE_LIST_CAP).enumerate() {
+      let tlp = self.get_page::<TableListPage>(*lists.get_unchecked(idx));
+      tlp.next = lists.get(idx + 1).cloned().unwrap_or(!0);
+      tlp.tables.as_mut_ptr().copy_from_nonoverlapping(chunk.as_ptr(), chunk.len());
+    }
+    let dp = self.dp();
+    dp.table_num = tables.len() as u32;
+    dp.tables.as_mut_ptr().copy_from_nonoverlapping(inline.as_ptr(), inline.len());
+    if let Some(&first) = lists.first() { *dp.tables.get_unchecked_mut(MAX_TABLE - 1) = first; }
+  }
+
   pub fn create_table<'a>(&mut self, c: &CreateTable<'a>) -> Result<'a, ()> {
     unsafe {
-      let dp = self.dp();
-
       // validate table and cols
-      if dp.table_num == MAX_TABLE as u16 { return Err(TableExhausted); }
-      if c.table.len() > MAX_TABLE_NAME { return Err(TableNameTooLong(c.table)); }
       if self.get_tp(c.table).is_ok() { return Err(DupTable(c.table)); }
-      if c.cols.len() > MAX_COL { return Err(ColTooMany(c.cols.len())); }
       if c.cols.is_empty() { return Err(ColTooFew); }
       // its V will be used later to validate col cons, only allow one primary / foreign / check for one col
       let mut cols = IndexMap::default();
       for cd in &c.cols {
         if cols.insert(cd.col, (false, false, false, false)).is_some() { return Err(DupCol(cd.col)); }
-        if cd.col.len() > MAX_COL_NAME { return Err(ColNameTooLong(cd.col)); }
       }
 
       // validate col cons
@@ -213,39 +362,48 @@ impl Db {
       for c in &c.cols { size += c.ty.size() as usize; }
       size = (size + 3) & !3; // it should be 4-aligned to keep the alignment of the next slot
       if size > MAX_DATA_BYTE { return Err(ColSizeTooBig(size)); }
+      if primary_cnt > 1 { Db::primary_index_ck(&c.cols.iter().zip(cols.values()).filter(|(_, x)| x.0).map(|(cd, _)| cd.ty).collect::<Vec<_>>())?; }
 
       // now no error can occur, can write to db safely
 
-      // handle each col def
-      let (id, tp) = self.alloc_page::<TablePage>();
+      // handle each col def, the TablePage takes as many pages as its cols and names need
+      let names = c.cols.iter().map(|cd| (cd.col, "")).collect::<Vec<_>>();
+      let page_num = TablePage::page_num_for(c.table, &names) as u32;
+      let (id, tp) = self.alloc_pages::<TablePage>(page_num);
       let mut size = (c.cols.len() as u16 + 31) / 32 * 4; // null bitset
-      for (i, c) in c.cols.iter().enumerate() {
+      let offs = c.cols.iter().map(|c| {
         if c.ty.align4() { size = (size + 3) & !3; }
-        tp.cols.get_unchecked_mut(i).init(c.ty, size, c.col, c.notnull);
-        size += c.ty.size();
-      }
+        (size, size += c.ty.size()).0
+      }).collect::<Vec<_>>();
       size = (size + 3) & !3;
-      tp.init(size.max(MIN_SLOT_SIZE as u16), c.cols.len() as u8, c.table);
+      tp.init(size.max(MIN_SLOT_SIZE as u16), c.cols.len() as u16);
+      tp.page_num = page_num;
+      for ((ci, c), &off) in tp.cols_mut().iter_mut().zip(&c.cols).zip(&offs) {
+        ci.init(c.ty, off, c.notnull);
+        ci.flags.set(ColFlags::COMPRESSED, c.compressed);
+      }
+      if tp.set_names(c.table, &names) { self.use_feature(DbFeatures::LONG_NAME); }
+      if c.cols.iter().any(|cd| cd.ty.is_long()) { self.use_feature(DbFeatures::LOB_TYPES); }
 
       // handle table cons
       for cons in &c.cons {
         match cons {
           ColCons::Primary(pks) => for col in pks {
-            let ci = tp.cols.get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
+            let ci = tp.cols_mut().get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
             ci.flags.set(ColFlags::PRIMARY, true);
           }
           ColCons::Foreign { col, f_table, f_col } => {
-            let ci = tp.cols.get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
+            let ci = tp.cols_mut().get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
             let (f_tp_id, f_tp) = self.get_tp(f_table).unchecked_unwrap();
-            let f_ci_id = f_tp.get_ci(f_col).unchecked_unwrap().idx(&f_tp.cols);
-            (ci.f_table = f_tp_id, ci.f_col = f_ci_id as u8);
+            let f_ci_id = f_tp.get_ci(f_col).unchecked_unwrap().idx(f_tp.cols());
+            (ci.f_table = f_tp_id, ci.f_col = f_ci_id as u16);
           }
           ColCons::Unique(col) => {
-            let ci = tp.cols.get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
+            let ci = tp.cols_mut().get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
             ci.flags.set(ColFlags::UNIQUE, true);
           }
           ColCons::Check(col, check) => {
-            let ci = tp.cols.get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
+            let ci = tp.cols_mut().get_unchecked_mut(cols.get_full(col).unchecked_unwrap().0);
             let (id, cp) = self.alloc_page::<CheckPage>();
             ci.check = id << 1;
             cp.count = check.len() as u16;
@@ -259,7 +417,7 @@ impl Db {
       for (idx, col) in c.cols.iter().enumerate() {
         if let Some(dft) = col.dft {
           if !dft.is_null() {
-            let ci = tp.cols.get_unchecked_mut(idx);
+            let ci = tp.cols_mut().get_unchecked_mut(idx);
             let cp = if ci.check == !0 {
               let (id, cp) = self.alloc_page::<CheckPage>();
               ci.check = id << 1;
@@ -271,40 +429,58 @@ impl Db {
         }
       }
 
-      *dp.tables.get_unchecked_mut(dp.table_num as usize) = id;
-      dp.table_num += 1;
-      tp.cols().iter().filter(|ci| ci.unique(primary_cnt) || ci.f_table != !0).for_each(|ci| self.alloc_index(ci.pr(), "").unchecked_unwrap());
+      let mut tables = self.tables();
+      tables.push(id);
+      self.set_tables(&tables);
+      tp.cols().iter().filter(|ci| ci.unique(primary_cnt) || ci.f_table != !0).for_each(|ci| { self.alloc_index(id, ci.pr(), ""); });
+      if primary_cnt > 1 { self.alloc_primary_index(id, &tp.primary_cols().map(|ci| ci.idx(tp.cols()) as u16).collect::<Vec<_>>()); }
+      self.catalog().add_table(id, tp, None);
       Ok(())
     }
   }
-
-  // return all the (tp_id1, ci_id1, ci_id), where tp_id1.ci_id1 has foreign link to tp_id.ci_id
-  pub unsafe fn foreign_links_to<'a>(&'a mut self, tp_id: u32) -> impl Iterator<Item=(u32, u8, u8)> + 'a {
-    self.dp().tables().iter().flat_map(move |&tp_id1|
-      self.get_page::<TablePage>(tp_id1).cols().iter().enumerate().filter_map(move |(ci_id1, ci1)|
-        if ci1.f_table == tp_id { Some((tp_id1, ci_id1 as u8, ci1.f_col)) } else { None }))
-  }
 }
 
 impl Db {
-  pub unsafe fn get_page<'a, P>(&mut self, page: u32) -> &'a mut P {
+  pub unsafe fn get_page<'a, P: Page>(&mut self, page: u32) -> &'a mut P {
+    self.modify_page(page, P::KIND);
+    // the other pages of a TablePage or an IndexListPage are modified through it
+    for page in page + 1..page + self.page::<P>(page).page_num() { self.modify_page(page, P::KIND); }
+    (self.pager.page(page) as *mut P).r()
+  }
+
+  // journal `page` and mark it as modified
+  unsafe fn modify_page(&mut self, page: u32, kind: &'static str) {
     debug_assert!(page < self.pages);
-    (self.mmap.get_unchecked_mut(page as usize * PAGE_SIZE).p() as *mut P).r()
+    self.journal_page(page);
+    if !self.is_dirty(page) { self.touch_page(page, kind); }
+  }
+
+  // read `page` without journaling it or marking it as modified, so that readers only need `&self`
+  // the page should have been verified (e.g., by `verify_table`), and it must not be modified through the returned reference
+  pub unsafe fn page<'a, P>(&self, page: u32) -> &'a P {
+    debug_assert!(page < self.pages);
+    &*(self.pager.page(page) as *const P)
   }
 
   // the return P is neither initialized nor zeroed, just keeping the original bytes
   // allocation may not always be successful(when 64G is used up), but in most cases this error is not recoverable, so let it crash
-  pub unsafe fn alloc_page<'a, P>(&mut self) -> (u32, &'a mut P) {
+  pub unsafe fn alloc_page<'a, P>(&mut self) -> (u32, &'a mut P) { self.alloc_pages(1) }
+
+  // allocate `page_num` consecutive pages for a TablePage or an IndexListPage, whose `page_num` should be set by the caller
+  // a single page is taken from the free list if possible, more pages are always added to the end of the file
+  pub unsafe fn alloc_pages<'a, P>(&mut self, page_num: u32) -> (u32, &'a mut P) {
     let dp = self.dp();
-    let free = if dp.first_free != !0 {
+    let free = if page_num == 1 && dp.first_free != !0 {
       let free = dp.first_free;
       dp.first_free = *self.get_page(free); // [0] stores next free(or none)
       free
     } else {
-      self.file.set_len((self.pages as u64 + 1) * PAGE_SIZE as u64).expect("Failed to allocate page. The database may already be in an invalid state.");
-      (self.pages, self.pages += 1).0
+      self.pager.set_pages(self.pages + page_num).expect("Failed to allocate page. The database may already be in an invalid state.");
+      for _ in 0..page_num { (self.pages += 1, self.push_sum()); }
+      self.pages - page_num
     };
-    (free, self.get_page(free))
+    // the pages are already journaled and modified, and `get_page` cannot be used before `page_num` is set
+    (free, (self.pager.page(free) as *mut P).r())
   }
 
   // add `page` to the head of free list
@@ -315,13 +491,73 @@ impl Db {
     dp.first_free = page;
   }
 
+  // free the pages allocated by `alloc_pages`
+  pub unsafe fn dealloc_pages(&mut self, page: u32, page_num: u32) {
+    for page in page..page + page_num { self.dealloc_page(page); }
+  }
+
+  // move the `page_num` pages from `page` to `new_num` new pages, and free the old ones; return the new id
+  // the content is copied as it is, names in the name area are still valid because their offsets are relative
+  unsafe fn move_pages(&mut self, page: u32, page_num: u32, new_num: u32) -> u32 {
+    let id = self.alloc_pages::<u8>(new_num).0;
+    self.pager.page(id).copy_from_nonoverlapping(self.pager.page(page), page_num as usize * PAGE_SIZE);
+    self.dealloc_pages(page, page_num);
+    id
+  }
+
+  // make the TablePage `tp_id` take at least `page_num` pages, it is moved to new pages if it takes fewer
+  // return its id, which should be used instead of `tp_id` after calling it, and any reference to the old TablePage is invalid
+  pub unsafe fn reserve_tp(&mut self, tp_id: u32, page_num: u32) -> u32 {
+    let old = self.page::<TablePage>(tp_id).page_num;
+    if page_num <= old { return tp_id; }
+    let id = self.move_pages(tp_id, old, page_num);
+    (self.pager.page(id) as *mut TablePage).r().page_num = page_num;
+    self.forget_table(tp_id);
+    let tables = self.tables().into_iter().map(|x| if x == tp_id { id } else { x }).collect::<Vec<_>>();
+    self.set_tables(&tables);
+    // foreign links to it, including those from itself
+    for &tp_id1 in &tables {
+      if self.page::<TablePage>(tp_id1).cols().iter().any(|ci| ci.f_table == tp_id) {
+        for ci in self.get_page::<TablePage>(tp_id1).cols_mut() {
+          if ci.f_table == tp_id { ci.f_table = id; }
+        }
+      }
+    }
+    self.invalidate_catalog();
+    id
+  }
+
+  // write all names of the table `tp_id` (see `TablePage::set_names`), it is moved if they need more pages (see `reserve_tp`)
+  pub unsafe fn set_table_names<S: AsRef<str>>(&mut self, tp_id: u32, table: &str, cols: &[(S, S)]) -> u32 {
+    let tp_id = self.reserve_tp(tp_id, TablePage::page_num_for(table, cols) as u32);
+    if self.get_page::<TablePage>(tp_id).set_names(table, cols) { self.use_feature(DbFeatures::LONG_NAME); }
+    tp_id
+  }
+
+  // write all composite index names of the table `tp_id`, its IndexListPage is moved if they need more pages
+  pub unsafe fn set_index_names<S: AsRef<str>>(&mut self, tp_id: u32, names: &[S]) {
+    let (mut list_id, page_num) = (self.page::<TablePage>(tp_id).indexes, IndexListPage::page_num_for(names) as u32);
+    let old = self.page::<IndexListPage>(list_id).page_num;
+    if page_num > old {
+      list_id = self.move_pages(list_id, old, page_num);
+      (self.pager.page(list_id) as *mut IndexListPage).r().page_num = page_num;
+      self.get_page::<TablePage>(tp_id).indexes = list_id;
+    }
+    if self.get_page::<IndexListPage>(list_id).set_names(names) { self.use_feature(DbFeatures::LONG_NAME); }
+  }
+
   // for convenience, the index of TablePage is returned (because it cannot be obtained by `idx`)
   pub unsafe fn get_tp<'a, 'b>(&mut self, table: &'b str) -> Result<'b, (u32, &'a mut TablePage)> {
-    for &tp_id in self.dp().tables() {
-      let tp = self.get_page::<TablePage>(tp_id);
-      if tp.name() == table { return Ok((tp_id, tp)); }
-    }
-    Err(NoSuchTable(table))
+    let tp_id = *self.catalog().tables.get(table).ok_or(NoSuchTable(table))?;
+    self.verify_table(tp_id)?;
+    Ok((tp_id, self.get_page::<TablePage>(tp_id)))
+  }
+
+  // like `get_tp`, but for readers
+  pub unsafe fn find_tp<'a, 'b>(&self, table: &'b str) -> Result<'b, (u32, &'a TablePage)> {
+    let tp_id = self.table_id(table).ok_or(NoSuchTable(table))?;
+    self.verify_table(tp_id)?;
+    Ok((tp_id, self.page::<TablePage>(tp_id)))
   }
 
   pub unsafe fn alloc_data_slot(&mut self, tp_id: u32) -> Rid {
@@ -357,4 +593,9 @@ impl Db {
   pub unsafe fn get_data_slot(&mut self, tp: &TablePage, rid: Rid) -> *mut u8 {
     self.get_page::<DataPage>(rid.page()).data.as_mut_ptr().add((rid.slot() * tp.size as u32) as usize)
   }
+
+  // like `get_data_slot`, but for readers
+  pub unsafe fn data_slot(&self, tp: &TablePage, rid: Rid) -> *const u8 {
+    self.page::<DataPage>(rid.page()).data.as_ptr().add((rid.slot() * tp.size as u32) as usize)
+  }
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use unchecked_unwrap::UncheckedUnwrap;
+
+use common::{*, BareTy::*};
+use physics::*;
+use crate::{Db, Unzipped, is_null};
+
+// records are inserted in batches of this size
+const DUMP_BATCH: usize = 100;
+
+impl Db {
+  // a script which rebuilds this database when it is executed in an empty one (e.g. by `.read` in the repl)
+  // a table is created after all tables it has foreign links to, and is followed by its records and named indexes;
+  // a foreign link which cannot be declared in `create table` (to the table itself, or in a cycle) is added by `alter table` at last
+  pub fn dump<'a>(&self) -> Result<'a, String> {
+    unsafe {
+      let tables = self.tables();
+      for &tp_id in &tables { self.verify_table(tp_id)?; }
+      let mut rest = tables.into_iter().map(|tp_id| (tp_id, self.page::<TablePage>(tp_id))).collect::<Vec<_>>();
+      let (mut created, mut deferred) = (HashSet::default(), Vec::new());
+      let mut s = String::from("-- generated by `dump database`\n");
+      while !rest.is_empty() {
+        // in a cycle, the first table is created anyway
+        let i = rest.iter().position(|&(tp_id, tp)| tp.cols().iter().all(|ci| ci.f_table == !0 || ci.f_table == tp_id || created.contains(&ci.f_table))).unwrap_or(0);
+        let (tp_id, tp) = rest.remove(i);
+        self.dump_table(tp, &created, &mut deferred, &mut s);
+        created.insert(tp_id);
+      }
+      for (tp, ci) in deferred {
+        let f_tp = self.page::<TablePage>(ci.f_table);
+        writeln!(s, "alter table {} add foreign key ({}) references {}({});", tp.name(), ci.name(), f_tp.name(), f_tp.cols().get_unchecked(ci.f_col as usize).name()).unchecked_unwrap();
+      }
+      Ok((s.pop(), s).1)
+    }
+  }
+
+  unsafe fn dump_table<'a>(&self, tp: &'a TablePage, created: &HashSet<u32>, deferred: &mut Vec<(&'a TablePage, &'a ColInfo)>, s: &mut String) {
+    let mut decl = Vec::new();
+    for ci in tp.cols() {
+      let mut d = format!("{} {}", ci.name(), ty_sql(ci.ty));
+      if ci.flags.contains(ColFlags::COMPRESSED) { d += " compressed"; }
+      if ci.flags.contains(ColFlags::NOTNULL) { d += " not null"; }
+      if ci.check != !0 && (ci.check & 1) == 1 {
+        let cp = self.page::<CheckPage>(ci.check >> 1);
+        write!(d, " default {}", lit_sql(self.ptr2lit(cp.data.as_ptr().add(cp.count as usize * ci.ty.size() as usize), ci.ty, &Unzipped::default()).lit())).unchecked_unwrap();
+      }
+      decl.push(d);
+    }
+    let pks = tp.primary_cols().map(|ci| ci.name()).collect::<Vec<_>>();
+    if !pks.is_empty() { decl.push(format!("primary key ({})", pks.join(", "))); }
+    for ci in tp.cols() {
+      if ci.f_table != !0 {
+        if created.contains(&ci.f_table) {
+          let f_tp = self.page::<TablePage>(ci.f_table);
+          decl.push(format!("foreign key ({}) references {}({})", ci.name(), f_tp.name(), f_tp.cols().get_unchecked(ci.f_col as usize).name()));
+        } else { deferred.push((tp, ci)); }
+      }
+      if ci.flags.contains(ColFlags::UNIQUE) { decl.push(format!("unique ({})", ci.name())); }
+      if ci.check != !0 {
+        let cp = self.page::<CheckPage>(ci.check >> 1);
+        if cp.count != 0 {
+          let size = ci.ty.size() as usize;
+          let list = (0..cp.count as usize).map(|i| lit_sql(self.ptr2lit(cp.data.as_ptr().add(i * size), ci.ty, &Unzipped::default()).lit())).collect::<Vec<_>>();
+          decl.push(format!("check ({} in ({}))", ci.name(), list.join(", ")));
+        }
+      }
+    }
+    writeln!(s, "create table {} ({});", tp.name(), decl.join(", ")).unchecked_unwrap();
+
+    let mut n = 0;
+    for (data, _) in self.record_iter(tp) {
+      *s += if n % DUMP_BATCH == 0 { if n != 0 { ";\ninsert into " } else { "insert into " } } else { ", " };
+      if n % DUMP_BATCH == 0 { write!(s, "{} values ", tp.name()).unchecked_unwrap(); }
+      s.push('(');
+      for (ci_id, ci) in tp.cols().iter().enumerate() {
+        if ci_id != 0 { *s += ", "; }
+        *s += &if is_null(data, ci_id as u32) { "null".into() } else { lit_sql(self.ptr2lit(data.add(ci.off as usize), ci.ty, &Unzipped::default()).lit()) };
+      }
+      (s.push(')'), n += 1);
+    }
+    if n != 0 { *s += ";\n"; }
+
+    for ci in tp.cols() {
+      if let Some(index) = ci.idx_name().filter(|x| !x.is_empty()) {
+        writeln!(s, "create index {} on {} ({});", index, tp.name(), ci.name()).unchecked_unwrap();
+      }
+    }
+    if tp.indexes != !0 {
+      // the index of the primary key is created with the table
+      for info in self.page::<IndexListPage>(tp.indexes).indexes().iter().filter(|info| !info.flags.contains(IndexFlags::PRIMARY)) {
+        let cols = info.cols().iter().map(|&ci_id| tp.cols().get_unchecked(ci_id as usize).name()).collect::<Vec<_>>();
+        writeln!(s, "create index {} on {} ({});", info.name(), tp.name(), cols.join(", ")).unchecked_unwrap();
+      }
+    }
+  }
+}
+
+fn ty_sql(ty: ColTy) -> String {
+  match ty {
+    // the size of bool / int / float / date is meaningless, but it is kept as it is
+    ColTy::FixTy(FixTy { ty, size }) => {
+      let name = match ty { Bool => "bool", Int => "int", Float => "float", Date => "date", Char => "char" };
+      if size == 0 && ty != Char { name.into() } else { format!("{}({})", name, size) }
+    }
+    ColTy::Varchar(size) => format!("varchar({})", size),
+    ColTy::Text => "text".into(),
+    ColTy::Blob => "blob".into(),
+  }
+}
+
+// the parser accepts it and produces the same value
+fn lit_sql(lit: Lit) -> String {
+  match lit {
+    Lit::Date(x) => format!("'{}'", x),
+    // an integer out of the range of i32 is regarded as an invalid int
+    Lit::Number(x) if x.fract() == 0.0 && (x < i32::MIN as f64 || x > i32::MAX as f64) => format!("{}.0", x),
+    // the parser reads '' in a string literal as '
+    Lit::Str(x) => format!("'{}'", x.replace('\'', "''")),
+    lit => format!("{:?}", lit),
+  }
+}
@@ -3,13 +3,15 @@ use physics::*;
 use crate::Db;
 
 impl Db {
-  pub unsafe fn record_iter<'a>(&mut self, tp: &TablePage) -> RecordIter<'a> {
-    RecordIter { db: self.pr(), page: tp.first, slot: 0, size: tp.size, cap: tp.cap }
+  // pages are read by `page`, so the records can only be read; a writer should use `get_data_slot` to modify one
+  // the iterator borrows `self`, a writer should collect the records it needs before modifying the database
+  pub unsafe fn record_iter<'a>(&'a self, tp: &TablePage) -> RecordIter<'a> {
+    RecordIter { db: self, page: tp.first, slot: 0, size: tp.size, cap: tp.cap }
   }
 }
 
 pub struct RecordIter<'a> {
-  db: &'a mut Db,
+  db: &'a Db,
   page: u32,
   slot: u16,
   size: u16,
@@ -17,18 +19,18 @@ pub struct RecordIter<'a> {
 }
 
 impl Iterator for RecordIter<'_> {
-  type Item = (*mut u8, Rid);
+  type Item = (*const u8, Rid);
 
   fn next(&mut self) -> Option<Self::Item> {
     unsafe {
       loop {
         if self.page == !0 { return None; }
         // now self.page must be a valid data page id
-        let dp = self.db.get_page::<DataPage>(self.page);
+        let dp = self.db.page::<DataPage>(self.page);
         for i in self.slot as usize..self.cap as usize {
           if bsget(dp.used.as_ptr(), i) {
             self.slot = i as u16 + 1;
-            let data = dp.data.as_mut_ptr().add(i * self.size as usize);
+            let data = dp.data.as_ptr().add(i * self.size as usize);
             return Some((data, Rid::new(self.page, i as u32)));
           }
         }
@@ -0,0 +1,268 @@
+use std::{io::{Read, Write, Seek, SeekFrom}, mem::size_of, slice, result};
+
+use common::{*, Error::*};
+use physics::*;
+use crate::{Db, SyncMode, pager::Store};
+
+// the journal is an undo log stored in `<db>.journal`
+// while a transaction is active, the original image of a page (or of a chunk of `LOB_CHUNK` lob slots) is appended to it
+// before the page is touched for the first time; committing empties the journal
+// so a non-empty journal found by `Db::open` belongs to an unfinished transaction, copying all images back and restoring
+// the file sizes recorded in the header brings the database back to the state before that transaction
+// a statement inside a transaction starts a savepoint, which remembers the current end of the journal, and journals
+// pages again on their first touch in this statement, so the statement alone can be undone by the records after the savepoint
+// a page modified outside a transaction (e.g., by calling `index::add_col` directly) begins an implicit transaction, which is
+// committed by the next `begin`, `sync`, or when the database is closed, so a crash before that undoes these changes
+
+pub const LOB_CHUNK: usize = PAGE_SIZE / LOB_SLOT_SIZE; // 256
+
+const JOURNAL_MAGIC: &[u8; MAGIC_LEN] = b"MashPlant-Journal\0";
+const HEADER_SIZE: u64 = size_of::<Header>() as u64;
+const RECORD_SIZE: u64 = size_of::<Record>() as u64;
+const PAGE: u32 = 0;
+const LOB: u32 = 1;
+
+#[repr(C)]
+struct Header {
+  magic: [u8; MAGIC_LEN],
+  _rsv: [u8; 2],
+  // the size of page file and lob file when the transaction began
+  pages: u32,
+  lob_slots: u32,
+}
+
+#[repr(C)]
+struct Record {
+  // PAGE or LOB
+  kind: u32,
+  // page id, or lob chunk id (the first slot of this chunk is `id * LOB_CHUNK`)
+  id: u32,
+  image: [u8; PAGE_SIZE],
+}
+
+pub struct Journal {
+  pub(crate) file: Box<dyn Store>,
+  // nothing is journaled outside a transaction
+  pub(crate) active: bool,
+  // the active transaction is begun by a modification outside a transaction, rather than by `begin`
+  pub(crate) implicit: bool,
+  // inside `Db::atomic`
+  stmt: bool,
+  pub(crate) sync: SyncMode,
+  // the end of journal, and the size of page file and lob file at the savepoint (or at the beginning of the transaction)
+  // pages and lob slots beyond these sizes are discarded by truncation when rolling back, so they need no image
+  sp: u64,
+  pub(crate) pages: u32,
+  pub(crate) lob_slots: u32,
+  // the current end of journal
+  len: u64,
+  // pages and lob chunks whose image since the savepoint is already in the journal
+  saved: HashSet<u32>,
+  saved_lob: HashSet<u32>,
+  rec: Box<Record>,
+}
+
+unsafe fn bytes<'a, T>(x: &T) -> &'a [u8] { slice::from_raw_parts(x as *const T as *const u8, size_of::<T>()) }
+
+unsafe fn bytes_mut<'a, T>(x: &mut T) -> &'a mut [u8] { slice::from_raw_parts_mut(x as *mut T as *mut u8, size_of::<T>()) }
+
+impl Journal {
+  pub(crate) fn new(file: Box<dyn Store>) -> Journal {
+    let rec = box Record { kind: PAGE, id: 0, image: [0; PAGE_SIZE] };
+    Journal { file, active: false, implicit: false, stmt: false, sync: SyncMode::Off, sp: 0, pages: 0, lob_slots: 0, len: 0, saved: HashSet::default(), saved_lob: HashSet::default(), rec }
+  }
+
+  // a journal with a header is left by a transaction that never finished
+  pub(crate) fn is_hot<'a>(&self) -> Result<'a, bool> { Ok(self.file.size()? >= HEADER_SIZE) }
+
+  unsafe fn append(&mut self) {
+    let rec = bytes(&*self.rec);
+    // the image must be on disk before the page is modified (and possibly written back by the kernel), whatever the sync mode is
+    self.file.seek(SeekFrom::Start(self.len)).and_then(|_| self.file.write_all(rec)).and_then(|_| self.file.sync())
+      .expect("Failed to write journal. The database may already be in an invalid state.");
+    self.len += RECORD_SIZE;
+  }
+
+  fn savepoint(&mut self, pages: u32, lob_slots: u32) {
+    (self.sp = self.len, self.pages = pages, self.lob_slots = lob_slots);
+    self.saved.clear();
+    self.saved_lob.clear();
+  }
+
+  fn end(&mut self) {
+    (self.active = false, self.implicit = false, self.stmt = false);
+    self.len = 0;
+    self.saved.clear();
+    self.saved_lob.clear();
+  }
+}
+
+impl Db {
+  // an implicit transaction is not regarded as a transaction here
+  pub fn in_transaction(&self) -> bool { self.journal.active && !self.journal.implicit }
+
+  // the implicit transaction, if any, is committed first
+  pub fn begin<'a>(&mut self) -> Result<'a, ()> {
+    if self.in_transaction() { return Err(NestedTransaction); }
+    if self.read_only { return Err(ReadOnlyDatabase); }
+    if self.journal.active { self.finish(self.journal.sync != SyncMode::Off)?; }
+    unsafe { self.begin_journal()?; }
+    Ok(())
+  }
+
+  unsafe fn begin_journal<'a>(&mut self) -> Result<'a, ()> {
+    let j = &mut self.journal;
+    let header = Header { magic: *JOURNAL_MAGIC, _rsv: [0; 2], pages: self.pages, lob_slots: self.lob_slots };
+    j.file.set_len(0)?;
+    j.file.seek(SeekFrom::Start(0))?;
+    j.file.write_all(bytes(&header))?;
+    j.len = HEADER_SIZE;
+    j.savepoint(self.pages, self.lob_slots);
+    j.active = true;
+    Ok(())
+  }
+
+  // called before a page or lob slot is modified outside a transaction; a read-only session never writes the files,
+  // so its modifications (e.g., by `check` without repair) need no journal
+  unsafe fn begin_implicit(&mut self) {
+    if self.journal.active || self.read_only { return; }
+    self.begin_journal().expect("Failed to write journal. The database may already be in an invalid state.");
+    self.journal.implicit = true;
+  }
+
+  pub fn commit<'a>(&mut self) -> Result<'a, ()> {
+    if !self.in_transaction() { return Err(NoTransaction); }
+    self.finish(self.journal.sync != SyncMode::Off)
+  }
+
+  // commit the active transaction, the data is synced before the journal is emptied if `sync` is true
+  // it is rolled back instead if a corrupted page is modified
+  pub(crate) fn finish<'a>(&mut self, sync: bool) -> Result<'a, ()> {
+    if let Err(e) = self.check_corrupted() { return (self.undo()?, Err(e)).1; }
+    // checksums must be updated before the journal is emptied, otherwise a crash here makes these pages look corrupted
+    unsafe { self.flush_sums()?; }
+    if sync { self.sync_files()?; }
+    self.journal.file.set_len(0)?;
+    if sync { self.journal.file.sync()?; }
+    self.journal.end();
+    self.pager.release();
+    Ok(())
+  }
+
+  pub fn rollback<'a>(&mut self) -> Result<'a, ()> {
+    if !self.in_transaction() { return Err(NoTransaction); }
+    self.undo()
+  }
+
+  fn undo<'a>(&mut self) -> Result<'a, ()> {
+    unsafe { self.restore()?; }
+    self.journal.end();
+    self.pager.release();
+    self.clear_corrupted();
+    Ok(())
+  }
+
+  // run `f` in a transaction, commit if it returns Ok, otherwise rollback all its changes
+  pub fn transaction<'a, T, E: From<Error<'a>>>(&mut self, f: impl FnOnce(&mut Db) -> result::Result<T, E>) -> result::Result<T, E> {
+    self.begin()?;
+    match f(self) {
+      Ok(x) => (self.commit()?, Ok(x)).1,
+      Err(e) => (self.rollback()?, Err(e)).1,
+    }
+  }
+
+  // run `f` as a single statement: if it returns Err, all its changes are undone, but changes before it are kept
+  // outside a transaction, it is a transaction itself; nested `atomic` is a part of the outermost one
+  pub fn atomic<'a, T, E: From<Error<'a>>>(&mut self, f: impl FnOnce(&mut Db) -> result::Result<T, E>) -> result::Result<T, E> {
+    if !self.in_transaction() { return self.transaction(f); }
+    if self.journal.stmt { return f(self); }
+    (self.journal.stmt = true, self.journal.savepoint(self.pages, self.lob_slots));
+    let res = f(self).and_then(|x| (self.check_corrupted()?, Ok(x)).1);
+    self.journal.stmt = false;
+    if res.is_err() { unsafe { self.rollback_savepoint()?; } }
+    self.clear_corrupted();
+    if self.journal.sync == SyncMode::Statement { self.sync()?; }
+    res
+  }
+
+  // called by `get_page` before a page is returned (and possibly modified)
+  pub(crate) unsafe fn journal_page(&mut self, page: u32) {
+    self.begin_implicit();
+    let j = &mut self.journal;
+    if j.active && page < j.pages && j.saved.insert(page) {
+      (j.rec.kind = PAGE, j.rec.id = page);
+      j.rec.image.as_mut_ptr().copy_from_nonoverlapping(self.pager.page(page), PAGE_SIZE);
+      j.append();
+    }
+  }
+
+  // must be called before lob slots [id, id + count) are modified
+  pub(crate) unsafe fn journal_lob(&mut self, id: u32, count: u32) {
+    self.begin_implicit();
+    let j = &mut self.journal;
+    if !j.active || id >= j.lob_slots { return; }
+    let end = (id + count).min(j.lob_slots);
+    let mut chunk = id / LOB_CHUNK as u32;
+    while chunk * (LOB_CHUNK as u32) < end {
+      if j.saved_lob.insert(chunk) {
+        let start = chunk * LOB_CHUNK as u32;
+        let n = (j.lob_slots - start).min(LOB_CHUNK as u32) as usize * LOB_SLOT_SIZE; // slots beyond `j.lob_slots` may not be mapped
+        (j.rec.kind = LOB, j.rec.id = chunk);
+        j.rec.image.as_mut_ptr().copy_from_nonoverlapping(self.pager.lob(start), n);
+        j.append();
+      }
+      chunk += 1;
+    }
+  }
+
+  // undo all changes since the savepoint, the transaction is still active
+  unsafe fn rollback_savepoint<'a>(&mut self) -> Result<'a, ()> {
+    let j = &mut self.journal;
+    let (sp, pages, lob_slots) = (j.sp, j.pages, j.lob_slots);
+    self.apply(sp, pages, lob_slots)?;
+    let j = &mut self.journal;
+    (j.file.set_len(sp)?, j.len = sp);
+    j.savepoint(pages, lob_slots);
+    Ok(())
+  }
+
+  // restore the file sizes in the header, then copy all images back, and finally empty the journal
+  pub(crate) unsafe fn restore<'a>(&mut self) -> Result<'a, ()> {
+    let j = &mut self.journal;
+    let mut header = Header { magic: [0; MAGIC_LEN], _rsv: [0; 2], pages: 0, lob_slots: 0 };
+    j.file.seek(SeekFrom::Start(0))?;
+    j.file.read_exact(bytes_mut(&mut header))?;
+    if &header.magic != JOURNAL_MAGIC { return Err(InvalidMagic(header.magic)); }
+    j.len = j.file.size()?;
+    self.apply(HEADER_SIZE, header.pages, header.lob_slots)?;
+    self.flush_sums()?;
+    self.journal.file.set_len(0)?;
+    Ok(())
+  }
+
+  // truncate page file and lob file to the given sizes, and apply the records in journal[start..]
+  unsafe fn apply<'a>(&mut self, start: u64, pages: u32, lob_slots: u32) -> Result<'a, ()> {
+    self.pager.set_pages(pages)?;
+    self.pager.set_lob_slots(lob_slots)?;
+    (self.pages = pages, self.lob_slots = lob_slots);
+    self.resize_sums(pages)?;
+    (self.catalog.invalidate(), self.free_lobs.invalidate(), self.sums.forget_tables());
+    let j = &mut self.journal;
+    // a record is written completely before the page is modified, so an incomplete record at the end can be ignored
+    // if a page has more than one image, the one closer to the header is older, so apply from back to front
+    for i in (0..(j.len - start) / RECORD_SIZE).rev() {
+      j.file.seek(SeekFrom::Start(start + i * RECORD_SIZE))?;
+      j.file.read_exact(bytes_mut(&mut *j.rec))?;
+      let (kind, id) = (j.rec.kind, j.rec.id);
+      if kind == PAGE && id < pages {
+        self.pager.page(id).copy_from_nonoverlapping(j.rec.image.as_ptr(), PAGE_SIZE);
+        self.sums.restored(id);
+      } else if kind == LOB && id * (LOB_CHUNK as u32) < lob_slots {
+        let start = id * LOB_CHUNK as u32;
+        let n = (lob_slots - start).min(LOB_CHUNK as u32) as usize * LOB_SLOT_SIZE;
+        self.pager.lob(start).copy_from_nonoverlapping(j.rec.image.as_ptr(), n);
+      }
+    }
+    Ok(())
+  }
+}
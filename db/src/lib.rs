@@ -5,14 +5,25 @@ pub mod iter;
 pub mod alter;
 pub mod show;
 pub mod lob;
+pub mod journal;
+pub mod checksum;
+pub mod sync;
+pub mod pager;
+pub mod catalog;
+pub mod version;
+pub mod check;
+pub mod shared;
+pub mod backup;
+pub mod bulk;
+pub mod compress;
+pub mod dump;
 
-pub use crate::{db::*, iter::*, lob::*, show::*};
+pub use crate::{db::*, iter::*, lob::*, show::*, sync::*, shared::*, compress::Unzipped};
 
 use regex::Regex;
 
-use common::{*, Error::*, BareTy::*};
+use common::{*, Error::*};
 use chrono::NaiveDate;
-use physics::ColInfo;
 
 // `data` points to the beginning of the whole data slot
 pub unsafe fn is_null(data: *const u8, ci_id: u32) -> bool { bsget(data as *const u32, ci_id as usize) }
@@ -25,20 +36,6 @@ pub fn like2re(like: &str) -> Result<Regex> {
   Regex::new(&escape_re(like)).map_err(|e| InvalidLike { like, reason: box e })
 }
 
-pub unsafe fn hash_pks(data: *const u8, pks: &[&ColInfo]) -> u128 {
-  const SEED: u128 = 19260817;
-  let mut hash = 0u128;
-  for &col in pks {
-    let ptr = data.add(col.off as usize);
-    match col.ty.fix_ty().ty {
-      Bool => hash = hash.wrapping_mul(SEED).wrapping_add(*ptr as u128),
-      Int | Float | Date => hash = hash.wrapping_mul(SEED).wrapping_add(*(ptr as *const u32) as u128),
-      Char => for &b in str_from_db(ptr).as_bytes() { hash = hash.wrapping_mul(SEED).wrapping_add(b as u128); }
-    }
-  }
-  hash
-}
-
 fn escape_re(like: &str) -> String {
   let mut re = String::with_capacity(like.len());
   let mut escape = false;
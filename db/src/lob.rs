@@ -1,65 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use common::*;
 use physics::*;
-use crate::Db;
+use crate::{Db, is_null};
+
+// an in-memory index of the free list in the lob file, to find a fit extent and the neighbors of an extent in O(log n)
+// the list in the lob file is still the persistent form of free slots; a rollback may restore it to any earlier state,
+// so the index is simply rebuilt after that, like `Catalog`
+#[derive(Default)]
+pub struct FreeLobs {
+  valid: bool,
+  // first slot -> slot count
+  pub(crate) by_id: BTreeMap<u32, u32>,
+  // (slot count, first slot), for best fit
+  by_count: BTreeSet<(u32, u32)>,
+}
+
+impl FreeLobs {
+  pub(crate) fn invalidate(&mut self) { self.valid = false; }
+
+  fn insert(&mut self, id: u32, count: u32) { (self.by_id.insert(id, count), self.by_count.insert((count, id))); }
+
+  fn remove(&mut self, id: u32, count: u32) { (self.by_id.remove(&id), self.by_count.remove(&(count, id))); }
+}
 
 impl Db {
-  pub unsafe fn get_lob(&mut self, id: u32) -> *mut u8 {
-    (self.lob_mmap.as_mut_ptr() as *mut FreeLobSlot).add(id as usize) as *mut u8
+  // the returned ptr is only for reading, use `get_lob_mut` to write
+  pub unsafe fn get_lob(&self, id: u32) -> *mut u8 {
+    self.pager.lob(id)
+  }
+
+  // lob slots [id, id + count) will be written through the returned ptr
+  pub unsafe fn get_lob_mut(&mut self, id: u32, count: u32) -> *mut u8 {
+    self.journal_lob(id, count);
+    self.get_lob(id)
+  }
+
+  // get a free list node for writing
+  unsafe fn lob_slot<'a>(&mut self, id: u32) -> &'a mut FreeLobSlot {
+    (self.get_lob_mut(id, 1) as *mut FreeLobSlot).r()
   }
 
   // return (lob id, actual bytes allocated, start addr of lob), lob id can be used for get & dealloc
   pub unsafe fn alloc_lob(&mut self, count: u32) -> (u32, u32, *mut u8) {
     let count = ((count + LOB_SLOT_SIZE as u32 - 1) / LOB_SLOT_SIZE as u32).max(1); // .max(1) to avoid alloc 0 uses the nil node
-    let base = self.lob_mmap.as_mut_ptr() as *mut FreeLobSlot;
-    let mut x = base.r();
-    while x.count < count {
-      if x.next == 0 { break; } else { x = base.add(x.next as usize).r(); }
+    let free = self.free_lobs();
+    // best fit: the smallest extent that is large enough, the rest of it is still free
+    if let Some(&(x_count, id)) = free.by_count.range((count, 0)..).next() {
+      (free.remove(id, x_count), self.unlink_lob(id));
+      if x_count > count { (free.insert(id + count, x_count - count), self.link_lob(id + count, x_count - count)); }
+      return (id, count * 32, self.get_lob_mut(id, count));
     }
-    if x.count >= count {
-      if x.count > count { self.shift_lob_link(x, count); } else {
-        let (prev, next) = (x.prev, x.next);
-        base.add(prev as usize).r().next = next;
-        base.add(next as usize).r().prev = prev;
-      }
-      (x.p().offset_from(base) as u32, count * 32, x.p() as *mut u8)
-    } else { // get out of `while` because of `break`
-      let id = (self.lob_slots, self.lob_slots += count).0;
-      self.lob_file.set_len(self.lob_slots as u64 * LOB_SLOT_SIZE as u64).expect("failed to allocate lob slot. the database may already be in an invalid state.");
-      (id, count * 32, base.add(id as usize) as *mut u8)
+    // no extent is large enough, so the file grows; if its last slots are free, they are used as the beginning
+    let end = self.lob_slots;
+    let id = match free.by_id.range(..end).next_back() {
+      Some((&id, &x_count)) if id + x_count == end => { (free.remove(id, x_count), self.unlink_lob(id)); id }
+      _ => end,
+    };
+    self.lob_slots = id + count;
+    self.pager.set_lob_slots(self.lob_slots).expect("failed to allocate lob slot. the database may already be in an invalid state.");
+    // lob slots may be moved by the pager, so get the ptr after growing; slots beyond the old end are not journaled
+    (id, count * 32, self.get_lob_mut(id, count))
+  }
+
+  // store `s` as a text / blob, return the first extent of it (see `TextExtent`)
+  // the best fit is used if an extent is large enough for the rest of `s`, otherwise the largest free extent is filled,
+  // so a long value can use free slots scattered in the lob file, and the file only grows if no slot is free
+  pub(crate) unsafe fn alloc_text(&mut self, s: &[u8]) -> u32 {
+    let (mut first, mut prev, mut rest) = (0, 0, s);
+    loop {
+      let need = ((rest.len() + TEXT_EXTENT_HEAD + LOB_SLOT_SIZE - 1) / LOB_SLOT_SIZE) as u32;
+      let free = self.free_lobs();
+      let (id, count) = match free.by_count.iter().next_back() {
+        Some(&(x_count, id)) if x_count < need => {
+          (free.remove(id, x_count), self.unlink_lob(id));
+          (id, x_count)
+        }
+        _ => { let (id, bytes, _) = self.alloc_lob((rest.len() + TEXT_EXTENT_HEAD) as u32); (id, bytes / LOB_SLOT_SIZE as u32) }
+      };
+      // get the ptr after allocating, because lob slots may be moved by the pager
+      let ptr = self.get_lob_mut(id, count);
+      let e = (ptr as *mut TextExtent).r();
+      (e.next = 0, e.count = count);
+      let n = rest.len().min(e.cap());
+      ptr.add(TEXT_EXTENT_HEAD).copy_from_nonoverlapping(rest.as_ptr(), n);
+      if prev == 0 { first = id; } else { (self.get_lob_mut(prev, 1) as *mut TextExtent).r().next = id; }
+      (prev = id, rest = rest.get_unchecked(n..));
+      if rest.is_empty() { break first; }
     }
   }
 
+  // (lob id, slot count) of all extents of a varchar / text / blob, `ptr` points to its VarcharSlot / TextSlot; empty for an inline varchar
+  pub(crate) unsafe fn lob_extents(&self, ptr: *const u8, ty: ColTy) -> Vec<(u32, u32)> {
+    if ty.is_long() { self.text_extents((ptr as *const TextSlot).r().lob_id) } else { lob_extent(ptr).into_iter().collect() }
+  }
+
+  // (lob id, slot count) of the extents of a text / blob from its first extent `id`
+  // the walk stops at an invalid extent, and is bounded by `lob_slots` like `free_lob_extents`; `check` reports such problems
+  pub(crate) unsafe fn text_extents(&self, mut id: u32) -> Vec<(u32, u32)> {
+    let mut ret = Vec::new();
+    while id != 0 && id < self.lob_slots && ret.len() < self.lob_slots as usize {
+      let e = (self.get_lob(id) as *const TextExtent).r();
+      if e.count == 0 || id.checked_add(e.count).map(|end| end > self.lob_slots).unwrap_or(true) { break; }
+      (ret.push((id, e.count)), id = e.next);
+    }
+    ret
+  }
+
+  // the freed extent is merged with the free extents on both sides
   pub unsafe fn dealloc_lob(&mut self, id: u32, count: u32) {
     debug_assert!(count != 0 && count % LOB_SLOT_SIZE as u32 == 0);
-    let count = count / LOB_SLOT_SIZE as u32;
-    let base = self.lob_mmap.as_mut_ptr() as *mut FreeLobSlot;
-    let (mut x_id, mut x) = (0, base.r());
-    loop {
-      if x_id + x.count == id {
-        return x.count += count;
-      } else if id + count == x_id {
-        return self.shift_lob_link(x, !count + 1); // !count + 1 == -count
-      } else {
-        x_id = x.next;
-        if x_id == 0 { break; } else { x = base.add(x_id as usize).r(); }
+    let mut count = count / LOB_SLOT_SIZE as u32;
+    let free = self.free_lobs();
+    if let Some(r_count) = free.by_id.get(&(id + count)).copied() {
+      (free.remove(id + count, r_count), self.unlink_lob(id + count));
+      count += r_count;
+    }
+    match free.by_id.range(..id).next_back() {
+      Some((&l_id, &l_count)) if l_id + l_count == id => {
+        (free.remove(l_id, l_count), free.insert(l_id, l_count + count));
+        self.lob_slot(l_id).count = l_count + count;
+      }
+      _ => (free.insert(id, count), self.link_lob(id, count)).1,
+    }
+  }
+
+  // move all varchars and extents of texts / blobs to the beginning of the lob file (keeping their order), rewrite their lob ids
+  // in records and in previous extents, and truncate the file, so that no slot is free after it
+  pub fn compact_lob<'a>(&mut self) -> Result<'a, ()> {
+    unsafe {
+      let tables = self.tables();
+      for &tp_id in &tables { self.verify_table(tp_id)?; }
+      // (lob id, slot count, whether it is an extent of a text / blob, the record referring to it if it is the first extent of a value)
+      // the record is (table, rid, offset of VarcharSlot / TextSlot in record)
+      let mut lobs = Vec::new();
+      for &tp_id in &tables {
+        let tp = self.page::<TablePage>(tp_id);
+        for (data, rid) in self.record_iter(tp) {
+          for (ci_id, ci) in tp.cols().iter().enumerate() {
+            if ci.ty.is_varchar() && !is_null(data, ci_id as u32) {
+              let mut owner = Some((tp_id, rid, ci.off));
+              for (lob_id, count) in self.lob_extents(data.add(ci.off as usize), ci.ty) { lobs.push((lob_id, count, ci.ty.is_long(), owner.take())); }
+            }
+          }
+        }
       }
+      lobs.sort_unstable_by_key(|x| x.0);
+      let mut end = 1; // slot 0 is the nil node
+      let new_ids = lobs.iter().map(|&(id, count, _, _)| (id, (end, end += count).0)).collect::<BTreeMap<_, _>>();
+      for (id, count, is_text, owner) in lobs {
+        let new_id = new_ids[&id];
+        // `new_id <= id`, the old and new places may overlap
+        if new_id != id { self.get_lob_mut(new_id, count).copy_from(self.get_lob(id), count as usize * LOB_SLOT_SIZE); }
+        // the next extent may also be moved, before or after this one
+        if is_text {
+          let next = (self.get_lob(new_id) as *const TextExtent).r().next;
+          match new_ids.get(&next) {
+            Some(&new_next) if new_next != next => (self.get_lob_mut(new_id, 1) as *mut TextExtent).r().next = new_next,
+            _ => {}
+          }
+        }
+        match owner {
+          Some((tp_id, rid, off)) if new_id != id => {
+            let tp = self.page::<TablePage>(tp_id);
+            // `lob_id` is at the same place in VarcharSlot and TextSlot
+            (self.get_data_slot(tp, rid).add(off as usize) as *mut VarcharSlot).r().lob_id = new_id;
+          }
+          _ => {}
+        }
+      }
+      self.lob_slot(0).init_nil();
+      // the slots to truncate are touched before truncation, so they can be restored on rollback (like `shrink`)
+      self.journal_lob(end, self.lob_slots - end);
+      self.pager.set_lob_slots(end)?;
+      self.lob_slots = end;
+      self.free_lobs.invalidate();
+      Ok(())
     }
-    // fails to extend any existing nodes, add to back
-    let nil = base.r();
-    let prev = base.add(nil.prev as usize).r();
-    let new = base.add(id as usize).r();
-    (prev.next = id, new.prev = nil.prev);
-    (new.next = 0, nil.prev = id);
-    new.count = count;
   }
 
-  // `shift as i32` can be negative
-  unsafe fn shift_lob_link(&mut self, x: &FreeLobSlot, shift: u32) {
-    let base = self.lob_mmap.as_mut_ptr() as *mut FreeLobSlot;
-    let (prev, next, new_x_id) = (x.prev, x.next, (x.p().offset_from(base) as u32).wrapping_add(shift));
-    let new_x = base.add(new_x_id as usize).r();
-    (base.add(prev as usize).r().next = new_x_id, new_x.prev = prev);
-    (base.add(next as usize).r().prev = new_x_id, new_x.next = next);
-    new_x.count = x.count.wrapping_sub(shift);
+  // remove the free node `id` from the list
+  unsafe fn unlink_lob(&mut self, id: u32) {
+    let x = self.lob_slot(id);
+    let (prev, next) = (x.prev, x.next);
+    self.lob_slot(prev).next = next;
+    self.lob_slot(next).prev = prev;
+  }
+
+  // add a free node to the back of the list, the order of the list doesn't matter
+  unsafe fn link_lob(&mut self, id: u32, count: u32) {
+    let prev = self.lob_slot(0).prev;
+    let x = self.lob_slot(id);
+    (x.prev = prev, x.next = 0, x.count = count);
+    self.lob_slot(prev).next = id;
+    self.lob_slot(0).prev = id;
+  }
+
+  pub(crate) unsafe fn free_lobs<'a>(&mut self) -> &'a mut FreeLobs {
+    if !self.free_lobs.valid { self.load_free_lobs(); }
+    (&mut self.free_lobs).pr()
+  }
+
+  unsafe fn load_free_lobs(&mut self) {
+    let mut free = FreeLobs::default();
+    for (id, count) in self.free_lob_extents() { free.insert(id, count); }
+    free.valid = true;
+    self.free_lobs = free;
+  }
+
+  // (first slot, slot count) of all free extents, in the order of the free list
+  // the walk is bounded by `lob_slots`, so that a corrupted list cannot make it loop forever; `check` reports such problems
+  pub(crate) unsafe fn free_lob_extents(&self) -> Vec<(u32, u32)> {
+    let base = self.pager.lob(0) as *const FreeLobSlot;
+    let (mut ret, mut cur) = (Vec::new(), (*base).next);
+    while cur != 0 && cur < self.lob_slots && ret.len() < self.lob_slots as usize {
+      let x = &*base.add(cur as usize);
+      (ret.push((cur, x.count)), cur = x.next);
+    }
+    ret
+  }
+
+  // rebuild the free list from `used`, which is (lob id, slot count) of all lobs in use; slots not covered by `used` are freed
+  pub(crate) unsafe fn rebuild_lob_list(&mut self, mut used: Vec<(u32, u32)>) {
+    used.sort_unstable();
+    used.push((self.lob_slots, 0));
+    let (mut prev, mut end) = (0, 1); // slot 0 is the nil node
+    for (id, count) in used {
+      if id > end {
+        let x = self.lob_slot(end);
+        (x.prev = prev, x.count = id - end);
+        self.lob_slot(prev).next = end;
+        prev = end;
+      }
+      end = end.max(id + count);
+    }
+    let nil = self.lob_slot(0);
+    (nil.prev = prev, nil.count = 0);
+    self.lob_slot(prev).next = 0;
+    self.free_lobs.invalidate();
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,152 @@
+use std::{fs::File, io::{self, Read, Write, Seek, Cursor}, mem};
+use memmap::{MmapOptions, MmapMut};
+
+use common::*;
+use physics::*;
+
+// where the pages and lob slots of a `Db` live
+// pages [0, pages) and lob slots [0, lob_slots) are accessible, `Db` keeps the two sizes and resizes the pager accordingly
+// the address of a page never moves until it is truncated; the address of lob slots may move when resizing,
+// but the old addresses are still readable (with the old content) until `release`
+// a `Db` may be shared by threads (see `SharedDb`), so pagers and stores must be `Send + Sync`
+pub trait Pager: Send + Sync {
+  unsafe fn page(&self, page: u32) -> *mut u8;
+
+  unsafe fn lob(&self, id: u32) -> *mut u8;
+
+  // newly added pages / lob slots are zeroed
+  // pages added by one call are contiguous in memory, so that a TablePage can take more than one page
+  fn set_pages(&mut self, pages: u32) -> io::Result<()>;
+
+  fn set_lob_slots(&mut self, lob_slots: u32) -> io::Result<()>;
+
+  // write all modifications to persistent storage (if any)
+  fn sync(&mut self, pages: u32, lob_slots: u32) -> io::Result<()>;
+
+  // called at the end of a transaction (or a statement outside transaction), when no pointer to lob is held
+  fn release(&mut self) {}
+}
+
+pub struct FilePager {
+  file: File,
+  mmap: MmapMut,
+  lob_file: File,
+  lob_mmap: MmapMut,
+}
+
+impl FilePager {
+  // `read_only` files are opened without write access, so they are mapped copy-on-write:
+  // the pages are still writable in memory (e.g., for `check database` without repair), but nothing reaches the files
+  // a private writable mapping is charged as committed memory, so it only covers the current file, which can't grow anyway
+  pub fn new(file: File, lob_file: File, read_only: bool) -> io::Result<FilePager> {
+    unsafe {
+      let map = |max: usize, file: &File| if read_only {
+        MmapOptions::new().len((file.metadata()?.len() as usize).min(max).max(PAGE_SIZE)).map_copy(file)
+      } else { MmapOptions::new().len(max).map_mut(file) };
+      // this is 64G, the maximum capacity of this db; mmap will not allocate memory unless accessed
+      let mmap = map(PAGE_SIZE * MAX_PAGE, &file)?;
+      // lob file can use all the 32 bits addr space, each addr for 32 bytes, in all 128G
+      let lob_mmap = map(!0u32 as usize * LOB_SLOT_SIZE, &lob_file)?;
+      Ok(FilePager { file, mmap, lob_file, lob_mmap })
+    }
+  }
+}
+
+impl Pager for FilePager {
+  unsafe fn page(&self, page: u32) -> *mut u8 { self.mmap.as_ptr().add(page as usize * PAGE_SIZE) as *mut u8 }
+
+  unsafe fn lob(&self, id: u32) -> *mut u8 { self.lob_mmap.as_ptr().add(id as usize * LOB_SLOT_SIZE) as *mut u8 }
+
+  fn set_pages(&mut self, pages: u32) -> io::Result<()> { self.file.set_len(pages as u64 * PAGE_SIZE as u64) }
+
+  fn set_lob_slots(&mut self, lob_slots: u32) -> io::Result<()> { self.lob_file.set_len(lob_slots as u64 * LOB_SLOT_SIZE as u64) }
+
+  fn sync(&mut self, pages: u32, lob_slots: u32) -> io::Result<()> {
+    self.mmap.flush_range(0, pages as usize * PAGE_SIZE)?;
+    self.file.sync_all()?;
+    self.lob_mmap.flush_range(0, lob_slots as usize * LOB_SLOT_SIZE)?;
+    self.lob_file.sync_all()
+  }
+}
+
+// align to 8 like the mmap, so that the u32 / u64 / f32 in a page can be accessed directly
+#[repr(C, align(8))]
+struct MemPage([u8; PAGE_SIZE]);
+
+// pages added by one `set_pages` are in one allocation, so that they are contiguous like in the mmap
+// the address of a page never moves until it is truncated, pages added again after truncation are in a new allocation
+// lob slots are in one contiguous buffer, because a lob can span many slots
+#[derive(Default)]
+pub struct MemPager {
+  // (id of its first page, pages)
+  segs: Vec<(usize, Box<[MemPage]>)>,
+  // the address of each page, pointing into `segs`
+  pages: Vec<*mut MemPage>,
+  // one element for one lob slot, [u64; 4] for size and alignment
+  lob: Vec<[u64; 4]>,
+  // buffers replaced by a larger one, there may still be pointers to them
+  retired: Vec<Vec<[u64; 4]>>,
+}
+
+// the pointers in `pages` are owned by `segs`, and only dereferenced like the pages of the mmap
+unsafe impl Send for MemPager {}
+unsafe impl Sync for MemPager {}
+
+impl Pager for MemPager {
+  unsafe fn page(&self, page: u32) -> *mut u8 { *self.pages.get_unchecked(page as usize) as *mut u8 }
+
+  unsafe fn lob(&self, id: u32) -> *mut u8 { self.lob.as_ptr().add(id as usize) as *mut u8 }
+
+  fn set_pages(&mut self, pages: u32) -> io::Result<()> {
+    let (pages, old) = (pages as usize, self.pages.len());
+    if pages < old {
+      self.pages.truncate(pages);
+      while self.segs.last().map(|s| s.0 >= pages).unwrap_or(false) { self.segs.pop(); }
+    } else if pages > old {
+      let mut seg = (old..pages).map(|_| MemPage([0; PAGE_SIZE])).collect::<Box<[_]>>();
+      self.pages.extend(seg.iter_mut().map(|page| page as *mut MemPage));
+      self.segs.push((old, seg));
+    }
+    Ok(())
+  }
+
+  fn set_lob_slots(&mut self, lob_slots: u32) -> io::Result<()> {
+    let lob_slots = lob_slots as usize;
+    if lob_slots > self.lob.capacity() {
+      let mut lob = Vec::with_capacity(lob_slots.max(self.lob.capacity() * 2));
+      lob.extend_from_slice(&self.lob);
+      self.retired.push(mem::replace(&mut self.lob, lob));
+    }
+    self.lob.resize(lob_slots, [0; 4]);
+    Ok(())
+  }
+
+  fn sync(&mut self, _pages: u32, _lob_slots: u32) -> io::Result<()> { Ok(()) }
+
+  fn release(&mut self) { self.retired.clear(); }
+}
+
+// where the journal and checksums are stored, a file or a memory buffer
+pub trait Store: Read + Write + Seek + Send + Sync {
+  fn size(&self) -> io::Result<u64>;
+
+  fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+  fn sync(&mut self) -> io::Result<()>;
+}
+
+impl Store for File {
+  fn size(&self) -> io::Result<u64> { Ok(self.metadata()?.len()) }
+
+  fn set_len(&mut self, len: u64) -> io::Result<()> { File::set_len(self, len) }
+
+  fn sync(&mut self) -> io::Result<()> { self.sync_data() }
+}
+
+impl Store for Cursor<Vec<u8>> {
+  fn size(&self) -> io::Result<u64> { Ok(self.get_ref().len() as u64) }
+
+  fn set_len(&mut self, len: u64) -> io::Result<()> { Ok(self.get_mut().resize(len as usize, 0)) }
+
+  fn sync(&mut self) -> io::Result<()> { Ok(()) }
+}
@@ -0,0 +1,26 @@
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::Db;
+
+// a `Db` shared by threads: any number of readers (e.g. `query::select`) run at the same time,
+// while a writer waits for all of them and runs alone
+// readers only see committed data if every transaction begins and ends inside one `write`,
+// otherwise (e.g. `begin` in one `write` and `commit` in another) readers in between see uncommitted changes
+pub struct SharedDb(RwLock<Db>);
+
+impl SharedDb {
+  pub fn new(db: Db) -> SharedDb { SharedDb(RwLock::new(db)) }
+
+  pub fn read(&self) -> RwLockReadGuard<Db> {
+    // a writer panicked in the middle of modification, the pages may be inconsistent, so don't read them
+    self.0.read().expect("a writer of the shared database panicked")
+  }
+
+  pub fn write(&self) -> RwLockWriteGuard<Db> {
+    self.0.write().expect("a writer of the shared database panicked")
+  }
+
+  pub fn into_inner(self) -> Db {
+    self.0.into_inner().expect("a writer of the shared database panicked")
+  }
+}
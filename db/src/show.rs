@@ -3,37 +3,70 @@ use unchecked_unwrap::UncheckedUnwrap;
 
 use common::*;
 use physics::*;
-use crate::Db;
+use crate::{Db, Unzipped};
 
+// it fails if the database is being written by another session, use `Db::show_db` for the database already opened
 pub fn show_db<'a>(path: impl AsRef<Path>, s: &mut String) -> Result<'a, ()> {
-  unsafe {
-    let mut db = Db::open(path)?;
-    let table_num = db.dp().table_num;
-    writeln!(s, "database: page count = {}, lob slot count = {}, table count = {}", db.pages, db.lob_slots, table_num).unchecked_unwrap();
-    Ok(())
-  }
+  Ok(Db::open_read_only(path)?.show_db(s))
 }
 
 impl Db {
+  pub fn show_db(&self, s: &mut String) {
+    unsafe {
+      let dp = (self.pager.page(0) as *const DbPage).r();
+      writeln!(s, "database: version = {}, features = {:?}, page count = {}, lob slot count = {}, table count = {}",
+        dp.version, dp.features, self.pages, self.lob_slots, dp.table_num).unchecked_unwrap();
+    }
+  }
+
+  // free extents are grouped by size classes of powers of 2 (in slots), fragmentation is the part of free slots not in the largest extent,
+  // i.e., how much of them cannot be used by one large lob without growing the file
+  pub fn show_lob(&self) -> String {
+    unsafe {
+      let free = self.free_lob_extents();
+      let free_slots = free.iter().map(|&(_, count)| count).sum::<u32>();
+      let largest = free.iter().map(|&(_, count)| count).max().unwrap_or(0);
+      let frag = if free_slots == 0 { 0.0 } else { (1.0 - largest as f64 / free_slots as f64) * 100.0 };
+      let mut s = String::new();
+      // slot 0 is the nil node of the free list, neither used nor free
+      writeln!(s, "lob: slot count = {}, used slot count = {}, free slot count = {}, free extent count = {}, largest free extent = {}, fragmentation = {:.1}%",
+        self.lob_slots, self.lob_slots - 1 - free_slots, free_slots, free.len(), largest, frag).unchecked_unwrap();
+      let mut classes = [(0u32, 0u32); 32];
+      for &(_, count) in &free {
+        let c = classes.get_unchecked_mut(31 - count.leading_zeros() as usize);
+        (c.0 += 1, c.1 += count);
+      }
+      for (i, &(n, slots)) in classes.iter().enumerate() {
+        if n != 0 {
+          writeln!(s, "  - extent size [{}, {}): extent count = {}, slot count = {}", 1u64 << i, 1u64 << (i + 1), n, slots).unchecked_unwrap();
+        }
+      }
+      (s.pop(), s).1
+    }
+  }
+
+  // only read paths are used, so they can run together with other readers (see `SharedDb`)
   pub fn show_table<'a>(&self, table: &'a str) -> Result<'a, String> {
     unsafe {
-      let tp = self.pr().get_tp(table)?.1;
+      let tp = self.find_tp(table)?.1;
       let mut s = String::new();
       self.show_table_info(tp, &mut s);
       Ok((s.pop(), s).1)
     }
   }
 
-  pub fn show_tables(&self) -> String {
+  pub fn show_tables<'a>(&self) -> Result<'a, String> {
     unsafe {
       let mut s = String::new();
-      for &tp_id in self.pr().dp().tables() {
-        self.show_table_info(self.pr().get_page::<TablePage>(tp_id), &mut s);
+      for tp_id in self.tables() {
+        self.verify_table(tp_id)?;
+        self.show_table_info(self.page::<TablePage>(tp_id), &mut s);
       }
-      (s.pop(), s).1
+      Ok((s.pop(), s).1)
     }
   }
 
+  // `tp` is verified, other TablePages and CheckPages are verified when the database is opened
   unsafe fn show_table_info(&self, tp: &TablePage, s: &mut String) {
     writeln!(s, "table `{}`: record count = {}, record size = {}", tp.name(), tp.count, tp.size).unchecked_unwrap();
     for (idx, ci) in tp.cols().iter().enumerate() {
@@ -43,11 +76,12 @@ impl Db {
         if ci.flags.contains(ColFlags::PRIMARY) { *s += "primary + "; }
         if ci.flags.contains(ColFlags::NOTNULL) { *s += "notnull + "; }
         if ci.flags.contains(ColFlags::UNIQUE) { *s += "unique + "; }
+        if ci.flags.contains(ColFlags::COMPRESSED) { *s += "compressed + "; }
         (s.pop(), s.pop(), s.push('\n'));
       }
       if ci.f_table != !0 {
-        let f_tp = self.pr().get_page::<TablePage>(ci.f_table);
-        let f_ci = f_tp.cols.get_unchecked(ci.f_col as usize);
+        let f_tp = self.page::<TablePage>(ci.f_table);
+        let f_ci = f_tp.cols().get_unchecked(ci.f_col as usize);
         writeln!(s, "    - foreign: `{}.{}`", f_tp.name(), f_ci.name()).unchecked_unwrap();
       }
       if let Some(idx) = ci.idx_name() {
@@ -56,19 +90,27 @@ impl Db {
         s.push('\n');
       }
       if ci.check != !0 {
-        let cp = self.pr().get_page::<CheckPage>(ci.check >> 1);
+        let cp = self.page::<CheckPage>(ci.check >> 1);
         let (count, size) = (cp.count as usize, ci.ty.size() as usize);
         if count != 0 {
           *s += "    - check: ";
           for idx in 0..count {
-            write!(s, "{:?}, ", self.ptr2lit(cp.data.as_ptr().add(idx * size), ci.ty)).unchecked_unwrap();
+            write!(s, "{:?}, ", self.ptr2lit(cp.data.as_ptr().add(idx * size), ci.ty, &Unzipped::default())).unchecked_unwrap();
           }
           (s.pop(), s.pop(), s.push('\n'));
         }
         if (ci.check & 1) == 1 {
-          writeln!(s, "    - default: {:?}", self.ptr2lit(cp.data.as_ptr().add(count * size), ci.ty)).unchecked_unwrap();
+          writeln!(s, "    - default: {:?}", self.ptr2lit(cp.data.as_ptr().add(count * size), ci.ty, &Unzipped::default())).unchecked_unwrap();
         }
       }
     }
+    if tp.indexes != !0 {
+      for info in self.page::<IndexListPage>(tp.indexes).indexes() {
+        let cols = info.cols().iter().map(|&ci_id| tp.cols().get_unchecked(ci_id as usize).name()).collect::<Vec<_>>();
+        *s += "  - index ";
+        if info.flags.contains(IndexFlags::PRIMARY) { *s += "<internal>"; } else { write!(s, "`{}`", info.name()).unchecked_unwrap(); }
+        writeln!(s, ": ({})", cols.join(", ")).unchecked_unwrap();
+      }
+    }
   }
 }
\ No newline at end of file
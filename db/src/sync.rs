@@ -0,0 +1,55 @@
+use std::str::FromStr;
+
+use common::*;
+use crate::Db;
+
+// the journal is always synced before a page is modified, so an unfinished transaction can be undone after a power failure;
+// but without `sync`, committed modifications are written to disk whenever the kernel decides to do so,
+// which is fast, but a power failure may lose committed changes, or even leave the database in an invalid state
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyncMode {
+  // only sync when `Db::sync` is called explicitly (e.g.: by `checkpoint`)
+  Off,
+  // sync after each statement, and each commit
+  Statement,
+  // sync at each commit
+  Transaction,
+}
+
+impl Default for SyncMode {
+  fn default() -> Self { SyncMode::Off }
+}
+
+impl FromStr for SyncMode {
+  type Err = ();
+
+  fn from_str(s: &str) -> std::result::Result<Self, ()> {
+    match s {
+      "off" => Ok(SyncMode::Off),
+      "statement" => Ok(SyncMode::Statement),
+      "transaction" => Ok(SyncMode::Transaction),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Db {
+  pub fn sync_mode(&self) -> SyncMode { self.journal.sync }
+
+  pub fn set_sync_mode(&mut self, mode: SyncMode) { self.journal.sync = mode; }
+
+  // write all modifications (including uncommitted ones, which can be undone by the journal) to disk
+  // modifications outside a transaction are committed
+  pub fn sync<'a>(&mut self) -> Result<'a, ()> {
+    if self.journal.implicit { return self.finish(true); }
+    self.sync_files()
+  }
+
+  pub(crate) fn sync_files<'a>(&mut self) -> Result<'a, ()> {
+    unsafe { self.flush_sums()?; }
+    self.journal.file.sync()?;
+    self.pager.sync(self.pages, self.lob_slots)?;
+    self.sums.store.sync()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,116 @@
+use common::{*, Error::*};
+use physics::*;
+use crate::{Db, bulk::{multi_keys, multi_key_cmp}};
+
+impl Db {
+  // refuse a file written by a newer version of this crate, or using optional structures unknown to this version
+  pub(crate) fn check_version<'a>(dp: &DbPage) -> Result<'a, ()> {
+    if dp.version > FORMAT_VERSION { return Err(UnsupportedVersion { version: dp.version, current: FORMAT_VERSION }); }
+    let unknown = dp.features.bits() & !DbFeatures::all().bits();
+    if unknown != 0 { return Err(UnsupportedFeatures(unknown)); }
+    Ok(())
+  }
+
+  // record that the file uses `feature`, DbPage is only modified the first time
+  pub unsafe fn use_feature(&mut self, feature: DbFeatures) {
+    if !(self.pager.page(0) as *const DbPage).r().features.contains(feature) { self.dp().features |= feature; }
+  }
+
+  // convert a file of an older format version in place
+  // it runs in a transaction, so a crash in the middle leaves the file in its old version
+  pub(crate) fn upgrade<'a>(&mut self) -> Result<'a, ()> {
+    unsafe {
+      // `check_version` refuses newer versions, so the file is either current or of version 0
+      if (self.pager.page(0) as *const DbPage).r().version == FORMAT_VERSION { return Ok(()); }
+      // names are read in the old layout before any change, and written in the current layout after all tables are converted
+      let names = self.tables().into_iter().map(|tp_id| self.old_names(tp_id)).collect::<Result<Vec<_>>>()?;
+      self.transaction(|db| {
+        // the header of TablePage is rearranged for `indexes`, `page_num` and an u16 `col_num`, and `f_col` becomes u16
+        // ColInfos keep their positions, the name fields are rewritten below
+        for tp_id in db.tables() {
+          let tp = db.get_page::<OldTablePage>(tp_id);
+          let col_num = tp.col_num;
+          for ci in tp.cols.get_unchecked_mut(..col_num as usize) {
+            let (f_col, flags, off) = (ci.f_col, ci.flags, ci.off);
+            let ci = (ci as *mut OldColInfo as *mut ColInfo).r();
+            (ci.f_col = f_col as u16, ci.off = off, ci.flags = flags);
+          }
+          let tp = (tp as *mut OldTablePage as *mut TablePage).r();
+          (tp.col_num = col_num as u16, tp.indexes = !0, tp.page_num = 1);
+        }
+        // the name area of the current layout may need more pages, which `set_table_names` allocates
+        for (tp_id, (table, cols)) in db.tables().into_iter().zip(&names) { db.set_table_names(tp_id, table, cols); }
+        for tp_id in db.tables() { db.index_primary(tp_id)?; }
+        db.dp().version = FORMAT_VERSION;
+        Ok::<_, Error>(())
+      })?;
+      // the layout of TablePages was not checked by `verify_catalog` before upgrading
+      self.verify_catalog()
+    }
+  }
+
+  // a primary key on more than one col is not indexed in version 0, its index (see `alloc_primary_index`) is built from the records
+  // a primary key that cannot be indexed is refused, so such a file cannot be upgraded
+  unsafe fn index_primary<'a>(&mut self, tp_id: u32) -> Result<'a, ()> {
+    let tp = self.page::<TablePage>(tp_id);
+    let pks = tp.primary_cols().collect::<Vec<_>>();
+    if pks.len() < 2 { return Ok(()); }
+    Db::primary_index_ck(&pks.iter().map(|ci| ci.ty).collect::<Vec<_>>())?;
+    self.verify_table(tp_id)?;
+    let idx = self.alloc_primary_index(tp_id, &pks.iter().map(|&ci| ci.idx(tp.cols()) as u16).collect::<Vec<_>>());
+    let info = self.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize);
+    let rows = self.record_iter(tp).collect::<Vec<_>>();
+    let (keys, cmp) = (multi_keys(tp, info, &rows).0, multi_key_cmp(tp, info));
+    let mut entries = keys.iter().map(|(key, rid)| (key.as_ptr() as *const u8, *rid)).collect::<Vec<_>>();
+    entries.sort_unstable_by(|l, r| cmp(self, l.0, r.0).then(l.1.cmp(&r.1)));
+    let root = self.bulk_build(info.index, &entries, self.fill_factor());
+    self.get_page::<IndexListPage>(tp.indexes).indexes.get_unchecked_mut(idx as usize).index = root;
+    Ok(())
+  }
+
+  // read all names of the table `tp_id` in the layout of version 0, return (table name, (col name, index name)s)
+  // the fields used by `upgrade` are checked here, since `verify_catalog` doesn't check an older layout
+  unsafe fn old_names<'a>(&self, tp_id: u32) -> Result<'a, (String, Vec<(String, String)>)> {
+    let tp = (self.pager.page(tp_id) as *const OldTablePage).r();
+    let corrupted = || PageCorrupted { page: tp_id, kind: "TablePage" };
+    if tp.col_num as usize > OLD_MAX_COL { return Err(corrupted()); }
+    let name = |len: u8, field: &[u8]| if len as usize <= field.len() { Ok(str_from_parts(field.as_ptr(), len as usize).to_owned()) } else { Err(corrupted()) };
+    let table = name(tp.name_len, &tp.name)?;
+    let mut cols = Vec::with_capacity(tp.col_num as usize);
+    for ci in tp.cols.get_unchecked(..tp.col_num as usize) {
+      let idx = if ci._index != !0 { name(ci.idx_name_len, &ci.idx_name)? } else { String::new() };
+      cols.push((name(ci.name_len, &ci.name)?, idx));
+    }
+    Ok((table, cols))
+  }
+}
+
+// the layout of TablePage in version 0, it takes exactly one page and all names are inline
+// only the fields used by `upgrade` are named
+#[repr(C)]
+struct OldTablePage {
+  _head: [u8; 16],
+  name_len: u8,
+  name: [u8; 46],
+  col_num: u8,
+  cols: [OldColInfo; OLD_MAX_COL],
+}
+
+impl Page for OldTablePage { const KIND: &'static str = "TablePage"; }
+
+const OLD_MAX_COL: usize = 127;
+
+#[repr(C)]
+struct OldColInfo {
+  _ty: ColTy,
+  _index: u32,
+  _check: u32,
+  _f_table: u32,
+  f_col: u8,
+  flags: ColFlags,
+  off: u16,
+  idx_name_len: u8,
+  idx_name: [u8; 15],
+  name_len: u8,
+  name: [u8; 25],
+}
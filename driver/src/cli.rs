@@ -21,8 +21,8 @@ impl Highlighter for SqlHelper {
       let range = start..start + piece.len();
       match token.kind {
         Lt | Le | Ge | Gt | Eq | Ne | LPar | RPar | Add | Sub | Mul | Div | Mod | Comma | Semicolon => {}
-        Null | True | False | FloatLit | IntLit | StrLit => ret.replace_range(range, &piece.green().to_string()),
-        Int | Bool | Char | Varchar | Float | Date => ret.replace_range(range, &piece.cyan().to_string()),
+        Null | True | False | FloatLit | IntLit | StrLit | HexLit => ret.replace_range(range, &piece.green().to_string()),
+        Int | Bool | Char | Varchar | Text | Blob | Float | Date => ret.replace_range(range, &piece.cyan().to_string()),
         Sum | Avg | Min | Max | Count => ret.replace_range(range, &piece.yellow().to_string()),
         Id1 | Dot => ret.replace_range(range, &piece.purple().to_string()),
         _Err | _Eof => break ret.into(),
@@ -58,6 +58,7 @@ fn main() {
           const OUTPUT: &str = ".output";
           const READ: &str = ".read";
           const COLOR: &str = ".color";
+          const SYNC: &str = ".sync";
           match cmd {
             OUTPUT => output = words.next().map(|x| x.to_owned()),
             READ => if let Some(file) = words.next() {
@@ -68,6 +69,9 @@ fn main() {
             COLOR => if let Some(color) = words.next().and_then(|x| x.parse().ok()) {
               rl.set_helper(if color { Some(SqlHelper) } else { None });
             } else { eprintln!("Usage: {} [true|false]", COLOR); }
+            SYNC => if let Some(mode) = words.next().and_then(|x| x.parse().ok()) {
+              e.set_sync_mode(mode);
+            } else { eprintln!("Usage: {} [off|statement|transaction]", SYNC); }
             _ => eprintln!("Unknown command: {}", cmd),
           }
         } else {
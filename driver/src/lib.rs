@@ -1,16 +1,24 @@
-use std::{borrow::Cow, fs, path::Path};
+use std::{borrow::Cow, fs, io, path::Path};
 use typed_arena::Arena;
 
 use common::{*, Error::*};
 use syntax::ast::*;
-use db::{Db, show::show_db};
+use db::{Db, SyncMode, show::show_db};
 use query::SelectResult;
 
+// `self.1` is applied to all databases opened by `use`, `self.2` is the path of the database in use (empty for in-memory)
 #[derive(Default)]
-pub struct Eval(Option<Db>);
+pub struct Eval(Option<Db>, SyncMode, String);
+
+// one problem per line, followed by the number of problems
+fn report(problems: Vec<String>) -> String {
+  let mut s = String::new();
+  for p in &problems { (s += p, s.push('\n')); }
+  s + &format!("{} problem(s) found", problems.len())
+}
 
 impl Eval {
-  pub fn exec_all<'a>(&mut self, code: &'a str, alloc: &'a Arena<u8>, input_handler: impl Fn(&Stmt), result_handler: impl Fn(&str)) -> ModifyResult<'a, ()> {
+  pub fn exec_all<'a>(&mut self, code: &'a str, alloc: &'a Arena<u8>, input_handler: impl Fn(&Stmt), result_handler: impl Fn(&str)) -> Result<'a, ()> {
     for s in &syntax::work(code, alloc)? {
       input_handler(s);
       result_handler(&self.exec(s)?);
@@ -18,42 +26,97 @@ impl Eval {
     Ok(())
   }
 
-  pub fn exec<'a>(&mut self, sql: &Stmt<'a>) -> ModifyResult<'a, Cow<str>> {
-    fn fmt<'a>(n: u32) -> Cow<'a, str> { Cow::Owned(format!("{} column(s) affected", n)) }
+  pub fn exec<'a>(&mut self, sql: &Stmt<'a>) -> Result<'a, Cow<str>> {
     use Stmt::*;
     Ok(match sql {
-      Insert(i) => fmt(query::insert(i, self.db()?)?),
-      Delete(d) => fmt(query::delete(d, self.db()?)?),
       Select(s) => query::select(s, self.db()?)?.csv().into(),
-      Update(u) => fmt(query::update(u, self.db()?)?),
-      &CreateDb(path) => (Db::create(path), "".into()).1,
-      &DropDb(path) => (fs::remove_file(path)?, fs::remove_file(AsRef::<Path>::as_ref(path).with_extension(LOB_SUFFIX))?, "".into()).2,
+      // an in-memory database has no name to `use` later, so it is used once created
+      &CreateDb(IN_MEMORY) => {
+        let mut db = Db::in_memory();
+        db.set_sync_mode(self.1);
+        (self.0 = Some(db), self.2.clear(), "".into()).2
+      }
+      &CreateDb(path) => (Db::create(path)?, "".into()).1,
+      &DropDb(path) => {
+        let path = AsRef::<Path>::as_ref(path);
+        (fs::remove_file(path)?, fs::remove_file(path.with_extension(LOB_SUFFIX))?);
+        // these files may not exist if the database is created by an older version
+        for &suffix in &[JOURNAL_SUFFIX, SUM_SUFFIX] {
+          if let Err(e) = fs::remove_file(path.with_extension(suffix)) {
+            if e.kind() != io::ErrorKind::NotFound { return Err(e.into()); }
+          }
+        }
+        "".into()
+      }
+      // the database in use is locked by this session, so it cannot be opened again
       &ShowDb(path) => {
         let mut s = String::new();
-        (show_db(path, &mut s)?, s.into()).1
+        match &self.0 { Some(db) if path == self.2 => db.show_db(&mut s), _ => show_db(path, &mut s)? }
+        s.into()
       }
       ShowDbs => {
         let mut s = String::new();
+        let cur = Path::new(".").join(&self.2);
         for entry in fs::read_dir(".")? {
-          // `show_db` may fail because not all files are db format, just ignore these files
-          let _ = show_db(entry?.path(), &mut s);
+          match (&self.0, entry?.path()) {
+            (Some(db), path) if path == cur => db.show_db(&mut s),
+            // `show_db` may fail because not all files are db format, just ignore these files
+            (_, path) => { let _ = show_db(path, &mut s); }
+          }
         }
         s.into()
       }
-      &UseDb(path) => (self.0 = Some(Db::open(path)?), "".into()).1,
-      CreateTable(c) => (self.db()?.create_table(c)?, "".into()).1,
-      &DropTable(table) => (self.db()?.drop_table(table)?, "".into()).1,
+      &UseDb(path) => {
+        // release the lock before opening it again
+        if path == self.2 { self.0 = None; }
+        let mut db = Db::open(path)?;
+        db.set_sync_mode(self.1);
+        (self.0 = Some(db), self.2 = path.to_owned(), "".into()).2
+      }
       &ShowTable(table) => self.db()?.show_table(table)?.into(),
-      ShowTables => self.db()?.show_tables().into(),
-      CreateIndex(c) => (index::create_index(self.db()?, c)?, "".into()).1,
-      &DropIndex { index, table } => (self.db()?.drop_index(index, table)?, "".into()).1,
-      &Rename { old, new } => (self.db()?.rename_table(old, new)?, "".into()).1,
-      AddForeign(a) => (index::add_foreign(self.db()?, a)?, "".into()).1,
-      &DropForeign { table, col } => (self.db()?.drop_foreign(table, col)?, "".into()).1,
-      AddPrimary { table, cols } => (index::add_primary(self.db()?, table, cols)?, "".into()).1,
-      DropPrimary { table, cols } => (index::drop_primary(self.db()?, table, cols)?, "".into()).1,
-      AddCol { table, col } => (index::add_col(self.db()?, table, col)?, "".into()).1,
-      &DropCol { table, col } => (index::drop_col(self.db()?, table, col)?, "".into()).1,
+      ShowTables => self.db()?.show_tables()?.into(),
+      ShowLob => self.db()?.show_lob().into(),
+      Begin => (self.db()?.begin()?, "".into()).1,
+      Commit => (self.db()?.commit()?, "".into()).1,
+      Rollback => (self.db()?.rollback()?, "".into()).1,
+      Checkpoint => (self.db()?.sync()?, "".into()).1,
+      CheckDb(false) => report(self.db()?.check()).into(),
+      &Backup(path) => (self.db()?.backup_to(path)?, "".into()).1,
+      Dump => self.db()?.dump()?.into(),
+      CopyTo(c) => format!("{} row(s) written", query::copy_to(c, self.db()?)?).into(),
+      // a failed statement has no effect (`RolledBack` if some rows were changed before the error); outside `begin` and `commit`,
+      // each statement is a transaction, so a crash in the middle of it will not leave the database in an invalid state
+      _ => self.db()?.atomic(|db| Eval::modify(db, sql))?,
+    })
+  }
+
+  fn modify<'a>(db: &mut Db, sql: &Stmt<'a>) -> Result<'a, Cow<'static, str>> {
+    fn fmt<'a>(n: u32) -> Cow<'a, str> { Cow::Owned(format!("{} column(s) affected", n)) }
+    use Stmt::*;
+    Ok(match sql {
+      Insert(i) => fmt(query::insert(i, db)?),
+      Delete(d) => fmt(query::delete(d, db)?),
+      Update(u) => fmt(query::update(u, db)?),
+      CopyFrom(c) => fmt(query::copy_from(c, db)?),
+      CreateTable(c) => (db.create_table(c)?, "".into()).1,
+      &DropTable(table) => (db.drop_table(table)?, "".into()).1,
+      CreateIndex(c) => (index::create_index(db, c)?, "".into()).1,
+      &DropIndex { index, table } => (db.drop_index(index, table)?, "".into()).1,
+      &Rename { old, new } => (db.rename_table(old, new)?, "".into()).1,
+      AddForeign(a) => (index::add_foreign(db, a)?, "".into()).1,
+      &DropForeign { table, col } => (db.drop_foreign(table, col)?, "".into()).1,
+      AddPrimary { table, cols } => (index::add_primary(db, table, cols)?, "".into()).1,
+      DropPrimary { table, cols } => (index::drop_primary(db, table, cols)?, "".into()).1,
+      AddCol { table, col } => (index::add_col(db, table, col)?, "".into()).1,
+      &DropCol { table, col } => (index::drop_col(db, table, col)?, "".into()).1,
+      &Vacuum(table) => (index::vacuum(db, table)?, "".into()).1,
+      CompactLob => (db.compact_lob()?, "".into()).1,
+      // report the problems found before repairing
+      CheckDb(true) => {
+        let s = report(db.check());
+        (index::repair(db)?, s.into()).1
+      }
+      _ => unreachable!(),
     })
   }
 
@@ -62,4 +125,9 @@ impl Eval {
   }
 
   pub fn db<'a>(&mut self) -> Result<'a, &mut Db> { self.0.as_mut().ok_or(NoDbInUse) }
+
+  pub fn set_sync_mode(&mut self, mode: SyncMode) {
+    self.1 = mode;
+    if let Some(db) = &mut self.0 { db.set_sync_mode(mode); }
+  }
 }
\ No newline at end of file
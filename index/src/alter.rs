@@ -1,29 +1,41 @@
-use unchecked_unwrap::UncheckedUnwrap;
+use std::cmp::Ordering;
 
 use common::{*, Error::*, BareTy::*};
-use db::{Db, is_null, hash_pks};
+use db::{Db, Unzipped, is_null};
 use syntax::ast::*;
 use physics::*;
-use crate::{Index, handle_all};
+use crate::{Index, MultiIndex, multi_index_num, primary_multi_index, cmp::Cmp, handle_all};
 
 // some alter operation cannot be put in `db` crate, because the need some index operation, and `index` crate depends on `db` crate
 
 // though we specify an index with a length = 0 means an internal index, it is not checked here
 // (you can create such an index though manually constructing `CreateIndex` struct, but not through parser)
 // mainly because even if you did that, there is no serious consequence
+// an index on more than one col is a composite index, which is always created even if there is an index on the same cols
 pub fn create_index<'a>(db: &mut Db, c: &CreateIndex<'a>) -> Result<'a, ()> {
   unsafe {
-    for &tp_id in db.dp().tables() {
-      for ci in db.get_page::<TablePage>(tp_id).cols() {
-        if ci.idx_name().filter(|&x| x == c.index).is_some() { return Err(DupIndex(c.index)); }
-      }
-    }
+    if db.index_owner(c.index).is_some() || db.multi_index_owner(c.index).is_some() { return Err(DupIndex(c.index)); }
     let (tp_id, tp) = db.get_tp(c.table)?;
-    let ci = tp.get_ci(c.col)?;
-    if ci.ty.is_varchar() { return Err(UnsupportedVarcharOp(c.col)); }
+    if c.cols.len() > MAX_INDEX_COL { return Err(IndexColTooMany(c.cols.len())); }
+    let mut cols = Vec::with_capacity(c.cols.len());
+    for (idx, &col) in c.cols.iter().enumerate() {
+      if c.cols.iter().take(idx).any(|&x| x == col) { return Err(DupCol(col)); }
+      let ci = tp.get_ci(col)?;
+      if ci.ty.is_varchar() { return Err(UnsupportedVarcharOp(col)); }
+      cols.push(ci.idx(tp.cols()) as u16);
+    }
+    if cols.len() > 1 {
+      let idx = db.alloc_multi_index(tp_id, c.index, &cols)?;
+      insert_all_multi(db, tp_id, tp, idx);
+      return Ok(());
+    }
+    let ci_id = *cols.get_unchecked(0) as usize;
+    let ci = tp.cols_mut().get_unchecked_mut(ci_id);
     if ci.index == !0 {
-      db.alloc_index(ci, c.index)?;
-      insert_all(db, tp_id, tp, ci);
+      // the TablePage may be moved for the index name
+      let tp_id = db.alloc_index(tp_id, ci, c.index);
+      let tp = db.get_page::<TablePage>(tp_id);
+      insert_all(db, tp_id, tp, tp.cols().get_unchecked(ci_id));
     }
     Ok(())
   }
@@ -33,11 +45,11 @@ pub fn add_foreign<'a>(db: &mut Db, a: &AddForeign<'a>) -> Result<'a, ()> {
   unsafe {
     let (tp_id, tp) = db.get_tp(a.table)?;
     let ci = tp.get_ci(a.col)?;
-    let ci_id = ci.idx(&tp.cols);
+    let ci_id = ci.idx(tp.cols());
     if ci.f_table != !0 { return Err(DupConstraint(a.col)); }
     let (f_tp_id, f_tp) = db.get_tp(a.f_table)?;
     let f_ci = f_tp.get_ci(a.f_col)?;
-    let f_ci_id = f_ci.idx(&f_tp.cols);
+    let f_ci_id = f_ci.idx(f_tp.cols());
     if !f_ci.unique(f_tp.primary_cols().count()) { return Err(ForeignOnNotUnique(a.f_col)); }
     debug_assert!(!f_ci.ty.is_varchar());
     if f_ci.ty != ci.ty { return Err(IncompatibleForeignTy { foreign: f_ci.ty, own: ci.ty }); }
@@ -47,16 +59,18 @@ pub fn add_foreign<'a>(db: &mut Db, a: &AddForeign<'a>) -> Result<'a, ()> {
         for (data, _) in db.record_iter(tp) {
           let ptr = data.add(ci.off as usize);
           if !is_null(data, ci_id) && !index.contains(ptr) {
-            return Err(PutNonexistentForeign { col: a.col, val: db.ptr2lit(ptr, ci.ty) });
+            // `ci` has a foreign link, so it is not a varchar and `val` doesn't refer to the temporary `Unzipped`
+            return Err(PutNonexistentForeign { col: a.col, val: db.ptr2lit(ptr, ci.ty, &Unzipped::default()) });
           }
         }
       }};
     }
     handle_all!(ci.ty.fix_ty().ty, handle);
     // now no error can occur
-    (ci.f_table = f_tp_id, ci.f_col = f_ci_id as u8);
+    (ci.f_table = f_tp_id, ci.f_col = f_ci_id as u16);
+    db.add_foreign_link(tp_id, ci_id as u16, f_tp_id, f_ci_id as u16);
     if ci.index == !0 {
-      db.alloc_index(ci, "").unchecked_unwrap();
+      db.alloc_index(tp_id, ci, "");
       insert_all(db, tp_id, tp, ci);
     }
     Ok(())
@@ -78,12 +92,13 @@ pub fn add_primary<'a>(db: &mut Db, table: &'a str, cols: &[&'a str]) -> Result<
     }
     for (data, _) in db.record_iter(tp) {
       for &ci in pks.get_unchecked(old_len..) {
-        if is_null(data, ci.idx(&tp.cols)) { return Err(PutNullOnNotNull); }
+        if is_null(data, ci.idx(tp.cols())) { return Err(PutNullOnNotNull); }
       }
     }
     if old_len == 0 && pks.len() != 0 { check_dup(db, tp, &pks)?; }
+    if pks.len() > 1 { check_primary_index(db, tp_id, &pks)?; }
     for (_, _, ci_id) in db.foreign_links_to(tp_id) {
-      let ci = tp.cols.get_unchecked(ci_id as usize);
+      let ci = tp.cols().get_unchecked(ci_id as usize);
       if !ci.unique(pks.len()) { return Err(ForeignOnNotUnique(ci.name())); }
     }
     // now no error can occur
@@ -107,8 +122,9 @@ pub fn drop_primary<'a>(db: &mut Db, table: &'a str, cols: &[&'a str]) -> Result
       } else { return Err(NoSuchPrimary(col)); }
     }
     if new_len != 0 { check_dup(db, tp, pks.get_unchecked(..new_len))?; }
+    if new_len > 1 { check_primary_index(db, tp_id, pks.get_unchecked(..new_len))?; }
     for (_, _, ci_id) in db.foreign_links_to(tp_id) {
-      let ci = tp.cols.get_unchecked(ci_id as usize);
+      let ci = tp.cols().get_unchecked(ci_id as usize);
       if pks.get_unchecked(new_len..).iter().any(|&x| x.p() == ci.p()) &&
         !ci.flags.contains(ColFlags::UNIQUE) { return Err(ForeignOnNotUnique(ci.name())); }
     }
@@ -122,8 +138,6 @@ pub fn drop_primary<'a>(db: &mut Db, table: &'a str, cols: &[&'a str]) -> Result
 pub fn add_col<'a>(db: &mut Db, table: &'a str, col: &ColDecl<'a>) -> Result<'a, ()> {
   unsafe {
     let (tp_id, tp) = db.get_tp(table)?;
-    if tp.col_num == MAX_COL as u8 { return Err(ColTooMany(tp.col_num as usize + 1)); }
-    if col.col.len() > MAX_COL_NAME { return Err(ColNameTooLong(col.col)); }
     if tp.get_ci(col.col).is_ok() { return Err(DupCol(col.col)); }
     let dft = col.dft.unwrap_or(CLit::new(Lit::Null));
     let dft = if !dft.is_null() {
@@ -138,23 +152,32 @@ pub fn add_col<'a>(db: &mut Db, table: &'a str, col: &ColDecl<'a>) -> Result<'a,
     if size > MAX_DATA_BYTE { return Err(ColSizeTooBig(size)); }
     // now no error can occur
     let bs_size = ((tp.col_num as usize + 31) / 32 * 4, ((tp.col_num + 1) as usize + 31) / 32 * 4);
+    // the new ColInfo may overwrite the name area, and the TablePage may be moved for it, so names are copied out first
+    let (table_name, mut names) = tp.names();
+    names.push((col.col.to_owned(), String::new()));
+    let tp_id = db.reserve_tp(tp_id, TablePage::page_num_for(&table_name, &names) as u32);
+    let tp = db.get_page::<TablePage>(tp_id);
 
-    let iter = db.record_iter(tp);
-    tp.cols.get_unchecked_mut(tp.col_num as usize).init(col.ty, 0, col.col, col.notnull); // `off` will be overwritten in `calc_size`
+    let olds = db.record_iter(tp).map(|(data, _)| data).collect::<Vec<_>>();
     tp.col_num += 1;
+    let ci = tp.cols_mut().get_unchecked_mut(tp.col_num as usize - 1);
+    ci.init(col.ty, 0, col.notnull); // `off` will be overwritten in `calc_size`
+    ci.flags.set(ColFlags::COMPRESSED, col.compressed);
+    if tp.set_names(&table_name, &names) { db.use_feature(DbFeatures::LONG_NAME); }
+    if col.ty.is_long() { db.use_feature(DbFeatures::LOB_TYPES); }
     calc_size(tp);
 
     let (size, cap, col_num) = (tp.size as usize, tp.cap, tp.col_num as usize);
     if let Some(dft) = dft.as_ref() {
       let (cp_id, cp) = db.alloc_page::<CheckPage>();
-      tp.cols.get_unchecked_mut(col_num - 1).check = (cp_id << 1) | 1;
+      tp.cols_mut().get_unchecked_mut(col_num - 1).check = (cp_id << 1) | 1;
       cp.count = 0;
       cp.data.as_mut_ptr().copy_from_nonoverlapping(dft.ptr, dft.size);
     }
-    let last_off = tp.cols.get_unchecked_mut(col_num - 1).off as usize;
+    let last_off = tp.cols().get_unchecked(col_num - 1).off as usize;
     let (mut dp_id, mut dp) = db.alloc_page::<DataPage>();
     dp.init(!0);
-    for (old, _) in iter {
+    for old in olds {
       let new = alloc_slot(db, &mut dp_id, &mut dp, cap, size);
       new.copy_from_nonoverlapping(old, bs_size.0);
       new.add(bs_size.1).copy_from_nonoverlapping(old.add(bs_size.0), last_off - bs_size.1);
@@ -174,39 +197,54 @@ pub fn drop_col<'a>(db: &mut Db, table: &'a str, col: &'a str) -> Result<'a, ()>
     let (tp_id, tp) = db.get_tp(table)?;
     let col_num = tp.col_num as usize;
     let ci = tp.get_ci(col)?;
-    let ci_id = ci.idx(&tp.cols) as usize;
+    let ci_id = ci.idx(tp.cols()) as usize;
     if col_num == 1 { return Err(ColTooFew); }
-    if db.foreign_links_to(tp_id).any(|x| x.2 == ci_id as u8) { return Err(ModifyTableWithForeignLink(table)); }
-    if ci.flags.contains(ColFlags::PRIMARY) {
-      let pks = tp.primary_cols().filter(|&x| x.p() != ci.p()).collect::<Vec<_>>();
-      if !pks.is_empty() { check_dup(db, tp, &pks)?; }
-    }
+    if db.foreign_links_to(tp_id).any(|x| x.2 == ci_id as u16) { return Err(ModifyTableWithForeignLink(table)); }
+    let pks = tp.primary_cols().filter(|&x| x.p() != ci.p()).collect::<Vec<_>>();
+    if ci.flags.contains(ColFlags::PRIMARY) && !pks.is_empty() { check_dup(db, tp, &pks)?; }
+    if pks.len() > 1 { check_primary_index(db, tp_id, &pks)?; }
     // now no error can occur
     let bs_size = ((col_num + 31) / 32 * 4, (col_num - 1 + 31) / 32 * 4);
     let l_size = ci.off as usize - bs_size.0;
     // the padding in right side may change, so need to copy data one by one; r_size_off is Vec<(size, old off, new off)>
-    let mut r_size_off = tp.cols.get_unchecked(ci_id + 1..col_num).iter().map(|ci| (ci.ty.size(), ci.off, 0u16)).collect::<Vec<_>>();
+    let mut r_size_off = tp.cols().get_unchecked(ci_id + 1..col_num).iter().map(|ci| (ci.ty.size(), ci.off, 0u16)).collect::<Vec<_>>();
 
     if ci.index != !0 { db.dealloc_index(ci.index); }
     if ci.check != !0 { db.dealloc_page(ci.check >> 1); }
+    // composite indexes on this col are dropped, and the col ids after it are changed in others
+    for idx in (0..multi_index_num(db, tp_id)).rev() {
+      if db.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize).cols().contains(&(ci_id as u16)) { db.dealloc_multi_index(tp_id, idx); }
+    }
+    if tp.indexes != !0 {
+      for info in db.get_page::<IndexListPage>(tp.indexes).indexes() {
+        for x in info.pr().cols.get_unchecked_mut(..info.col_num as usize) {
+          if *x as usize > ci_id { *x -= 1; }
+        }
+      }
+    }
     if ci.ty.is_varchar() {
-      for (data, _) in db.record_iter(tp) {
-        if !is_null(data, ci_id as u32) { db.free_varchar(data.add(ci.off as usize)); }
+      let rows = db.record_iter(tp).map(|(data, _)| data).collect::<Vec<_>>();
+      for data in rows {
+        if !is_null(data, ci_id as u32) { db.free_varchar(data.add(ci.off as usize), ci.ty); }
       }
     }
 
-    let iter = db.record_iter(tp); // it will iterate over old data because necessary information is copied into iter
+    let (table_name, mut names) = tp.names();
+    names.remove(ci_id);
+    let olds = db.record_iter(tp).map(|(data, _)| data).collect::<Vec<_>>(); // the old data pages are kept until `reset_data`
     tp.cols.as_mut_ptr().add(ci_id).copy_from(tp.cols.as_mut_ptr().add(ci_id + 1), col_num - ci_id - 1);
     tp.col_num -= 1;
+    tp.set_names(&table_name, &names); // dropping a col never needs more pages
+    db.invalidate_catalog(); // col ids after `ci_id` are changed
     calc_size(tp);
 
     let (size, cap, col_num) = (tp.size as usize, tp.cap, tp.col_num as usize);
     for idx in ci_id..col_num {
-      r_size_off.get_unchecked_mut(idx - ci_id).2 = tp.cols.get_unchecked(idx).off;
+      r_size_off.get_unchecked_mut(idx - ci_id).2 = tp.cols().get_unchecked(idx).off;
     }
     let (mut dp_id, mut dp) = db.alloc_page::<DataPage>();
     dp.init(!0);
-    for (old, _) in iter {
+    for old in olds {
       let new = alloc_slot(db, &mut dp_id, &mut dp, cap, size);
       (new as *mut u32).write_bytes(0, bs_size.1);
       for i in 0..ci_id {
@@ -226,14 +264,83 @@ pub fn drop_col<'a>(db: &mut Db, table: &'a str, col: &'a str) -> Result<'a, ()>
   }
 }
 
+// rewrite the data pages of `table` (or all tables if it is None) densely, rebuild their indexes, and truncate trailing free pages
+// if it is None, the lob file is also compacted (see `Db::compact_lob`)
+pub fn vacuum<'a>(db: &mut Db, table: Option<&'a str>) -> Result<'a, ()> {
+  unsafe {
+    let tables = if let Some(table) = table { vec![db.get_tp(table)?.0] } else {
+      let tables = db.tables();
+      for &tp_id in &tables { db.verify_table(tp_id)?; }
+      tables
+    };
+    // now no error can occur, except IO error in `shrink` and `compact_lob`
+    for &tp_id in &tables {
+      let tp = db.get_page::<TablePage>(tp_id);
+      db.compact_data(tp);
+      for ci in tp.cols() {
+        if ci.index != !0 { db.dealloc_index(ci.index); }
+      }
+      for idx in 0..multi_index_num(db, tp_id) { db.dealloc_index(db.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize).index); }
+    }
+    // so that the rebuilt indexes use the pages with smaller id
+    db.shrink()?;
+    for &tp_id in &tables {
+      let tp = db.get_page::<TablePage>(tp_id);
+      for ci in tp.cols() {
+        if ci.index != !0 {
+          let (id, ip) = db.alloc_page::<IndexPage>();
+          ci.pr().index = id;
+          ip.init(true, ci.ty.size());
+          insert_all(db, tp_id, tp, ci);
+        }
+      }
+      for idx in 0..multi_index_num(db, tp_id) { realloc_multi(db, tp_id, tp, idx); }
+    }
+    // the lob file is shared by all tables
+    if table.is_none() { db.compact_lob()?; }
+    Ok(())
+  }
+}
+
+// rebuild all indexes, and all free lists from the data pages (see `Db::rebuild_free_lists`)
+// problems in the records themselves (e.g., a record referring to a nonexistent foreign value) are not fixed
+pub fn repair<'a>(db: &mut Db) -> Result<'a, ()> {
+  unsafe {
+    let mut indexes = Vec::new();
+    for tp_id in db.tables() {
+      for ci in db.get_page::<TablePage>(tp_id).cols() {
+        if ci.index != !0 { (indexes.push((tp_id, ci)), ci.pr().index = !0); }
+      }
+      for idx in 0..multi_index_num(db, tp_id) {
+        db.get_page::<IndexListPage>(db.page::<TablePage>(tp_id).indexes).indexes.get_unchecked_mut(idx as usize).index = !0;
+      }
+    }
+    db.rebuild_free_lists();
+    for (tp_id, ci) in indexes {
+      let (id, ip) = db.alloc_page::<IndexPage>();
+      ci.pr().index = id;
+      ip.init(true, ci.ty.size());
+      let tp = db.get_page::<TablePage>(tp_id);
+      insert_all(db, tp_id, tp, ci);
+    }
+    // `rebuild_free_lists` may drop an invalid IndexListPage
+    for tp_id in db.tables() {
+      let tp = db.get_page::<TablePage>(tp_id);
+      for idx in 0..multi_index_num(db, tp_id) { realloc_multi(db, tp_id, tp, idx); }
+    }
+    Ok(())
+  }
+}
+
 unsafe fn calc_size(tp: &mut TablePage) {
-  let mut size = (tp.col_num as u16 + 31) / 32 * 4;
+  let mut size = (tp.col_num + 31) / 32 * 4;
   for ci in tp.cols() {
     if ci.ty.align4() { size = (size + 3) & !3; }
     ci.pr().off = size;
     size += ci.ty.size();
   }
-  size = (size + 3) & !3;
+  // the same as `Db::create_table`, a page can't have more than MAX_SLOT slots
+  size = ((size + 3) & !3).max(MIN_SLOT_SIZE as u16);
   (tp.size = size, tp.cap = MAX_DATA_BYTE as u16 / size);
 }
 
@@ -261,38 +368,88 @@ unsafe fn reset_data(db: &mut Db, tp_id: u32, tp: &mut TablePage, dp_id: u32, dp
       insert_all(db, tp_id, tp, ci);
     }
   }
+  for idx in 0..multi_index_num(db, tp_id) {
+    db.dealloc_index(db.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize).index);
+    realloc_multi(db, tp_id, tp, idx);
+  }
 }
 
 
+// the primary key on more than one col should have passed `check_primary_index`
 unsafe fn index_unique_primary(db: &mut Db, tp_id: u32, tp: &TablePage) {
   for (idx, ci) in tp.cols().iter().enumerate() {
     if ci.flags.contains(ColFlags::PRIMARY) {
       if ci.index == !0 && !tp.cols().get_unchecked(idx + 1..).iter().any(|ci| ci.flags.contains(ColFlags::PRIMARY)) {
-        db.alloc_index(ci.pr(), "").unchecked_unwrap();
+        db.alloc_index(tp_id, ci.pr(), "");
         insert_all(db, tp_id, tp, ci);
       }
       break;
     }
   }
+  // the composite index of the primary key is rebuilt when the primary cols change
+  let pks = tp.primary_cols().map(|ci| ci.idx(tp.cols()) as u16).collect::<Vec<_>>();
+  if let Some(idx) = primary_multi_index(db, tp_id) {
+    if pks.len() > 1 && db.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize).cols() == &pks[..] { return; }
+    db.dealloc_multi_index(tp_id, idx);
+  }
+  if pks.len() > 1 {
+    let idx = db.alloc_primary_index(tp_id, &pks);
+    insert_all_multi(db, tp_id, tp, idx);
+  }
 }
 
+// check whether the primary key `pks` (more than one col) can be backed by a composite index, see `Db::alloc_primary_index`
+unsafe fn check_primary_index<'a>(db: &Db, tp_id: u32, pks: &[&ColInfo]) -> Result<'a, ()> {
+  let cols = db.page::<TablePage>(tp_id).cols();
+  let mut pks = pks.to_vec();
+  pks.sort_by_key(|&ci| ci.idx(cols)); // the key is in the order of cols
+  Db::primary_index_ck(&pks.iter().map(|ci| ci.ty).collect::<Vec<_>>())?;
+  // the old index is replaced, so it only fails when there is no old index
+  if primary_multi_index(db, tp_id).is_none() && multi_index_num(db, tp_id) as usize == MAX_MULTI_INDEX { return Err(IndexTooMany(MAX_MULTI_INDEX + 1)); }
+  Ok(())
+}
+
+// the index of `ci` must be empty, it is built by `Index::bulk_build` with the fill factor of `db`
 unsafe fn insert_all(db: &mut Db, tp_id: u32, tp: &TablePage, ci: &ColInfo) {
-  let ci_id = ci.idx(&tp.cols);
-  macro_rules! handle {
-    ($ty: ident) => {{
-      let mut index = Index::<{ $ty }>::new(db, tp_id, ci_id);
-      for (data, rid) in db.record_iter(tp) {
-        if !is_null(data, ci_id) { index.insert(data.add(ci.off as usize), rid); }
-      }
-    }};
-  }
+  let ci_id = ci.idx(tp.cols());
+  let mut entries = db.record_iter(tp).filter(|&(data, _)| !is_null(data, ci_id))
+    .map(|(data, rid)| (data.add(ci.off as usize) as *const u8, rid)).collect::<Vec<_>>();
+  let fill_factor = db.fill_factor();
+  macro_rules! handle { ($ty: ident) => {{ Index::<{ $ty }>::new(db, tp_id, ci_id).bulk_build(&mut entries, fill_factor); }}; }
   handle_all!(ci.ty.fix_ty().ty, handle);
 }
 
+// like `insert_all`, but for the composite index `idx`
+unsafe fn insert_all_multi(db: &mut Db, tp_id: u32, tp: &TablePage, idx: u32) {
+  let fill_factor = db.fill_factor();
+  let mut index = MultiIndex::new(db, tp_id, idx);
+  let keys = db.record_iter(tp).filter_map(|(data, rid)| index.record_key(data).map(|key| (key, rid))).collect::<Vec<_>>();
+  let mut entries = keys.iter().map(|(key, rid)| (key.ptr as *const u8, *rid)).collect::<Vec<_>>();
+  index.bulk_build(&mut entries, fill_factor);
+}
+
+// allocate a new root for the composite index `idx` (its old pages should have been deallocated), and insert all records
+unsafe fn realloc_multi(db: &mut Db, tp_id: u32, tp: &TablePage, idx: u32) {
+  let (id, ip) = db.alloc_page::<IndexPage>();
+  let info = db.get_page::<IndexListPage>(tp.indexes).indexes.get_unchecked_mut(idx as usize);
+  info.index = id;
+  ip.init(true, info.key_layout(tp).1 + 1);
+  insert_all_multi(db, tp_id, tp, idx);
+}
+
+// the records are sorted by `pks` (which are not null), so that duplicate keys are adjacent
 unsafe fn check_dup<'a>(db: &mut Db, tp: &TablePage, pks: &[&ColInfo]) -> Result<'a, ()> {
-  let mut pk_set = HashSet::default();
-  for (data, _) in db.record_iter(tp) {
-    if !pk_set.insert(hash_pks(data, &pks)) { return Err(PutDupOnPrimary); }
-  }
+  let cmp = |l: *const u8, r: *const u8| {
+    for ci in pks {
+      let (l, r) = (l.add(ci.off as usize), r.add(ci.off as usize));
+      macro_rules! handle { ($ty: ident) => { Cmp::<{ $ty }>::cmp(l, r) }; }
+      let ord = handle_all!(ci.ty.fix_ty().ty, handle);
+      if ord != Ordering::Equal { return ord; }
+    }
+    Ordering::Equal
+  };
+  let mut rows = db.record_iter(tp).map(|(data, _)| data as *const u8).collect::<Vec<_>>();
+  rows.sort_unstable_by(|&l, &r| cmp(l, r));
+  if rows.windows(2).any(|w| cmp(*w.get_unchecked(0), *w.get_unchecked(1)) == Ordering::Equal) { return Err(PutDupOnPrimary); }
   Ok(())
 }
\ No newline at end of file
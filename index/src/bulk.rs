@@ -0,0 +1,12 @@
+use physics::*;
+use crate::{TreeMut, cmp::KeyCmp};
+
+impl<C: KeyCmp> TreeMut<C> {
+  // sort `entries` (pointers to the data part of the keys and their rids) and build the tree from them by `Db::bulk_build`,
+  // the tree must be empty; `fill_factor` is in percentage
+  pub unsafe fn bulk_build(&mut self, entries: &mut [(*const u8, Rid)], fill_factor: u8) {
+    entries.sort_unstable_by(|l, r| self.cmp.cmp_data(l.0, r.0).then(l.1.cmp(&r.1)));
+    let root = self.db().bulk_build(self.root(), entries, fill_factor);
+    self.make_root(root);
+  }
+}
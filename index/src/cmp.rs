@@ -1,14 +1,24 @@
 use std::cmp::Ordering;
 use chrono::NaiveDate;
 
-use common::*;
+use common::{*, BareTy::*};
 use physics::*;
+use db::is_null;
+use crate::handle_all;
+
+// compare the keys in a B+ tree, the data part is compared by `cmp_data`, and ties are broken by the rid
+pub trait KeyCmp {
+  unsafe fn cmp_data(&self, l: *const u8, r: *const u8) -> Ordering;
+
+  unsafe fn cmp_full(&self, l: *const u8, r: *const u8, rid_off: usize) -> Ordering {
+    self.cmp_data(l, r).then((*(l.add(rid_off) as *const Rid)).cmp(&*(r.add(rid_off) as *const Rid)))
+  }
+}
 
 pub struct Cmp<const T: BareTy>;
 
 impl<const T: BareTy> Cmp<{ T }> {
   pub unsafe fn cmp(l: *const u8, r: *const u8) -> Ordering {
-    use BareTy::*;
     match T { // should be optimized out
       Bool => (*(l as *const bool)).cmp(&*(r as *const bool)),
       Int => (*(l as *const i32)).cmp(&*(r as *const i32)),
@@ -17,25 +27,85 @@ impl<const T: BareTy> Cmp<{ T }> {
       Char => str_from_db(l).cmp(str_from_db(r)),
     }
   }
+}
+
+impl<const T: BareTy> KeyCmp for Cmp<{ T }> {
+  unsafe fn cmp_data(&self, l: *const u8, r: *const u8) -> Ordering { Self::cmp(l, r) }
+}
+
+pub struct KeyCol {
+  pub ty: ColTy,
+  // offset in the key
+  pub off: usize,
+  pub ci_id: u32,
+  // offset in the record
+  pub data_off: usize,
+}
+
+// compare the keys of a composite index (see `IndexInfo::key_layout`) col by col, a null col is less than any value
+pub struct MultiCmp {
+  pub cols: Vec<KeyCol>,
+  pub null_off: usize,
+}
+
+impl MultiCmp {
+  pub unsafe fn new(tp: &TablePage, info: &IndexInfo) -> MultiCmp {
+    let (offs, null_off) = info.key_layout(tp);
+    let cols = info.cols().iter().zip(offs).map(|(&ci_id, off)| {
+      let ci = tp.cols().get_unchecked(ci_id as usize);
+      KeyCol { ty: ci.ty, off: off as usize, ci_id: ci_id as u32, data_off: ci.off as usize }
+    }).collect();
+    MultiCmp { cols, null_off: null_off as usize }
+  }
+
+  // the size of the data part of a key
+  pub fn size(&self) -> usize { self.null_off + 1 }
+
+  // only compare the first `n` cols
+  pub unsafe fn cmp_prefix(&self, l: *const u8, r: *const u8, n: usize) -> Ordering {
+    let (l_null, r_null) = (*l.add(self.null_off), *r.add(self.null_off));
+    for (i, col) in self.cols.iter().take(n).enumerate() {
+      let (l_null, r_null) = ((l_null >> i) & 1 != 0, (r_null >> i) & 1 != 0);
+      let ord = if l_null || r_null { r_null.cmp(&l_null) } else {
+        let (l, r) = (l.add(col.off), r.add(col.off));
+        macro_rules! handle { ($ty: ident) => { Cmp::<{ $ty }>::cmp(l, r) }; }
+        handle_all!(col.ty.fix_ty().ty, handle)
+      };
+      if ord != Ordering::Equal { return ord; }
+    }
+    Ordering::Equal
+  }
 
-  pub unsafe fn cmp_full(l: *const u8, r: *const u8, rid_off: usize) -> Ordering {
-    Self::cmp(l, r).then((*(l.add(rid_off) as *const Rid)).cmp(&*(r.add(rid_off) as *const Rid)))
+  // write the key of the record `data` to `key`, which should have at least `size()` bytes
+  pub unsafe fn make_key(&self, data: *const u8, key: *mut u8) {
+    key.write_bytes(0, self.size());
+    for (i, col) in self.cols.iter().enumerate() {
+      if is_null(data, col.ci_id) { *key.add(self.null_off) |= 1 << i; } else {
+        key.add(col.off).copy_from_nonoverlapping(data.add(col.data_off), col.ty.size() as usize);
+      }
+    }
   }
 }
 
-// `x` should be like data_rid consecutively stored(have the same layout as in IndexPage::data)
-// return the first index `i` that the `i`th element is the first element > `x` (in the sense of `cmp_full`)
-// if caller can guarantee `x` exists in `ip`, and the elements in `ip` are strictly ascending, then the `i - 1`th element the only element == `x`
-pub unsafe fn upper_bound<const T: BareTy>(ip: &IndexPage, x: *const u8) -> usize {
-  let (slot_size, rid_off) = (ip.slot_size() as usize, ip.rid_off as usize);
+impl KeyCmp for MultiCmp {
+  unsafe fn cmp_data(&self, l: *const u8, r: *const u8) -> Ordering { self.cmp_prefix(l, r, self.cols.len()) }
+}
+
+// return the number of the leading slots in `ip` satisfying `pred`, these slots should be a prefix of all slots
+pub unsafe fn partition_point(ip: &IndexPage, pred: impl Fn(*const u8) -> bool) -> usize {
+  let slot_size = ip.slot_size() as usize;
   let (mut i, mut last) = (0, ip.count as isize - 1); // count may be 0, usize may overflow
   while i <= last {
     let mid = (i + last) >> 1;
-    if Cmp::<{ T }>::cmp_full(x, ip.data.as_ptr().add(mid as usize * slot_size), rid_off) == Ordering::Less {
-      last = mid - 1;
-    } else {
-      i = mid + 1;
-    }
+    if pred(ip.data.as_ptr().add(mid as usize * slot_size)) { i = mid + 1; } else { last = mid - 1; }
   }
   i as usize
-}
\ No newline at end of file
+}
+
+// `x` should be like data_rid consecutively stored(have the same layout as in IndexPage::data)
+// return the first index `i` that the `i`th element is the first element > `x` (in the sense of `cmp_full`)
+// if caller can guarantee `x` exists in `ip`, and the elements in `ip` are strictly ascending, then the `i - 1`th element the only element == `x`
+pub unsafe fn upper_bound(cmp: &impl KeyCmp, ip: &IndexPage, x: *const u8) -> usize {
+  let rid_off = ip.rid_off as usize;
+  partition_point(ip, |e| cmp.cmp_full(x, e, rid_off) != Ordering::Less)
+}
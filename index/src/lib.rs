@@ -2,7 +2,7 @@
 #![feature(const_generics)]
 #![feature(box_syntax)]
 
-use std::{ptr::{self, NonNull}, marker::PhantomData, cmp::Ordering};
+use std::{ptr::{self, NonNull}, ops::Deref, cmp::Ordering};
 
 use common::*;
 use db::Db;
@@ -12,24 +12,122 @@ use crate::cmp::*;
 pub mod cmp;
 pub mod iter;
 pub mod alter;
+pub mod bulk;
+pub mod multi;
 
 pub use alter::*;
+pub use multi::*;
 
+// the location of the root page id of a tree
+#[derive(Copy, Clone)]
+enum RootAt {
+  // `TablePage::cols[ci_id].index`
+  Col { tp_id: u32, ci_id: u32 },
+  // `IndexListPage::indexes[idx].index` of the table
+  Multi { tp_id: u32, idx: u32 },
+}
+
+// a B+ tree whose keys are compared by `C`, it is created by `Index` (on a single col) or `MultiIndex` (on several cols)
 // using both lifetime parameter and const parameter will cause my rustc (1.40.0-nightly) to ICE, so just use pointer here
-pub struct Index<const T: BareTy> {
+// a Tree (created by `new_ref`) can only be used for reading, which reads pages through `&Db`, so that many readers can use indexes at the same time
+pub struct Tree<C> {
+  db: *const Db,
+  root_at: RootAt,
+  cmp: C,
+}
+
+// a Tree created by `new` from `&mut Db`, which can also modify the tree
+pub struct TreeMut<C> {
+  tree: Tree<C>,
   db: *mut Db,
-  tp_id: u32,
-  ci_id: u32,
-  _p: PhantomData<Cmp<{ T }>>,
 }
 
+impl<C> Deref for TreeMut<C> {
+  type Target = Tree<C>;
+  fn deref(&self) -> &Tree<C> { &self.tree }
+}
+
+// the index on a single col of type `T`
+pub struct Index<const T: BareTy>;
+
 impl<const T: BareTy> Index<{ T }> {
-  pub unsafe fn new(db: &mut Db, tp_id: u32, ci_id: u32) -> Index<{ T }> { Index { db, tp_id, ci_id, _p: PhantomData } }
+  pub unsafe fn new(db: &mut Db, tp_id: u32, ci_id: u32) -> TreeMut<Cmp<{ T }>> { TreeMut::new(db, RootAt::Col { tp_id, ci_id }, Cmp::<{ T }>) }
 
-  unsafe fn db<'a>(&mut self) -> &'a mut Db { self.db.r() }
-  // these 2 functions are not frequently called, so not save these 2 values in `Index` struct
-  unsafe fn root(&self) -> u32 { self.db.r().get_page::<TablePage>(self.tp_id).cols.get_unchecked_mut(self.ci_id as usize).index }
-  unsafe fn rid_off(&self) -> usize { self.db.r().get_page::<IndexPage>(self.root()).rid_off as usize }
+  pub unsafe fn new_ref(db: &Db, tp_id: u32, ci_id: u32) -> Tree<Cmp<{ T }>> { Tree { db, root_at: RootAt::Col { tp_id, ci_id }, cmp: Cmp::<{ T }> } }
+}
+
+impl<C: KeyCmp> Tree<C> {
+  unsafe fn db_ref<'a>(&self) -> &'a Db { &*self.db }
+  // these 2 functions are not frequently called, so not save these 2 values in `Tree` struct
+  unsafe fn root(&self) -> u32 {
+    match self.root_at {
+      RootAt::Col { tp_id, ci_id } => self.db_ref().page::<TablePage>(tp_id).cols().get_unchecked(ci_id as usize).index,
+      RootAt::Multi { tp_id, idx } => {
+        let list = self.db_ref().page::<TablePage>(tp_id).indexes;
+        self.db_ref().page::<IndexListPage>(list).indexes.get_unchecked(idx as usize).index
+      }
+    }
+  }
+  unsafe fn rid_off(&self) -> usize { self.db_ref().page::<IndexPage>(self.root()).rid_off as usize }
+
+  unsafe fn make_data_rid(&self, data: *const u8, rid: Rid) -> Align4U8 {
+    let rid_off = self.rid_off();
+    let data_rid = Align4U8::new(rid_off + 4);
+    data_rid.ptr.copy_from_nonoverlapping(data, rid_off);
+    *(data_rid.ptr.add(rid_off) as *mut Rid) = rid;
+    data_rid
+  }
+
+  unsafe fn debug_check(&self, page: u32) {
+    if cfg!(debug_assertions) { // ensure compiler can optimize this out
+      let ip = self.db_ref().page::<IndexPage>(page);
+      let slot_size = ip.slot_size() as usize;
+      macro_rules! at { ($pos: expr) => { ip.data.as_ptr().add($pos * slot_size) }; }
+      // previously the relationship between `cap` and `slot_size` is checked here (in the commented line)
+      // but it is now removed because we want to modify the `cap` in tests without modifying `slot_size`
+      // assert_eq!(ip.cap, MAX_INDEX_BYTES as u16 / ip.slot_size());
+      assert!(ip.count < ip.cap); // cannot have count == cap, the code depends on it
+      assert!(page == self.root() || ip.cap / 2 <= ip.count);
+      assert_eq!(ip.rid_off as usize, self.rid_off());
+      for i in 1..ip.count as usize {
+        assert_eq!(self.cmp.cmp_full(at!(i - 1), at!(i), self.rid_off()), Ordering::Less);
+      }
+    }
+  }
+
+  // it is only called explicitly, so there is no `if cfg!(debug_assertions)`
+  pub unsafe fn debug_check_all(&self) {
+    unsafe fn dfs<C: KeyCmp>(s: &Tree<C>, page: u32, lb: *const u8, ub: *const u8) {
+      s.debug_check(page);
+      let ip = s.db_ref().page::<IndexPage>(page);
+      let (slot_size, key_size) = (ip.slot_size() as usize, ip.key_size() as usize);
+      macro_rules! at { ($pos: expr) => { ip.data.as_ptr().add($pos * slot_size) }; }
+      macro_rules! at_ch { ($pos: expr) => { *(ip.data.as_ptr().add($pos * slot_size + key_size) as *const u32) }; }
+      if !lb.is_null() {
+        // the min key must be the dup key
+        assert_eq!(s.cmp.cmp_full(lb, at!(0), s.rid_off()), Ordering::Equal);
+      }
+      if !ub.is_null() {
+        assert_eq!(s.cmp.cmp_full(at!(ip.count as usize - 1), ub, s.rid_off()), Ordering::Less);
+      }
+      if !ip.leaf {
+        for i in 0..ip.count as usize {
+          let ub = if i + 1 == ip.count as usize { ub } else { at!(i + 1) };
+          dfs(s, at_ch!(i), at!(i), ub);
+        }
+      }
+    }
+    dfs(self, self.root(), ptr::null(), ptr::null());
+  }
+}
+
+impl<C: KeyCmp> TreeMut<C> {
+  fn new(db: &mut Db, root_at: RootAt, cmp: C) -> TreeMut<C> {
+    let db = db as *mut Db;
+    TreeMut { tree: Tree { db, root_at, cmp }, db }
+  }
+
+  unsafe fn db<'a>(&mut self) -> &'a mut Db { &mut *self.db }
 
   // caller guarantee data_rid doesn't exist in tree
   pub unsafe fn insert(&mut self, data: *const u8, rid: Rid) {
@@ -66,9 +164,10 @@ impl<const T: BareTy> Index<{ T }> {
       };
     }
     if ip.leaf {
-      insert!(upper_bound::<{ T }>(ip, x), x);
+      let pos = upper_bound(&self.cmp, ip, x);
+      insert!(pos, x);
     } else {
-      let ub = upper_bound::<{ T }>(ip, x);
+      let ub = upper_bound(&self.cmp, ip, x);
       let pos = if ub == 0 {
         (at!(0).copy_from_nonoverlapping(x, key_size), 0).1 // update min key
       } else { ub - 1 }; // insert before `lb`
@@ -108,11 +207,11 @@ impl<const T: BareTy> Index<{ T }> {
       };
     }
     if ip.leaf {
-      let pos = upper_bound::<{ T }>(ip, x) - 1;
-      debug_assert_eq!(Cmp::<{ T }>::cmp_full(x, at!(pos), self.rid_off()), Ordering::Equal);
+      let pos = upper_bound(&self.cmp, ip, x) - 1;
+      debug_assert_eq!(self.cmp.cmp_full(x, at!(pos), self.rid_off()), Ordering::Equal);
       remove!(pos);
     } else {
-      let pos = upper_bound::<{ T }>(ip, x).max(1) - 1;
+      let pos = upper_bound(&self.cmp, ip, x).max(1) - 1;
       let (new_min, need_merge) = self.do_delete(at_ch!(pos), x);
       at!(pos).copy_from_nonoverlapping(new_min, key_size); // update dup key
       if need_merge {
@@ -156,75 +255,33 @@ impl<const T: BareTy> Index<{ T }> {
   }
 
   unsafe fn make_root(&mut self, new_id: u32) {
-    self.db().get_page::<TablePage>(self.tp_id).cols.get_unchecked_mut(self.ci_id as usize).index = new_id;
-  }
-
-  unsafe fn make_data_rid(&self, data: *const u8, rid: Rid) -> Align4U8 {
-    let rid_off = self.rid_off();
-    let data_rid = Align4U8::new(rid_off + 4);
-    data_rid.ptr.copy_from_nonoverlapping(data, rid_off);
-    *(data_rid.ptr.add(rid_off) as *mut Rid) = rid;
-    data_rid
-  }
-
-  unsafe fn debug_check(&self, page: u32) {
-    if cfg!(debug_assertions) { // ensure compiler can optimize this out
-      let ip = self.pr().db().get_page::<IndexPage>(page);
-      let slot_size = ip.slot_size() as usize;
-      macro_rules! at { ($pos: expr) => { ip.data.as_mut_ptr().add($pos * slot_size) }; }
-      // previously the relationship between `cap` and `slot_size` is checked here (in the commented line)
-      // but it is now removed because we want to modify the `cap` in tests without modifying `slot_size`
-      // assert_eq!(ip.cap, MAX_INDEX_BYTES as u16 / ip.slot_size());
-      assert!(ip.count < ip.cap); // cannot have count == cap, the code depends on it
-      assert!(page == self.root() || ip.cap / 2 <= ip.count);
-      assert_eq!(ip.rid_off as usize, self.rid_off());
-      for i in 1..ip.count as usize {
-        assert_eq!(Cmp::<{ T }>::cmp_full(at!(i - 1), at!(i), self.rid_off()), Ordering::Less);
-      }
-    }
-  }
-
-  // it is only called explicitly, so there is no `if cfg!(debug_assertions)`
-  pub unsafe fn debug_check_all(&self) {
-    unsafe fn dfs<const T: BareTy>(s: &Index<{ T }>, page: u32, lb: *const u8, ub: *const u8) {
-      s.debug_check(page);
-      let ip = s.pr().db().get_page::<IndexPage>(page);
-      let (slot_size, key_size) = (ip.slot_size() as usize, ip.key_size() as usize);
-      macro_rules! at { ($pos: expr) => { ip.data.as_mut_ptr().add($pos * slot_size) }; }
-      macro_rules! at_ch { ($pos: expr) => { *(ip.data.as_mut_ptr().add($pos * slot_size + key_size) as *mut u32) }; }
-      if !lb.is_null() {
-        // the min key must be the dup key
-        assert_eq!(Cmp::<{ T }>::cmp_full(lb, at!(0), s.rid_off()), Ordering::Equal);
-      }
-      if !ub.is_null() {
-        assert_eq!(Cmp::<{ T }>::cmp_full(at!(ip.count as usize - 1), ub, s.rid_off()), Ordering::Less);
-      }
-      if !ip.leaf {
-        for i in 0..ip.count as usize {
-          let ub = if i + 1 == ip.count as usize { ub } else { at!(i + 1) };
-          dfs(s, at_ch!(i), at!(i), ub);
-        }
+    match self.root_at {
+      RootAt::Col { tp_id, ci_id } => self.db().get_page::<TablePage>(tp_id).cols_mut().get_unchecked_mut(ci_id as usize).index = new_id,
+      RootAt::Multi { tp_id, idx } => {
+        let list = self.db_ref().page::<TablePage>(tp_id).indexes;
+        self.db().get_page::<IndexListPage>(list).indexes.get_unchecked_mut(idx as usize).index = new_id;
       }
     }
-    dfs(self, self.root(), ptr::null(), ptr::null());
   }
+}
 
-  #[cfg(feature = "print-dot")]
+#[cfg(feature = "print-dot")]
+impl<const T: BareTy> Tree<Cmp<{ T }>> {
   pub unsafe fn print_dot(&self) -> String {
     use std::fmt::Write;
-    unsafe fn dfs<const T: BareTy>(s: &Index<{ T }>, page: u32, id: &mut u32, dot: &mut String) -> u32 {
+    unsafe fn dfs<const T: BareTy>(s: &Tree<Cmp<{ T }>>, page: u32, id: &mut u32, dot: &mut String) -> u32 {
       let my_id = (*id, *id += 1).0;
       let _ = write!(dot, "n{}[label=\"", my_id);
-      let ip = s.pr().db().get_page::<IndexPage>(page);
-      let db = s.pr().db();
+      let ip = s.db_ref().page::<IndexPage>(page);
+      let db = s.db_ref();
       let (slot_size, key_size) = (ip.slot_size() as usize, ip.key_size() as usize);
-      macro_rules! at { ($pos: expr) => { ip.data.as_mut_ptr().add($pos * slot_size) }; }
-      macro_rules! at_ch { ($pos: expr) => { *(ip.data.as_mut_ptr().add($pos * slot_size + key_size) as *mut u32) }; }
+      macro_rules! at { ($pos: expr) => { ip.data.as_ptr().add($pos * slot_size) }; }
+      macro_rules! at_ch { ($pos: expr) => { *(ip.data.as_ptr().add($pos * slot_size + key_size) as *const u32) }; }
       let rid_off = s.rid_off();
       let ty = ColTy::FixTy(FixTy { ty: T, size: 0 });
       for i in 0..ip.count as usize {
         let rid = *(at!(i).add(rid_off) as *const Rid);
-        let _ = write!(dot, "<f{}> {:?}\\n{}, {}|", i, db.ptr2lit(at!(i), ty), rid.page(), rid.slot());
+        let _ = write!(dot, "<f{}> {:?}\\n{}, {}|", i, db.ptr2lit(at!(i), ty, &db::Unzipped::default()), rid.page(), rid.slot());
       }
       dot.pop();
       let _ = writeln!(dot, "\"]");
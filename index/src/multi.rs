@@ -0,0 +1,116 @@
+use std::cmp::Ordering;
+use unchecked_unwrap::UncheckedUnwrap;
+
+use common::*;
+use db::{Db, is_null};
+use physics::*;
+use crate::{Tree, TreeMut, RootAt, cmp::{MultiCmp, KeyCmp}, iter::IndexIter};
+
+// the composite index `IndexListPage::indexes[idx]` of a table
+// a record whose first col is null is not in the index, because a lookup always has a condition on the first col
+pub struct MultiIndex;
+
+impl MultiIndex {
+  pub unsafe fn new(db: &mut Db, tp_id: u32, idx: u32) -> TreeMut<MultiCmp> {
+    let cmp = MultiIndex::cmp(db, tp_id, idx);
+    TreeMut::new(db, RootAt::Multi { tp_id, idx }, cmp)
+  }
+
+  pub unsafe fn new_ref(db: &Db, tp_id: u32, idx: u32) -> Tree<MultiCmp> {
+    Tree { db, root_at: RootAt::Multi { tp_id, idx }, cmp: MultiIndex::cmp(db, tp_id, idx) }
+  }
+
+  unsafe fn cmp(db: &Db, tp_id: u32, idx: u32) -> MultiCmp {
+    let tp = db.page::<TablePage>(tp_id);
+    MultiCmp::new(tp, db.page::<IndexListPage>(tp.indexes).indexes.get_unchecked(idx as usize))
+  }
+}
+
+impl Tree<MultiCmp> {
+  // the key of the record `data`, None if its first col is null
+  pub unsafe fn record_key(&self, data: *const u8) -> Option<Align4U8> {
+    if is_null(data, self.cmp.cols.get_unchecked(0).ci_id) { return None; }
+    let key = Align4U8::new(self.rid_off());
+    self.cmp.make_key(data, key.ptr);
+    Some(key)
+  }
+
+  // the key whose first `lits.len()` cols are `lits` (they should be non-null and have passed the type check), and other cols are null
+  pub unsafe fn lits_key(&self, lits: &[CLit]) -> Align4U8 {
+    let key = Align4U8::new(self.rid_off());
+    key.ptr.write_bytes(0, key.size);
+    for (i, col) in self.cmp.cols.iter().enumerate() {
+      match lits.get(i) {
+        Some(&lit) => self.db_ref().lit2ptr(key.ptr.add(col.off), col.ty.fix_ty(), lit).unchecked_unwrap(),
+        None => *key.ptr.add(self.cmp.null_off) |= 1 << i,
+      }
+    }
+    key
+  }
+
+  // the position of the first key whose first `n` cols are >= those of `key`
+  pub unsafe fn prefix_lower_bound<'a>(&self, key: *const u8, n: usize) -> IndexIter<'a> {
+    self.bound(|e| self.cmp.cmp_prefix(e, key, n) == Ordering::Less)
+  }
+
+  // the position of the first key whose first `n` cols are > those of `key`
+  pub unsafe fn prefix_upper_bound<'a>(&self, key: *const u8, n: usize) -> IndexIter<'a> {
+    self.bound(|e| self.cmp.cmp_prefix(e, key, n) != Ordering::Greater)
+  }
+
+  // whether a record other than `rid` has the same key as the record `data`, whose first col should not be null
+  pub unsafe fn has_dup(&self, data: *const u8, rid: Option<Rid>) -> bool {
+    let (key, n) = (self.record_key(data).unchecked_unwrap(), self.cmp.cols.len());
+    let (mut it, end) = (self.prefix_lower_bound(key.ptr, n), self.prefix_upper_bound(key.ptr, n));
+    while it != end {
+      if rid != Some(it.next().unchecked_unwrap()) { return true; }
+    }
+    false
+  }
+}
+
+// the number of composite indexes of the table
+pub unsafe fn multi_index_num(db: &Db, tp_id: u32) -> u32 {
+  let list = db.page::<TablePage>(tp_id).indexes;
+  if list == !0 { 0 } else { db.page::<IndexListPage>(list).count }
+}
+
+// the position of the index of the primary key, None if the primary key has at most one col
+pub unsafe fn primary_multi_index(db: &Db, tp_id: u32) -> Option<u32> {
+  let list = db.page::<TablePage>(tp_id).indexes;
+  if list == !0 { return None; }
+  db.page::<IndexListPage>(list).indexes().iter().position(|info| info.flags.contains(IndexFlags::PRIMARY)).map(|idx| idx as u32)
+}
+
+// below 3 functions keep all composite indexes of the table up to date with a record `rid`
+
+pub unsafe fn insert_multi(db: &mut Db, tp_id: u32, data: *const u8, rid: Rid) {
+  for idx in 0..multi_index_num(db, tp_id) {
+    let mut index = MultiIndex::new(db, tp_id, idx);
+    if let Some(key) = index.record_key(data) { index.insert(key.ptr, rid); }
+  }
+}
+
+pub unsafe fn delete_multi(db: &mut Db, tp_id: u32, data: *const u8, rid: Rid) {
+  for idx in 0..multi_index_num(db, tp_id) {
+    let mut index = MultiIndex::new(db, tp_id, idx);
+    if let Some(key) = index.record_key(data) { index.delete(key.ptr, rid); }
+  }
+}
+
+// `old` and `new` are the record before and after updating, an index is not modified if the key doesn't change
+pub unsafe fn update_multi(db: &mut Db, tp_id: u32, old: *const u8, new: *const u8, rid: Rid) {
+  for idx in 0..multi_index_num(db, tp_id) {
+    let mut index = MultiIndex::new(db, tp_id, idx);
+    let (old, new) = (index.record_key(old), index.record_key(new));
+    let same = match (&old, &new) {
+      (Some(old), Some(new)) => index.cmp.cmp_data(old.ptr, new.ptr) == Ordering::Equal,
+      (None, None) => true,
+      _ => false,
+    };
+    if !same {
+      if let Some(old) = old { index.delete(old.ptr, rid); }
+      if let Some(new) = new { index.insert(new.ptr, rid); }
+    }
+  }
+}
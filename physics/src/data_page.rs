@@ -12,6 +12,10 @@ pub struct DataPage {
   pub data: [u8; common::MAX_DATA_BYTE],
 }
 
+impl crate::Page for DataPage { const KIND: &'static str = "DataPage"; }
+
+impl crate::Page for CheckPage { const KIND: &'static str = "CheckPage"; }
+
 impl DataPage {
   pub unsafe fn init(&mut self, next: u32) {
     self.next = next;
@@ -49,18 +53,85 @@ impl FreeLobSlot {
 }
 
 // this is how varchar exists in DataPage
+// a varchar no longer than `INLINE_VARCHAR` is stored in the slot itself (see `set_inline`), and uses no lob
 #[repr(C)]
 pub struct VarcharSlot {
   pub lob_id: u32,
+  // the length in lob, which is the compressed length for a compressed varchar
   pub len: u16,
   // `cap` is used to deallocate
+  // it is a multiple of LOB_SLOT_SIZE, so its lowest bit is used as the flag of an inline varchar,
+  // and the next bit is used as the flag of a compressed varchar (see `db::compress`)
   pub cap: u16,
 }
 
+pub const INLINE_VARCHAR: usize = 6;
+
+impl VarcharSlot {
+  pub fn is_inline(&self) -> bool { self.cap & 1 != 0 }
+
+  // the content takes the place of `lob_id` and `len`, and `cap` is `len << 1 | 1`
+  pub unsafe fn set_inline(&mut self, s: &[u8]) {
+    debug_assert!(s.len() <= INLINE_VARCHAR);
+    // `s` may point to this slot itself (e.g. `set v = v`)
+    let mut buf = [0; INLINE_VARCHAR];
+    buf.get_unchecked_mut(..s.len()).copy_from_slice(s);
+    (self as *mut VarcharSlot as *mut [u8; INLINE_VARCHAR]).write(buf);
+    self.cap = (s.len() << 1 | 1) as u16;
+  }
+
+  pub fn inline_len(&self) -> usize { (self.cap >> 1) as usize }
+
+  // an inline varchar is never compressed
+  pub fn is_compressed(&self) -> bool { self.cap & 0b11 == 0b10 }
+
+  pub fn set_compressed(&mut self, compressed: bool) { self.cap = self.cap() | ((compressed as u16) << 1); }
+
+  // `cap` without flags, only meaningful if it is not inline
+  pub fn cap(&self) -> u16 { self.cap & !(LOB_SLOT_SIZE as u16 - 1) }
+}
+
+// this is how text and blob exist in DataPage, `len` is not limited to u16
+// the content is stored in a chain of extents starting from `lob_id` (see `TextExtent`), so it doesn't need a contiguous free space
+// `lob_id` is at the same place as in VarcharSlot
+#[repr(C)]
+pub struct TextSlot {
+  pub lob_id: u32,
+  pub len: u32,
+}
+
+// keep `(len + TEXT_EXTENT_HEAD + LOB_SLOT_SIZE - 1)` in u32
+pub const MAX_TEXT_LEN: usize = u32::MAX as usize - TEXT_EXTENT_HEAD - LOB_SLOT_SIZE;
+
+// the header of an extent of a text / blob, the content follows it
+// all extents except the last one are full, so the content is split only where the space is not contiguous
+#[repr(C)]
+pub struct TextExtent {
+  // 0 for none (slot 0 is the nil node of free list, so it is never an extent)
+  pub next: u32,
+  // slot count of this extent
+  pub count: u32,
+}
+
+pub const TEXT_EXTENT_HEAD: usize = size_of::<TextExtent>();
+
+impl TextExtent {
+  // bytes of content this extent can hold
+  pub fn cap(&self) -> usize { (self.count as usize * LOB_SLOT_SIZE).saturating_sub(TEXT_EXTENT_HEAD) }
+}
+
+// (lob id, slot count) of a varchar, `ptr` points to its VarcharSlot; None for an inline varchar
+// a text / blob may have many extents, see `Db::lob_extents`
+pub unsafe fn lob_extent(ptr: *const u8) -> Option<(u32, u32)> {
+  let v = &*(ptr as *const VarcharSlot);
+  if v.is_inline() { None } else { Some((v.lob_id, v.cap as u32 / LOB_SLOT_SIZE as u32)) }
+}
+
 #[cfg_attr(tarpaulin, ignore)]
 fn _ck() {
   const_assert_eq!(size_of::<DataPage>(), common::PAGE_SIZE);
   const_assert_eq!(size_of::<CheckPage>(), common::PAGE_SIZE);
   const_assert_eq!(size_of::<FreeLobSlot>(), LOB_SLOT_SIZE);
   const_assert_eq!(size_of::<VarcharSlot>(), common::VARCHAR_SLOT_SIZE);
+  const_assert_eq!(size_of::<TextSlot>(), common::VARCHAR_SLOT_SIZE);
 }
\ No newline at end of file
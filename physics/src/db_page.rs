@@ -1,17 +1,38 @@
-use std::{mem::size_of, slice};
+use std::mem::size_of;
 
 use common::*;
 
+// files created before `version` was added have version 0 (the two bytes were reserved and zero)
+// a new version is needed when the layout of existing structures changes, `Db::open` upgrades an older version in place
+pub const FORMAT_VERSION: u8 = 1;
+
+bitflags::bitflags! {
+  // optional structures used by this file, a file using an unknown feature is refused
+  pub struct DbFeatures: u8 {
+    // `table_num > MAX_TABLE`, some table ids are in TableListPages
+    const TABLE_LIST = 0b1;
+    // some names in TablePages or IndexListPages are stored in the name area, see `LONG_NAME`
+    const LONG_NAME = 0b10;
+    // some cols are text or blob, see `TextSlot`
+    const LOB_TYPES = 0b100;
+    // some varchars are stored in data slots, see `VarcharSlot::set_inline`
+    const INLINE_VARCHAR = 0b1000;
+    // some varchars are compressed in lob, see `VarcharSlot::is_compressed`
+    const COMPRESSED_VARCHAR = 0b10000;
+  }
+}
+
 #[repr(C)]
 pub struct DbPage {
   pub magic: [u8; MAGIC_LEN],
-  pub _rsv1: [u8; 2],
+  pub version: u8,
+  pub features: DbFeatures,
   // !0 for none
   pub first_free: u32,
-  // using u16 here is not to save space (since there is still enough space in _rsv)
-  // but to explicitly show that u16 is enough
-  pub table_num: u16,
-  pub _rsv2: [u8; 2],
+  // it was a u16 followed by 2 reserved zero bytes, so old files can be read as u32 directly
+  pub table_num: u32,
+  // if `table_num <= MAX_TABLE`, all table ids are here
+  // otherwise the first `MAX_TABLE - 1` ids are here, and `tables[MAX_TABLE - 1]` is the first TableListPage of the rest
   pub tables: [u32; MAX_TABLE],
 }
 
@@ -20,14 +41,34 @@ pub const MAX_TABLE: usize = 2041;
 impl DbPage {
   pub fn init(&mut self) {
     self.magic = *MAGIC;
+    self.version = FORMAT_VERSION;
+    self.features = DbFeatures::empty();
     self.first_free = !0;
     self.table_num = 0;
   }
 
-  pub unsafe fn tables<'a>(&self) -> &'a [u32] {
-    slice::from_raw_parts(self.tables.as_ptr(), self.table_num as usize)
+  // the first page of TableListPage, if any
+  pub fn table_list(&self) -> Option<u32> {
+    if self.table_num as usize > MAX_TABLE { Some(self.tables[MAX_TABLE - 1]) } else { None }
   }
 }
 
+#[repr(C)]
+pub struct TableListPage {
+  // !0 for none
+  pub next: u32,
+  // the number of ids in this page is determined by `DbPage::table_num`
+  pub tables: [u32; TABLE_LIST_CAP],
+}
+
+pub const TABLE_LIST_CAP: usize = 2047;
+
+impl crate::Page for DbPage { const KIND: &'static str = "DbPage"; }
+
+impl crate::Page for TableListPage { const KIND: &'static str = "TableListPage"; }
+
 #[cfg_attr(tarpaulin, ignore)]
-fn _ck() { const_assert_eq!(size_of::<DbPage>(), common::PAGE_SIZE); }
\ No newline at end of file
+fn _ck() {
+  const_assert_eq!(size_of::<DbPage>(), common::PAGE_SIZE);
+  const_assert_eq!(size_of::<TableListPage>(), common::PAGE_SIZE);
+}
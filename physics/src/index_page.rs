@@ -1,4 +1,7 @@
-use std::mem::size_of;
+use std::{mem::size_of, slice};
+
+use common::*;
+use crate::{TablePage, table_page::{get_name, check_name, page_num_for, NameWriter}};
 
 pub struct IndexPage {
   // !0 for invalid
@@ -31,5 +34,109 @@ impl IndexPage {
   pub fn slot_size(&self) -> u16 { self.key_size() + if self.leaf { 0 } else { 4 } }
 }
 
+// the composite (multi-column) indexes of a table, pointed by `TablePage::indexes`
+// indexes are stored densely in `indexes[..count]`, so the position of an index changes when an index before it is dropped
+// like TablePage, the name area is after `indexes[..count]`, and it may continue in the pages after the first one
+#[repr(C)]
+pub struct IndexListPage {
+  pub count: u32,
+  // the number of consecutive pages it takes
+  pub page_num: u32,
+  pub _rsv: [u8; 56],
+  pub indexes: [IndexInfo; MAX_MULTI_INDEX],
+}
+
+bitflags::bitflags! {
+  pub struct IndexFlags: u8 {
+    // the index of a primary key on more than one col, it has no name, and is dropped with the primary key
+    const PRIMARY = 0b1;
+  }
+}
+
+#[repr(C)]
+pub struct IndexInfo {
+  // index root page id
+  pub index: u32,
+  pub col_num: u8,
+  pub name_len: u8,
+  pub flags: IndexFlags,
+  pub _rsv: u8,
+  // col ids in TablePage::cols, in the order of the key
+  pub cols: [u16; MAX_INDEX_COL],
+  // encoded as described in `LONG_NAME`
+  pub name: [u8; MAX_MULTI_IDX_NAME],
+}
+
+pub const MAX_MULTI_INDEX: usize = 127;
+pub const MAX_INDEX_COL: usize = 8;
+// the capacity of the inline name field, a longer name is stored in the name area
+pub const MAX_MULTI_IDX_NAME: usize = 40;
+// the max size of the data part of a composite key, so that an inner IndexPage has at least 7 slots
+pub const MAX_MULTI_KEY: usize = 1024;
+// the size of the fields before `IndexListPage::indexes`
+const INDEX_LIST_HEAD: usize = size_of::<IndexListPage>() - MAX_MULTI_INDEX * size_of::<IndexInfo>();
+
+impl crate::Page for IndexPage { const KIND: &'static str = "IndexPage"; }
+
+impl crate::Page for IndexListPage {
+  const KIND: &'static str = "IndexListPage";
+
+  fn page_num(&self) -> u32 { self.page_num }
+}
+
+impl IndexListPage {
+  pub unsafe fn indexes<'a>(&self) -> &'a [IndexInfo] {
+    slice::from_raw_parts(self.indexes.as_ptr(), self.count as usize)
+  }
+
+  // copy out the names of all indexes, so that they can be written back by `set_names`
+  pub unsafe fn names(&self) -> Vec<String> { self.indexes().iter().map(|info| info.name().to_owned()).collect() }
+
+  // the number of pages an IndexListPage with these names needs, it has `names.len()` indexes
+  pub fn page_num_for<S: AsRef<str>>(names: &[S]) -> usize {
+    page_num_for(INDEX_LIST_HEAD + names.len() * size_of::<IndexInfo>(), names.iter().map(|name| (name.as_ref(), MAX_MULTI_IDX_NAME)))
+  }
+
+  // like `TablePage::set_names`, see `Db::set_index_names`
+  pub unsafe fn set_names<S: AsRef<str>>(&mut self, names: &[S]) -> bool {
+    debug_assert!(names.len() == self.count as usize && IndexListPage::page_num_for(names) <= self.page_num as usize);
+    let mut w = NameWriter::new(self as *mut IndexListPage as *mut u8, self.page_num as usize);
+    for (info, name) in self.indexes.iter_mut().zip(names) { w.put(&mut info.name_len, info.name.as_mut_ptr(), MAX_MULTI_IDX_NAME, name.as_ref()); }
+    w.long
+  }
+
+  // like `TablePage::check_names`, `count` and `page_num` should be valid
+  pub unsafe fn check_names(&self) -> bool {
+    let (base, size) = (self as *const IndexListPage as *const u8, self.page_num as usize * common::PAGE_SIZE);
+    self.indexes().iter().all(|info| check_name(base, info.name_len, info.name.as_ptr().offset_from(base) as usize, size))
+  }
+}
+
+impl IndexInfo {
+  pub unsafe fn name<'a>(&self) -> &'a str { get_name(self.name_len, self.name.as_ptr()) }
+
+  pub unsafe fn cols<'a>(&self) -> &'a [u16] { slice::from_raw_parts(self.cols.as_ptr(), self.col_num as usize) }
+
+  pub unsafe fn key_layout(&self, tp: &TablePage) -> (Vec<u16>, u16) {
+    key_layout(self.cols().iter().map(|&ci_id| tp.cols().get_unchecked(ci_id as usize).ty))
+  }
+}
+
+// a composite key is the images of `cols` (aligned in the same way as in a record) followed by one byte of null bits,
+// bit `i` is set if the `i`th col is null, and the image of a null col is all zero
+// `tys` are the types of the cols, return (the offsets of the images in the key, the offset of the null bits), the size of the data part is `.1 + 1`
+pub fn key_layout(tys: impl Iterator<Item = ColTy>) -> (Vec<u16>, u16) {
+  let (mut offs, mut size) = (Vec::new(), 0);
+  for ty in tys {
+    if ty.align4() { size = (size + 3) & !3; }
+    (offs.push(size), size += ty.size());
+  }
+  (offs, size)
+}
+
 #[cfg_attr(tarpaulin, ignore)]
-fn _ck() { const_assert_eq!(size_of::<IndexPage>(), common::PAGE_SIZE); }
\ No newline at end of file
+fn _ck() {
+  const_assert_eq!(size_of::<IndexPage>(), common::PAGE_SIZE);
+  const_assert_eq!(size_of::<IndexInfo>(), 64);
+  const_assert_eq!(size_of::<IndexListPage>(), common::PAGE_SIZE);
+}
\ No newline at end of file
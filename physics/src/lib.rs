@@ -7,4 +7,16 @@ pub mod index_page;
 pub mod table_page;
 pub mod rid;
 
-pub use crate::{data_page::*, db_page::*, index_page::*, table_page::*, rid::*};
\ No newline at end of file
+pub use crate::{data_page::*, db_page::*, index_page::*, table_page::*, rid::*};
+
+// a structure stored in pages, see `Db::get_page`
+pub trait Page {
+  // the name of the page in `Error::PageCorrupted`
+  const KIND: &'static str;
+
+  // the number of consecutive pages it takes, the pages after the first one are modified through it
+  fn page_num(&self) -> u32 { 1 }
+}
+
+// a free page, the first 4 bytes are the id of the next free page
+impl Page for u32 { const KIND: &'static str = "free page"; }
\ No newline at end of file
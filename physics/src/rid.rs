@@ -2,7 +2,7 @@ use std::{fmt, num::NonZeroU32};
 use common::{MAX_SLOT, LOG_MAX_SLOT};
 
 // (32 - LOG_MAX_SLOT) bits for page, LOG_MAX_SLOT bits for slot
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 #[repr(transparent)]
 pub struct Rid(NonZeroU32); // page 0 cannot be used in rid, so rid cannot be 0
 
@@ -1,4 +1,4 @@
-use std::{mem::size_of, slice};
+use std::{mem::size_of, slice, iter::once};
 
 use common::{*, Error::*};
 
@@ -7,6 +7,8 @@ bitflags::bitflags! {
     const PRIMARY = 0b1;
     const NOTNULL = 0b10;
     const UNIQUE = 0b100;
+    // only for varchar, values are compressed when it saves space, see `VarcharSlot::is_compressed`
+    const COMPRESSED = 0b1000;
     const NOTNULL1 = Self::PRIMARY.bits | Self::NOTNULL.bits; // if any bits in NOTNULL1 exists, this slot can't be null
   }
 }
@@ -22,12 +24,13 @@ pub struct ColInfo {
   // index of TablePage, !0 for none
   pub f_table: u32,
   // index in TablePage::cols, if f_table == !0, f_col is meaningless
-  pub f_col: u8,
-  pub flags: ColFlags,
+  pub f_col: u16,
   // offset in a record; this is an important field, placing it so behind is to avoid the space of padding
   pub off: u16,
+  pub flags: ColFlags,
   // if `index == !0`, below 2 are meaningless
   // if `index != !0 && idx_name_len == 0`, it is an anonymous index (created by dbms, has no name)
+  // all names are encoded as described in `LONG_NAME`
   pub idx_name_len: u8,
   pub idx_name: [u8; MAX_IDX_NAME],
   pub name_len: u8,
@@ -35,24 +38,20 @@ pub struct ColInfo {
 }
 
 impl ColInfo {
-  // `idx_name_len` and `idx_name` is not initialized here
-  pub unsafe fn init(&mut self, ty: ColTy, off: u16, name: &str, notnull: bool) {
+  // names are not initialized here, use `TablePage::set_names`
+  pub unsafe fn init(&mut self, ty: ColTy, off: u16, notnull: bool) {
     self.ty = ty;
     self.off = off;
     self.index = !0;
     self.check = !0;
-    self.name_len = name.len() as u8;
-    self.name.as_mut_ptr().copy_from_nonoverlapping(name.as_ptr(), name.len());
     self.flags = if notnull { ColFlags::NOTNULL } else { ColFlags::empty() };
     self.f_table = !0;
   }
 
-  pub unsafe fn name<'a>(&self) -> &'a str {
-    str_from_parts(self.name.as_ptr(), self.name_len as usize)
-  }
+  pub unsafe fn name<'a>(&self) -> &'a str { get_name(self.name_len, self.name.as_ptr()) }
 
   pub unsafe fn idx_name<'a>(&self) -> Option<&'a str> {
-    if self.index != !0 { Some(str_from_parts(self.idx_name.as_ptr(), self.idx_name_len as usize)) } else { None }
+    if self.index != !0 { Some(get_name(self.idx_name_len, self.idx_name.as_ptr())) } else { None }
   }
 
   pub fn unique(&self, primary_cnt: usize) -> bool {
@@ -74,47 +73,145 @@ pub struct TablePage {
   pub cap: u16,
   pub name_len: u8,
   pub name: [u8; MAX_TABLE_NAME],
-  pub col_num: u8,
-  pub cols: [ColInfo; MAX_COL],
+  // each col takes at least one byte in a record, so there can't be more than MAX_DATA_BYTE cols
+  pub col_num: u16,
+  // the IndexListPage of its composite indexes, !0 for none
+  pub indexes: u32,
+  // the number of consecutive pages it takes, `cols` and the name area continue in the pages after the first one
+  pub page_num: u32,
+  // actually `col_num` ColInfos, followed by the name area
+  pub cols: [ColInfo; 0],
 }
 
-pub const MAX_TABLE_NAME: usize = 46;
+// the capacity of inline name fields, longer names are stored in the name area
+pub const MAX_TABLE_NAME: usize = 37;
 pub const MAX_COL_NAME: usize = 25;
 pub const MAX_IDX_NAME: usize = 15;
-pub const MAX_COL: usize = 127;
+
+// a name no longer than the capacity of its inline field is stored there directly, with its length in the len byte
+// otherwise the len byte is LONG_NAME, the name is stored in the name area, which is the space between the last ColInfo
+// and the end of the last page of the TablePage (IndexListPage uses the same scheme for its IndexInfos),
+// and the inline field holds (offset of the name relative to the field: u32, length: u32)
+// an offset relative to the field (rather than the page) makes it possible to find the name without knowing the page
+pub const LONG_NAME: u8 = !0;
+
+pub(crate) unsafe fn get_name<'a>(len: u8, field: *const u8) -> &'a str {
+  if len != LONG_NAME { str_from_parts(field, len as usize) } else {
+    let (off, len) = ((field as *const u32).read_unaligned(), (field.add(4) as *const u32).read_unaligned());
+    str_from_parts(field.add(off as usize), len as usize)
+  }
+}
+
+// whether a long name is inside the first `size` bytes from `page`, `field` is the offset of the field in the page
+pub(crate) unsafe fn check_name(page: *const u8, len: u8, field: usize, size: usize) -> bool {
+  len != LONG_NAME || {
+    let p = page.add(field);
+    let (off, len) = ((p as *const u32).read_unaligned() as usize, (p.add(4) as *const u32).read_unaligned() as usize);
+    field + off + len <= size
+  }
+}
+
+// the number of pages taking `fixed` bytes (the header and all infos) and the long ones of `names` (name, capacity of its field)
+pub(crate) fn page_num_for<'a>(fixed: usize, names: impl Iterator<Item=(&'a str, usize)>) -> usize {
+  let size = fixed + names.filter(|&(name, cap)| name.len() > cap).map(|(name, _)| name.len()).sum::<usize>();
+  (size + common::PAGE_SIZE - 1) / common::PAGE_SIZE
+}
+
+// write names to the name area from its end, which is the end of the last page
+pub(crate) struct NameWriter { base: *mut u8, end: usize, pub(crate) long: bool }
+
+impl NameWriter {
+  pub(crate) fn new(base: *mut u8, page_num: usize) -> NameWriter { NameWriter { base, end: page_num * common::PAGE_SIZE, long: false } }
+
+  pub(crate) unsafe fn put(&mut self, len: &mut u8, field: *mut u8, cap: usize, name: &str) {
+    if name.len() <= cap {
+      *len = name.len() as u8;
+      field.copy_from_nonoverlapping(name.as_ptr(), name.len());
+    } else {
+      self.end -= name.len();
+      let dst = self.base.add(self.end);
+      dst.copy_from_nonoverlapping(name.as_ptr(), name.len());
+      (*len = LONG_NAME, self.long = true);
+      (field as *mut u32).write_unaligned(dst.offset_from(field) as u32);
+      (field.add(4) as *mut u32).write_unaligned(name.len() as u32);
+    }
+  }
+}
+
+impl crate::Page for TablePage {
+  const KIND: &'static str = "TablePage";
+
+  fn page_num(&self) -> u32 { self.page_num }
+}
 
 impl TablePage {
-  pub unsafe fn init(&mut self, size: u16, col_num: u8, name: &str) {
-    (self.first = !0, self.first_free = !0);
+  // names are not initialized here, use `set_names`; `page_num` is set by `Db::alloc_pages`
+  pub unsafe fn init(&mut self, size: u16, col_num: u16) {
+    (self.first = !0, self.first_free = !0, self.indexes = !0);
     self.count = 0;
     (self.size = size, self.cap = MAX_DATA_BYTE as u16 / size);
-    self.name_len = name.len() as u8;
-    self.name.as_mut_ptr().copy_from_nonoverlapping(name.as_ptr(), name.len());
     self.col_num = col_num;
   }
 
-  pub unsafe fn name<'a>(&self) -> &'a str {
-    str_from_parts(self.name.as_ptr(), self.name_len as usize)
+  pub unsafe fn name<'a>(&self) -> &'a str { get_name(self.name_len, self.name.as_ptr()) }
+
+  // copy out (table name, [(col name, index name)]), so that they can be modified and written back by `set_names`
+  pub unsafe fn names(&self) -> (String, Vec<(String, String)>) {
+    (self.name().to_owned(), self.cols().iter().map(|ci| (ci.name().to_owned(), ci.idx_name().unwrap_or("").to_owned())).collect())
+  }
+
+  // the number of pages a TablePage with these names needs, it has `cols.len()` cols
+  pub fn page_num_for<S: AsRef<str>>(table: &str, cols: &[(S, S)]) -> usize {
+    let names = cols.iter().flat_map(|(c, i)| once((c.as_ref(), MAX_COL_NAME)).chain(once((i.as_ref(), MAX_IDX_NAME))));
+    page_num_for(size_of::<TablePage>() + cols.len() * size_of::<ColInfo>(), once((table, MAX_TABLE_NAME)).chain(names))
+  }
+
+  // write all names, the name area is rewritten from scratch; it should take at least `page_num_for` pages, see `Db::set_table_names`
+  // `cols.len()` should be equal to `col_num`, and the names should not point into this page
+  // return whether the name area is used
+  pub unsafe fn set_names<S: AsRef<str>>(&mut self, table: &str, cols: &[(S, S)]) -> bool {
+    debug_assert!(cols.len() == self.col_num as usize && TablePage::page_num_for(table, cols) <= self.page_num as usize);
+    let mut w = NameWriter::new(self as *mut TablePage as *mut u8, self.page_num as usize);
+    w.put(&mut self.name_len, self.name.as_mut_ptr(), MAX_TABLE_NAME, table);
+    for (ci, (name, idx)) in self.cols_mut().iter_mut().zip(cols) {
+      w.put(&mut ci.name_len, ci.name.as_mut_ptr(), MAX_COL_NAME, name.as_ref());
+      w.put(&mut ci.idx_name_len, ci.idx_name.as_mut_ptr(), MAX_IDX_NAME, idx.as_ref());
+    }
+    w.long
+  }
+
+  // whether all cols and long names are inside the pages of this TablePage, `page_num` should be valid
+  pub unsafe fn check_names(&self) -> bool {
+    let (base, size) = (self as *const TablePage as *const u8, self.page_num as usize * common::PAGE_SIZE);
+    let off = |p: *const u8| p.offset_from(base) as usize;
+    size_of::<TablePage>() + self.col_num as usize * size_of::<ColInfo>() <= size && check_name(base, self.name_len, off(self.name.as_ptr()), size) &&
+      self.cols().iter().all(|ci| check_name(base, ci.name_len, off(ci.name.as_ptr()), size) && (ci.index == !0 || check_name(base, ci.idx_name_len, off(ci.idx_name.as_ptr()), size)))
   }
 
   pub unsafe fn cols<'a>(&self) -> &'a [ColInfo] {
     slice::from_raw_parts(self.cols.as_ptr(), self.col_num as usize)
   }
 
+  pub unsafe fn cols_mut<'a>(&mut self) -> &'a mut [ColInfo] {
+    slice::from_raw_parts_mut(self.cols.as_mut_ptr(), self.col_num as usize)
+  }
+
   pub unsafe fn primary_cols<'a>(&self) -> impl Iterator<Item=&'a ColInfo> {
     self.cols().iter().filter(|ci| ci.flags.contains(ColFlags::PRIMARY))
   }
 
+  // like `get_ci`, but for readers
+  pub unsafe fn find_ci<'a, 'b>(&self, col: &'b str) -> Result<'b, &'a ColInfo> {
+    self.cols().iter().find(|c| c.name() == col).ok_or(NoSuchCol(col))
+  }
+
   pub unsafe fn get_ci<'a, 'b>(&mut self, col: &'b str) -> Result<'b, &'a mut ColInfo> {
-    match self.pr().cols().iter().map(|c| c.name()).enumerate().find(|n| n.1 == col) {
-      Some((idx, _)) => Ok(self.pr().cols.get_unchecked_mut(idx)),
-      None => Err(NoSuchCol(col)),
-    }
+    self.cols_mut().iter_mut().find(|c| c.name() == col).ok_or(NoSuchCol(col))
   }
 }
 
 #[cfg_attr(tarpaulin, ignore)]
 fn _ck() {
   const_assert_eq!(size_of::<ColInfo>(), 64);
-  const_assert_eq!(size_of::<TablePage>(), common::PAGE_SIZE);
+  const_assert_eq!(size_of::<TablePage>(), 64);
 }
\ No newline at end of file
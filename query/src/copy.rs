@@ -0,0 +1,157 @@
+use unchecked_unwrap::UncheckedUnwrap;
+use std::{borrow::Cow, cell::RefCell, fs::File, io::{BufRead, BufReader, BufWriter}, slice};
+
+use common::{*, BareTy::*, Error::*};
+use syntax::ast::*;
+use db::Db;
+use crate::{insert::InsertCtx, select::select_each, export::RowWriter};
+
+struct Field<'s> {
+  s: Cow<'s, str>,
+  quoted: bool,
+}
+
+// RFC 4180: fields are separated by the delimiter and records by CRLF (LF is also accepted),
+// a field in double quotes may contain delimiters, line breaks and "" (for a ")
+// the file is read line by line, a record takes more than one line only if a quoted field has line breaks
+struct Csv<R> {
+  r: R,
+  // the lines of the current record
+  buf: String,
+  pos: usize,
+  // the number of lines read
+  line: usize,
+  delimiter: u8,
+}
+
+impl<R: BufRead> Csv<R> {
+  // append the next line to `buf`, return false at the end of file
+  fn next_line<'a>(&mut self) -> Result<'a, bool> {
+    let n = self.r.read_line(&mut self.buf)?;
+    (self.line += (n != 0) as usize, Ok(n != 0)).1
+  }
+
+  // return the line where the record starts and its fields, a record which cannot be parsed is reported as `CopyFailed`
+  fn record<'a>(&mut self) -> Result<'a, Option<(usize, Vec<Field>)>> {
+    // an empty line is not a record
+    loop {
+      (self.buf.clear(), self.pos = 0);
+      if !self.next_line()? { return Ok(None); }
+      if self.buf != "\n" && self.buf != "\r\n" { break; }
+    }
+    let line = self.line;
+    let fail = |reason: &str| CopyFailed { line, reason: reason.into() };
+    // an unquoted field is kept as a range of `buf` until the record ends, because `buf` may still grow
+    let mut fields = Vec::new();
+    loop {
+      if self.buf.as_bytes().get(self.pos) == Some(&b'"') {
+        let (mut s, mut start) = (String::new(), self.pos + 1);
+        self.pos += 1;
+        loop {
+          let b = self.buf.as_bytes();
+          match b.get(self.pos) {
+            None => if !self.next_line()? { return Err(fail("unterminated quoted field")); },
+            Some(b'"') if b.get(self.pos + 1) == Some(&b'"') => {
+              s += &self.buf[start..self.pos + 1];
+              (self.pos += 2, start = self.pos);
+            }
+            Some(b'"') => {
+              s += &self.buf[start..self.pos];
+              self.pos += 1;
+              break;
+            }
+            _ => self.pos += 1,
+          }
+        }
+        fields.push((Some(s), 0, 0));
+      } else {
+        let (b, start) = (self.buf.as_bytes(), self.pos);
+        while let Some(&c) = b.get(self.pos) {
+          if c == self.delimiter || c == b'\n' || c == b'\r' { break; }
+          self.pos += 1;
+        }
+        fields.push((None, start, self.pos));
+      }
+      let b = self.buf.as_bytes();
+      match b.get(self.pos) {
+        Some(&c) if c == self.delimiter => self.pos += 1,
+        // the last line may have no line break
+        None | Some(b'\n') => break,
+        Some(b'\r') if b.get(self.pos + 1) == Some(&b'\n') => break,
+        _ => return Err(fail("unexpected character after a quoted field")),
+      }
+    }
+    // the delimiter and line breaks are ascii, so the ranges are at char boundaries
+    let buf = &self.buf;
+    Ok(Some((line, fields.into_iter().map(|(s, start, end)| match s {
+      Some(s) => Field { s: Cow::Owned(s), quoted: true },
+      None => Field { s: Cow::Borrowed(&buf[start..end]), quoted: false },
+    }).collect())))
+  }
+}
+
+// "0aFF" => [0x0a, 0xff], the same as the content of a hex literal
+fn hex(s: &str) -> Option<Vec<u8>> {
+  if s.len() % 2 != 0 { return None; }
+  (0..s.len()).step_by(2).map(|i| s.get(i..i + 2).and_then(|x| u8::from_str_radix(x, 16).ok())).collect()
+}
+
+// the field is converted to the literal which the parser produces for this column, the value is checked later in `InsertCtx`
+fn field2lit<'s>(s: &'s str, ty: ColTy, blob: Option<&'s [u8]>) -> Result<'s, CLit<'s>> {
+  let lit = match ty {
+    ColTy::FixTy(FixTy { ty: Bool, .. }) =>
+      if s.eq_ignore_ascii_case("true") { Some(Lit::Bool(true)) } else if s.eq_ignore_ascii_case("false") { Some(Lit::Bool(false)) } else { None },
+    ColTy::FixTy(FixTy { ty: Int, .. }) => s.parse::<i32>().ok().map(|x| Lit::Number(x as f64)),
+    ColTy::FixTy(FixTy { ty: Float, .. }) => s.parse::<f32>().ok().map(|x| Lit::Number(x as f64)),
+    ColTy::Blob => blob.map(Lit::Blob),
+    // date is parsed from a string in `lit2ptr`
+    _ => Some(Lit::Str(s)),
+  };
+  lit.map(CLit::new).ok_or(ColLitMismatch { ty, val: CLit::new(Lit::Str(s)) })
+}
+
+// `tys` are the types of the columns to insert into
+unsafe fn copy_record<'a>(ctx: &mut InsertCtx<'a>, buf: *mut u8, fields: &[Field], tys: &[ColTy], null: &str) -> Result<'a, ()> {
+  if fields.len() > tys.len() { return Err(InsertTooLong { max: tys.len(), actual: fields.len() }); }
+  let blobs = fields.iter().zip(tys).map(|(f, &ty)| if ty == ColTy::Blob { hex(&f.s) } else { None }).collect::<Vec<_>>();
+  let mut vals = Vec::with_capacity(fields.len());
+  for ((f, &ty), blob) in fields.iter().zip(tys).zip(&blobs) {
+    vals.push(if !f.quoted && f.s == null { CLit::new(Lit::Null) } else {
+      // `ctx` lives longer than the record, but the literals are not used after `insert` returns (the error is formatted at once)
+      let (s, blob) = (&*(&*f.s as *const str), blob.as_ref().map(|b| slice::from_raw_parts(b.as_ptr(), b.len())));
+      field2lit(s, ty, blob)?
+    });
+  }
+  ctx.insert(buf, &vals)
+}
+
+// the file is read as csv, each record is inserted in the same way as `insert`; like `insert`, the caller should run it in `Db::atomic`,
+// so that if any record fails, no record is inserted
+pub fn copy_from<'a>(c: &CopyFrom<'a>, db: &mut Db) -> Result<'a, u32> {
+  let mut csv = Csv { r: BufReader::new(File::open(c.path)?), buf: String::new(), pos: 0, line: 0, delimiter: c.opts.delimiter };
+  unsafe {
+    let mut ctx = InsertCtx::new(db, c.table, c.cols.as_deref())?;
+    let tys = match &c.cols {
+      Some(cols) => cols.iter().map(|col| ctx.tp.get_ci(col).map(|ci| ci.ty)).collect::<Result<Vec<_>>>()?,
+      None => ctx.tp.cols().iter().map(|ci| ci.ty).collect(),
+    };
+    let buf = Align4U8::new(ctx.tp.size as usize);
+    let (mut n, mut first) = (0, true);
+    while let Some((line, fields)) = csv.record()? {
+      if (c.opts.header && first, first = false).0 { continue; }
+      if let Err(e) = copy_record(&mut ctx, buf.ptr, &fields, &tys, c.opts.null) { return Err(CopyFailed { line, reason: format!("{:?}", e) }); }
+      n += 1;
+    }
+    Ok(n)
+  }
+}
+
+// the file is created after the select succeeds, and the result is written to it row by row
+pub fn copy_to<'a>(c: &CopyTo<'a>, db: &mut Db) -> Result<'a, u32> {
+  let w = RefCell::new(None);
+  let n = select_each(&c.select, db, |cols| Ok(*w.borrow_mut() = Some(RowWriter::new(BufWriter::new(File::create(c.path)?), c.format, cols)?)),
+    // `cols` is called before any row
+    |row| Ok(unsafe { w.borrow_mut().as_mut().unchecked_unwrap() }.write_row(row)?))?;
+  if let Some(w) = w.into_inner() { w.finish()?; }
+  Ok(n)
+}
@@ -1,16 +1,18 @@
 use common::{*, BareTy::*};
 use syntax::ast::*;
 use db::{Db, is_null};
-use index::{Index, handle_all};
-use crate::{predicate::one_where, filter::filter, check_foreign_link};
+use index::{Index, delete_multi, handle_all};
+use crate::{predicate::one_where, filter::filter, check_foreign_link, rolled_back};
 
-pub fn delete<'a>(d: &Delete<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
+// if any row fails, the rows deleted before it are undone by the caller's `Db::atomic`, see `insert`
+pub fn delete<'a>(d: &Delete<'a>, db: &mut Db) -> Result<'a, u32> {
   unsafe {
     let (tp_id, tp) = db.get_tp(d.table)?;
     let f_links = db.foreign_links_to(tp_id).collect::<Vec<_>>();
+    for &(tp_id1, _, _) in &f_links { db.verify_table(tp_id1)?; }
     let pred = one_where(db.pr(), &d.where_, tp)?;
     let mut cnt = 0;
-    if let Err(e) = filter(db.pr(), &d.where_, tp_id, pred, |data, rid| {
+    filter(db.pr(), &d.where_, tp_id, pred, |data, rid| {
       check_foreign_link(db, tp, data, &f_links)?;
       // now no error can occur
       for (ci_id, ci) in tp.cols().iter().enumerate() {
@@ -20,13 +22,15 @@ pub fn delete<'a>(d: &Delete<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
             macro_rules! handle { ($ty: ident) => {{ Index::<{ $ty }>::new(db, tp_id, ci_id).delete(ptr, rid); }}; }
             handle_all!(ci.ty.fix_ty().ty, handle);
           }
-          if ci.ty.is_varchar() { db.free_varchar(ptr); }
+          if ci.ty.is_varchar() { db.free_varchar(ptr, ci.ty); }
         }
       }
+      delete_multi(db, tp_id, data, rid);
       db.dealloc_data_slot(tp, rid);
       cnt += 1;
       tp.count -= 1;
       Ok(())
-    }, false) { Err(ModifyError(cnt, e)) } else { Ok(cnt) }
+    }, false).map_err(|e| rolled_back(e, cnt))?;
+    Ok(cnt)
   }
 }
\ No newline at end of file
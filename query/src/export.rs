@@ -0,0 +1,99 @@
+use std::io::{self, Write};
+
+use common::*;
+use syntax::ast::CopyFormat;
+use crate::select::Col;
+
+// writes the rows of a select result as they are produced (e.g. by `select_each`)
+// csv: RFC 4180, a string is quoted if necessary, and an empty string is always quoted to be distinguished from null
+// tsv: a tab, line break or backslash in a string is escaped as \t, \n, \r or \\
+// jsonl: one object per line, whose keys are the names of the cols
+// in all formats numbers are unquoted, dates are in ISO format (e.g. 2020-02-29), blobs are in hex, and nulls are empty (null in jsonl)
+pub struct RowWriter<W: Write> {
+  w: W,
+  format: CopyFormat,
+  // only used by jsonl, already escaped
+  keys: Vec<String>,
+}
+
+impl<W: Write> RowWriter<W> {
+  // the header line of csv and tsv is written here
+  pub fn new(mut w: W, format: CopyFormat, cols: &[Col]) -> io::Result<RowWriter<W>> {
+    let names = cols.iter().map(|col| col.name()).collect::<Vec<_>>();
+    let mut keys = vec![];
+    match format {
+      CopyFormat::Csv => writeln!(w, "{}", names.iter().map(|x| csv_str(x)).collect::<Vec<_>>().join(","))?,
+      CopyFormat::Tsv => writeln!(w, "{}", names.iter().map(|x| tsv_str(x)).collect::<Vec<_>>().join("\t"))?,
+      CopyFormat::Jsonl => keys = names.iter().map(|x| json_str(x)).collect(),
+    }
+    Ok(RowWriter { w, format, keys })
+  }
+
+  pub fn write_row(&mut self, row: &[CLit]) -> io::Result<()> {
+    let w = &mut self.w;
+    for (i, lit) in row.iter().enumerate() {
+      let lit = lit.lit();
+      match self.format {
+        CopyFormat::Csv | CopyFormat::Tsv => {
+          if i != 0 { w.write_all(if self.format == CopyFormat::Csv { b"," } else { b"\t" })?; }
+          match lit {
+            Lit::Null => {}
+            Lit::Str(s) => w.write_all(if self.format == CopyFormat::Csv { csv_str(s) } else { tsv_str(s) }.as_bytes())?,
+            _ => write_plain(w, lit)?,
+          }
+        }
+        CopyFormat::Jsonl => {
+          write!(w, "{}{}:", if i == 0 { "{" } else { "," }, unsafe { self.keys.get_unchecked(i) })?;
+          match lit {
+            // json has no nan or inf
+            Lit::Number(x) if !x.is_finite() => w.write_all(b"null")?,
+            Lit::Str(s) => w.write_all(json_str(s).as_bytes())?,
+            Lit::Date(_) | Lit::Blob(_) => (w.write_all(b"\"")?, write_plain(w, lit)?, w.write_all(b"\"")?).2,
+            _ => write!(w, "{:?}", lit)?,
+          }
+        }
+      }
+    }
+    if self.format == CopyFormat::Jsonl { w.write_all(if row.is_empty() { b"{}" } else { b"}" })?; }
+    w.write_all(b"\n")
+  }
+
+  // flush and return the inner writer
+  pub fn finish(mut self) -> io::Result<W> { (self.w.flush()?, Ok(self.w)).1 }
+}
+
+// bool, number, date or blob
+fn write_plain(w: &mut impl Write, lit: Lit) -> io::Result<()> {
+  match lit {
+    Lit::Blob(b) => b.iter().try_for_each(|b| write!(w, "{:02X}", b)),
+    Lit::Date(d) => write!(w, "{}", d),
+    _ => write!(w, "{:?}", lit),
+  }
+}
+
+fn csv_str(s: &str) -> String {
+  if s.is_empty() || s.contains(|ch: char| ch == ',' || ch == '"' || ch == '\n' || ch == '\r') {
+    format!("\"{}\"", s.replace('"', "\"\""))
+  } else { s.into() }
+}
+
+fn tsv_str(s: &str) -> String {
+  let mut ret = String::with_capacity(s.len());
+  for ch in s.chars() {
+    match ch { '\t' => ret += "\\t", '\n' => ret += "\\n", '\r' => ret += "\\r", '\\' => ret += "\\\\", _ => ret.push(ch) }
+  }
+  ret
+}
+
+fn json_str(s: &str) -> String {
+  let mut ret = String::with_capacity(s.len() + 2);
+  ret.push('"');
+  for ch in s.chars() {
+    match ch {
+      '"' => ret += "\\\"", '\\' => ret += "\\\\", '\n' => ret += "\\n", '\r' => ret += "\\r", '\t' => ret += "\\t",
+      ch if (ch as u32) < 0x20 => ret += &format!("\\u{:04x}", ch as u32),
+      _ => ret.push(ch),
+    }
+  }
+  (ret.push('"'), ret).1
+}
@@ -5,20 +5,73 @@ use common::{*, BareTy::*, CmpOp::*};
 use syntax::ast::*;
 use physics::*;
 use db::Db;
-use index::{Index, handle_all};
+use index::{Index, MultiIndex, handle_all};
 
 // return true for successfully filtered with index
-unsafe fn try_filter_with_index<'a>(db: &mut Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
-                                    pred: &impl Fn(*const u8) -> bool, f: &mut impl FnMut(*mut u8, Rid) -> Result<'a, ()>) -> Result<'a, bool> {
-  let tp = db.get_page::<TablePage>(tp_id);
+// a composite index matching at least 2 cols is preferred, then an index on a single col, then a composite index matching 1 col
+unsafe fn try_filter_with_index<'a>(db: &Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
+                                    pred: &impl Fn(*const u8) -> bool, f: &mut impl FnMut(*const u8, Rid) -> Result<'a, ()>) -> Result<'a, bool> {
+  if try_filter_with_multi(db, where_, tp_id, pred, f, 2)? { return Ok(true); }
+  if try_filter_with_single(db, where_, tp_id, pred, f)? { return Ok(true); }
+  try_filter_with_multi(db, where_, tp_id, pred, f, 1)
+}
+
+// a composite index matches `n` cols if there are `=` conditions on its first `n` cols,
+// or `=` conditions on its first `n - 1` cols and a range condition on the next one
+// the index matching most cols (at least `min_cols`) is used, and records are still checked by `pred`
+unsafe fn try_filter_with_multi<'a>(db: &Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
+                                    pred: &impl Fn(*const u8) -> bool, f: &mut impl FnMut(*const u8, Rid) -> Result<'a, ()>, min_cols: usize) -> Result<'a, bool> {
+  let tp = db.page::<TablePage>(tp_id);
+  if tp.indexes == !0 { return Ok(false); }
+  // the first `=` (if `eq`) or range condition with a non-null literal on the col
+  let cond_on = |ci_id: u16, eq: bool| where_.iter().filter_map(|cond| match cond.borrow() {
+    &Cond::Cmp(op, l, Atom::Lit(r)) if !r.is_null() && op != Ne && (op == Eq) == eq => Some((op, l, r)),
+    _ => None,
+  }).find(|&(_, l, _)| tp.find_ci(l.col).unchecked_unwrap().idx(tp.cols()) == ci_id as u32).map(|(op, _, r)| (op, r));
+  // (position in IndexListPage, `=` literals, the range condition)
+  let mut best = None::<(usize, Vec<CLit>, Option<(CmpOp, CLit)>)>;
+  for (idx, info) in db.page::<IndexListPage>(tp.indexes).indexes().iter().enumerate() {
+    let mut eqs = Vec::new();
+    for &ci_id in info.cols() {
+      match cond_on(ci_id, true) { Some((_, r)) => eqs.push(r), None => break }
+    }
+    let range = info.cols().get(eqs.len()).and_then(|&ci_id| cond_on(ci_id, false));
+    let n = eqs.len() + range.is_some() as usize;
+    if n >= min_cols && best.as_ref().map(|(_, eqs1, range1)| n > eqs1.len() + range1.is_some() as usize).unwrap_or(true) { best = Some((idx, eqs, range)); }
+  }
+  let (idx, mut lits, range) = match best { Some(x) => x, None => return Ok(false) };
+  let index = MultiIndex::new_ref(db, tp_id, idx as u32);
+  let n = lits.len();
+  if let Some((_, r)) = range { lits.push(r); }
+  let key = index.lits_key(&lits);
+  let (mut it, end) = match range.map(|x| x.0) {
+    None => (index.prefix_lower_bound(key.ptr, n), index.prefix_upper_bound(key.ptr, n)),
+    Some(Lt) => (index.prefix_lower_bound(key.ptr, n), index.prefix_lower_bound(key.ptr, n + 1)),
+    Some(Le) => (index.prefix_lower_bound(key.ptr, n), index.prefix_upper_bound(key.ptr, n + 1)),
+    Some(Gt) => (index.prefix_upper_bound(key.ptr, n + 1), index.prefix_upper_bound(key.ptr, n)),
+    Some(Ge) => (index.prefix_lower_bound(key.ptr, n + 1), index.prefix_upper_bound(key.ptr, n)),
+    _ => impossible!(),
+  };
+  // a null in the range col is less than any value, so it is in the range of `<` or `<=`, and is filtered by `pred`
+  while it != end {
+    let rid = it.next().unchecked_unwrap();
+    let ptr = db.data_slot(tp, rid);
+    if pred(ptr) { f(ptr, rid)?; }
+  }
+  Ok(true)
+}
+
+unsafe fn try_filter_with_single<'a>(db: &Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
+                                     pred: &impl Fn(*const u8) -> bool, f: &mut impl FnMut(*const u8, Rid) -> Result<'a, ()>) -> Result<'a, bool> {
+  let tp = db.page::<TablePage>(tp_id);
   for cond in where_ {
     if let &Cond::Cmp(op, l, Atom::Lit(r)) = cond.borrow() {
       match r.lit() {
         Lit::Null => {}
         _ => {
           // safe because `one_predicate` have verified the name
-          let ci = tp.pr().get_ci(l.col).unchecked_unwrap();
-          let ci_id = ci.idx(&tp.cols);
+          let ci = tp.find_ci(l.col).unchecked_unwrap();
+          let ci_id = ci.idx(tp.cols());
           if ci.index != !0 {
             let buf = Align4U8::new(ci.ty.size() as usize);
             let is_only_pred = where_.len() == 1;
@@ -26,7 +79,7 @@ unsafe fn try_filter_with_index<'a>(db: &mut Db, where_: &[impl Borrow<Cond<'a>>
             db.lit2ptr(buf.ptr, ci.ty.fix_ty(), r).unchecked_unwrap();
             macro_rules! handle {
               ($ty: ident) => {{
-                let mut index = Index::<{ $ty }>::new(db, tp_id, ci_id);
+                let index = Index::<{ $ty }>::new_ref(db, tp_id, ci_id);
                 match op {
                   Lt | Le | Eq => {
                     let (mut it, end) = match op {
@@ -37,14 +90,14 @@ unsafe fn try_filter_with_index<'a>(db: &mut Db, where_: &[impl Borrow<Cond<'a>>
                     };
                     while it != end {
                       let rid = it.next().unchecked_unwrap();
-                      let ptr = db.get_data_slot(tp, rid);
+                      let ptr = db.data_slot(tp, rid);
                       if is_only_pred || pred(ptr) { f(ptr, rid)?; }
                     }
                   },
                   Ge | Gt => {
                     let mut it = if op == Ge { index.lower_bound(buf.ptr) } else { index.upper_bound(buf.ptr) };
                     while let Some(rid) = it.next() {
-                      let ptr = db.get_data_slot(tp, rid);
+                      let ptr = db.data_slot(tp, rid);
                       if is_only_pred || pred(ptr) { f(ptr, rid)?; }
                     }
                   },
@@ -62,15 +115,17 @@ unsafe fn try_filter_with_index<'a>(db: &mut Db, where_: &[impl Borrow<Cond<'a>>
   Ok(false)
 }
 
-// guarantee the `*mut u8` passed to f only comes from DataPage, not from IndexPage
+// guarantee the `*const u8` passed to f only comes from DataPage, not from IndexPage
+// pages are only read here, to modify the data slot, get it again by `get_data_slot`
 // if you want to modify index while iterating, you CANNOT modify while iterating, remember to set `use_index` = false
-// if you want to delete the current data slot from data page while iterating, you CAN delete while iterating (due to the implementation)
-pub(crate) unsafe fn filter<'a>(db: &mut Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
-                                pred: impl Fn(*const u8) -> bool, mut f: impl FnMut(*mut u8, Rid) -> Result<'a, ()>,
+// the matched records are collected before calling f, so f can modify or delete them
+pub(crate) unsafe fn filter<'a>(db: &Db, where_: &[impl Borrow<Cond<'a>>], tp_id: u32,
+                                pred: impl Fn(*const u8) -> bool, mut f: impl FnMut(*const u8, Rid) -> Result<'a, ()>,
                                 use_index: bool) -> Result<'a, ()> {
   if !use_index || !try_filter_with_index(db, where_, tp_id, &pred, &mut f)? {
-    let tp = db.get_page::<TablePage>(tp_id);
-    for (data, rid) in db.record_iter(tp) { if pred(data) { f(data, rid)?; } }
+    let tp = db.page::<TablePage>(tp_id);
+    let rows = db.record_iter(tp).filter(|&(data, _)| pred(data)).collect::<Vec<_>>();
+    for (data, rid) in rows { f(data, rid)?; }
   }
   Ok(())
 }
\ No newline at end of file
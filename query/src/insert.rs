@@ -4,8 +4,9 @@ use std::{borrow::Cow::{self, *}, cmp::Ordering::*};
 use common::{*, BareTy::*, Error::*};
 use syntax::ast::*;
 use physics::*;
-use index::{Index, cmp::Cmp, handle_all};
-use db::{Db, is_null, hash_pks};
+use index::{Index, MultiIndex, cmp::Cmp, insert_multi, primary_multi_index, handle_all};
+use db::{Db, Unzipped, is_null};
+use crate::rolled_back;
 
 // update can also use this
 pub(crate) struct InsertCtx<'a> {
@@ -13,35 +14,38 @@ pub(crate) struct InsertCtx<'a> {
   pub(crate) tp_id: u32,
   pub(crate) tp: &'a mut TablePage,
   pub(crate) pks: Vec<&'a ColInfo>,
-  pub(crate) pk_set: HashSet<u128>,
+  // the position of the index of the primary key in IndexListPage, if the primary key has more than one col
+  pub(crate) pk_index: Option<u32>,
   // these 2 not used in update (it may be a little waste, but is acceptable)
   cols: Option<Box<[u32]>>,
   dfts: Box<[CLit<'a>]>,
+  // compressed default values in `dfts` refer to it
+  _dfts_unzipped: Unzipped,
 }
 
 impl<'a> InsertCtx<'a> {
   pub(crate) unsafe fn new<'b>(db: &mut Db, table: &'b str, cols: Option<&[&'b str]>) -> Result<'b, InsertCtx<'a>> {
     let (tp_id, tp) = db.get_tp(table)?;
+    // indexes of referenced tables are read in `check_col`
+    for ci in tp.cols() { if ci.f_table != !0 { db.verify_table(ci.f_table)?; } }
     let pks = tp.primary_cols().collect::<Vec<_>>();
-    let pk_set: HashSet<_> = if pks.len() > 1 {
-      db.record_iter(tp).map(|(data, _)| hash_pks(data, &pks)).collect()
-    } else { HashSet::default() }; // no need to collect
+    let pk_index = if pks.len() > 1 { Some(primary_multi_index(db, tp_id).unchecked_unwrap()) } else { None };
     let cols = if let Some(cols1) = cols {
       let mut cols = vec![0; cols1.len()].into_boxed_slice();
       for (idx, c) in cols1.iter().enumerate() {
-        *cols.get_unchecked_mut(idx) = tp.get_ci(c)?.idx(&tp.cols);
+        *cols.get_unchecked_mut(idx) = tp.get_ci(c)?.idx(tp.cols());
       }
       Some(cols)
     } else { None };
-    let mut dfts = vec![CLit::new(Lit::Null); tp.col_num as usize].into_boxed_slice();
+    let (mut dfts, z) = (vec![CLit::new(Lit::Null); tp.col_num as usize].into_boxed_slice(), Unzipped::default());
     for (idx, ci) in tp.cols().iter().enumerate() {
       if ci.check != !0 && ((ci.check & 1) == 1) {
         let cp = db.get_page::<CheckPage>(ci.check >> 1);
         let ptr = cp.data.as_ptr().add(cp.count as usize * ci.ty.size() as usize); // the one-past-last slot
-        *dfts.get_unchecked_mut(idx) = db.ptr2lit(ptr, ci.ty);
+        *dfts.get_unchecked_mut(idx) = db.ptr2lit(ptr, ci.ty, &z);
       }
     }
-    Ok(InsertCtx { db: db.pr(), tp, tp_id, pks, pk_set, cols, dfts })
+    Ok(InsertCtx { db: db.pr(), tp, tp_id, pks, pk_index, cols, dfts, _dfts_unzipped: z })
   }
 
   // result's len == table's col num
@@ -66,11 +70,11 @@ impl<'a> InsertCtx<'a> {
     }
   }
 
-  unsafe fn insert(&mut self, buf: *mut u8, vals: &[CLit<'a>]) -> Result<'a, ()> {
+  pub(crate) unsafe fn insert(&mut self, buf: *mut u8, vals: &[CLit<'a>]) -> Result<'a, ()> {
     let vals = self.get_insert_val(vals)?;
     (buf as *mut u32).write_bytes(0, (vals.len() + 31) / 32); // clear null-bitset
     for (ci_id, &val) in vals.iter().enumerate() {
-      let ci = self.tp.cols.get_unchecked(ci_id);
+      let ci = self.tp.cols().get_unchecked(ci_id);
       if val.is_null() {
         if ci.flags.intersects(ColFlags::NOTNULL1) { return Err(PutNullOnNotNull); }
         bsset(buf as *mut u32, ci_id);
@@ -81,20 +85,20 @@ impl<'a> InsertCtx<'a> {
     for ci_id in 0..self.tp.col_num as u32 {
       self.check_col(buf, ci_id, *vals.get_unchecked(ci_id as usize), None)?;
     }
-    if self.pks.len() > 1 {
-      if !self.pk_set.insert(hash_pks(buf, &self.pks)) { return Err(PutDupOnPrimary); }
+    if let Some(idx) = self.pk_index {
+      if MultiIndex::new_ref(self.db, self.tp_id, idx).has_dup(buf, None) { return Err(PutDupOnPrimary); }
     }
     // now fill varchar fields, unlike non-varchar fields:
-    // 1. they never affect the result of `check_col` and `pk_set`
+    // 1. they never affect the result of `check_col` and the primary key check
     // 2. if one varchar field is written, the whole insertion must succeed (otherwise need to deallocate the space, which is not handled currently)
     for (ci_id, &val) in vals.iter().enumerate() {
-      if !val.is_null() { Db::varchar_ck(self.tp.cols.get_unchecked(ci_id).ty, val)?; }
+      if !val.is_null() { Db::varchar_ck(self.tp.cols().get_unchecked(ci_id).ty, val)?; }
     }
     // now no error can occur
     for (ci_id, &val) in vals.iter().enumerate() {
-      let ci = self.tp.cols.get_unchecked(ci_id);
+      let ci = self.tp.cols().get_unchecked(ci_id);
       if !val.is_null() && ci.ty.is_varchar() {
-        match val.lit() { Lit::Str(s) => self.db.lit2varchar(buf.add(ci.off as usize), s, false), _ => impossible!() }
+        self.db.lit2varchar(buf.add(ci.off as usize), ci, val.lit().bytes(), false);
       }
     }
     self.tp.count += 1;
@@ -112,6 +116,7 @@ impl<'a> InsertCtx<'a> {
         handle_all!(ci.ty.fix_ty().ty, handle);
       }
     }
+    insert_multi(self.db, self.tp_id, buf, rid);
     Ok(())
   }
 
@@ -121,12 +126,12 @@ impl<'a> InsertCtx<'a> {
   pub(crate) unsafe fn check_col(&mut self, data: *const u8, ci_id: u32, val: CLit<'a>, rid: Option<Rid>) -> Result<'a, ()> {
     // unique / foreign / `check` check, null item doesn't need them (null check is in `fill_buf`)
     if !is_null(data, ci_id) {
-      let ci = self.tp.cols.get_unchecked(ci_id as usize);
+      let ci = self.tp.cols().get_unchecked(ci_id as usize);
       let ptr = data.add(ci.off as usize);
       if ci.unique(self.pks.len()) {
         macro_rules! handle {
           ($ty: ident) => {{
-            let mut index = Index::<{ $ty }>::new(self.db, self.tp_id, ci_id);
+            let index = Index::<{ $ty }>::new_ref(self.db, self.tp_id, ci_id);
             let (mut it, end) = (index.lower_bound(ptr), index.upper_bound(ptr));
             while it != end {
               if rid != Some(it.next().unchecked_unwrap()) { return Err(PutDupOnUnique { col: ci.name(), val }); }
@@ -138,7 +143,7 @@ impl<'a> InsertCtx<'a> {
       if ci.f_table != !0 {
         macro_rules! handle {
           ($ty: ident) => {{
-            if !Index::<{ $ty }>::new(self.db, ci.f_table, ci.f_col as u32).contains(ptr) { return Err(PutNonexistentForeign { col: ci.name(), val }); }
+            if !Index::<{ $ty }>::new_ref(self.db, ci.f_table, ci.f_col as u32).contains(ptr) { return Err(PutNonexistentForeign { col: ci.name(), val }); }
           }};
         }
         handle_all!(ci.ty.fix_ty().ty, handle); // their type are exactly the same, so can use `ptr` directly to search in index, `create_table` guarantee this
@@ -157,15 +162,12 @@ impl<'a> InsertCtx<'a> {
   }
 }
 
-pub fn insert<'a>(i: &Insert<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
+// it should run in `Db::atomic` (as the driver does), so that if any row fails, no row is inserted
+pub fn insert<'a>(i: &Insert<'a>, db: &mut Db) -> Result<'a, u32> {
   unsafe {
     let mut ctx = InsertCtx::new(db, i.table, i.cols.as_deref())?;
     let buf = Align4U8::new(ctx.tp.size as usize);
-    let mut cnt = 0;
-    for vals in &i.vals {
-      if let Err(e) = ctx.insert(buf.ptr, vals) { return Err(ModifyError(cnt, e)); }
-      cnt += 1;
-    }
-    Ok(cnt)
+    for (cnt, vals) in i.vals.iter().enumerate() { ctx.insert(buf.ptr, vals).map_err(|e| rolled_back(e, cnt as u32))?; }
+    Ok(i.vals.len() as u32)
   }
 }
\ No newline at end of file
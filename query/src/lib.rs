@@ -5,26 +5,31 @@ pub mod insert;
 pub mod delete;
 pub mod select;
 pub mod update;
+pub mod copy;
+pub mod export;
 mod predicate;
 mod filter;
 
-pub use crate::{insert::*, delete::*, select::*, update::*};
+pub use crate::{insert::*, delete::*, select::*, update::*, copy::*, export::*};
 
-use db::{Db, is_null};
+use db::{Db, Unzipped, is_null};
 use physics::*;
 use index::{Index, handle_all};
 use common::{*, Error::*, BareTy::*};
 
-// return Err if there is a foreign link to `data`
-unsafe fn check_foreign_link<'a>(db: &Db, tp: &TablePage, data: *const u8, f_links: &[(u32, u8, u8)]) -> Result<'a, ()> {
-  let db = db.pr();
+// the error of a statement that failed after `cnt` rows were changed, these changes are undone by the caller (see `Db::atomic`)
+fn rolled_back(e: Error, cnt: u32) -> Error { if cnt == 0 { e } else { RolledBack(box e) } }
+
+// return Err if there is a foreign link to `data`, the tables in `f_links` should have been verified by `verify_table`
+unsafe fn check_foreign_link<'a>(db: &Db, tp: &TablePage, data: *const u8, f_links: &[(u32, u16, u16)]) -> Result<'a, ()> {
   for &(tp_id1, ci_id1, ci_id) in f_links {
-    let ci = tp.cols.get_unchecked(ci_id as usize);
+    let ci = tp.cols().get_unchecked(ci_id as usize);
     let ptr = data.add(ci.off as usize);
     macro_rules! handle {
       ($ty: ident) => {{
-        if !is_null(data, ci_id as u32) && Index::<{ $ty }>::new(db, tp_id1, ci_id1 as u32).contains(ptr) {
-          return Err(ModifyColWithForeignLink { col: ci.name(), val: db.ptr2lit(ptr, ci.ty) });
+        if !is_null(data, ci_id as u32) && Index::<{ $ty }>::new_ref(db, tp_id1, ci_id1 as u32).contains(ptr) {
+          // `ci` is referenced by a foreign link, so it is not a varchar and `val` doesn't refer to the temporary `Unzipped`
+          return Err(ModifyColWithForeignLink { col: ci.name(), val: db.ptr2lit(ptr, ci.ty, &Unzipped::default()) });
         }
       }};
     }
@@ -3,7 +3,7 @@ use chrono::NaiveDate;
 use common::{*, Error::*, BareTy::*, CmpOp::*};
 use syntax::ast::*;
 use physics::*;
-use db::{is_null, Db};
+use db::{is_null, Db, Unzipped};
 
 macro_rules! handle_op {
   ($cmp: ident, $op:expr, $p: ident, $l: expr, $r: expr) => {
@@ -14,14 +14,15 @@ macro_rules! handle_op {
   };
 }
 
+// a compressed varchar or a text / blob in more than one extent is copied into a temporary `Unzipped`, which is dropped when the predicate returns
+
 // the pointer from IndexPage cannot be passed to predicate!
 // It is just the data ptr, but all these predicate accept the pointer to the beginning of the whole data slot
 
 // assume both lhs and rhs belongs to tp's table, so ColRef::table is not checked
 pub unsafe fn one_predicate<'a, 'b>(db: &'a Db, e: &Cond<'b>, tp: &TablePage) -> Result<'b, Box<dyn Fn(*const u8) -> bool + 'a>> {
-  let tp = tp.pr();
-  let l = tp.get_ci(e.lhs_col().col)?;
-  let l_id = l.idx(&tp.cols) as u8; // reduce the size of lambda closure, do conversion inside lambda
+  let l = tp.find_ci(e.lhs_col().col)?;
+  let l_id = l.idx(tp.cols()) as u16; // reduce the size of lambda closure, do conversion inside lambda
   let l_off = l.off;
   match *e {
     Cond::Cmp(op, _, r) => match r {
@@ -45,14 +46,22 @@ pub unsafe fn one_predicate<'a, 'b>(db: &'a Db, e: &Cond<'b>, tp: &TablePage) ->
           }
           (varchar!(), Lit::Str(v)) => {
             let v = Box::<str>::from(v);
-            handle_op!(cmp, op, p, db.varchar(p.add(l_off as _)), v.as_ref())
+            handle_op!(cmp, op, p, db.varchar(p.add(l_off as _), &Unzipped::default()), v.as_ref())
+          }
+          (ColTy::Text, Lit::Str(v)) => {
+            let v = Box::<str>::from(v);
+            handle_op!(cmp, op, p, db.text(p.add(l_off as _), &Unzipped::default()), v.as_ref())
+          }
+          (ColTy::Blob, Lit::Blob(v)) => {
+            let v = Box::<[u8]>::from(v);
+            handle_op!(cmp, op, p, db.blob(p.add(l_off as _), &Unzipped::default()), v.as_ref())
           }
           _ => return Err(ColLitMismatch { ty: l.ty, val: r })
         }
       }
       Atom::ColRef(r) => {
-        let r = tp.get_ci(r.col)?;
-        let r_id = r.idx(&tp.cols) as u16;
+        let r = tp.find_ci(r.col)?;
+        let r_id = r.idx(tp.cols()) as u16;
         let r_off = r.off;
         macro_rules! cmp {
           ($op: tt, $p: ident, $l: expr, $r: expr) => { Ok(box move |$p| !is_null($p, l_id as u32) && !is_null($p, r_id as u32) && $l $op $r) };
@@ -65,9 +74,15 @@ pub unsafe fn one_predicate<'a, 'b>(db: &'a Db, e: &Cond<'b>, tp: &TablePage) ->
           (float!(), int!()) => handle_op!(cmp, op, p, *(p.add(l_off as _) as *const f32), *(p.add(r_off as _) as *const i32) as f32),
           (date!(), date!()) => handle_op!(cmp, op, p, *(p.add(l_off as _) as *const NaiveDate), *(p.add(r_off as _) as *const NaiveDate)),
           (char!(), char!()) => handle_op!(cmp, op, p, str_from_db(p.add(l_off as _)), str_from_db(p.add(r_off as _))),
-          (char!(), varchar!()) => handle_op!(cmp, op, p, str_from_db(p.add(l_off as _)), db.varchar(p.add(r_off as _))),
-          (varchar!(), char!()) => handle_op!(cmp, op, p, db.varchar(p.add(l_off as _)), str_from_db(p.add(r_off as _))),
-          (varchar!(), varchar!()) => handle_op!(cmp, op, p, db.varchar(p.add(l_off as _)), db.varchar(p.add(r_off as _))),
+          (char!(), varchar!()) => handle_op!(cmp, op, p, str_from_db(p.add(l_off as _)), db.varchar(p.add(r_off as _), &Unzipped::default())),
+          (varchar!(), char!()) => handle_op!(cmp, op, p, db.varchar(p.add(l_off as _), &Unzipped::default()), str_from_db(p.add(r_off as _))),
+          (varchar!(), varchar!()) => handle_op!(cmp, op, p, db.varchar(p.add(l_off as _), &Unzipped::default()), db.varchar(p.add(r_off as _), &Unzipped::default())),
+          (char!(), ColTy::Text) => handle_op!(cmp, op, p, str_from_db(p.add(l_off as _)), db.text(p.add(r_off as _), &Unzipped::default())),
+          (ColTy::Text, char!()) => handle_op!(cmp, op, p, db.text(p.add(l_off as _), &Unzipped::default()), str_from_db(p.add(r_off as _))),
+          (varchar!(), ColTy::Text) => handle_op!(cmp, op, p, db.varchar(p.add(l_off as _), &Unzipped::default()), db.text(p.add(r_off as _), &Unzipped::default())),
+          (ColTy::Text, varchar!()) => handle_op!(cmp, op, p, db.text(p.add(l_off as _), &Unzipped::default()), db.varchar(p.add(r_off as _), &Unzipped::default())),
+          (ColTy::Text, ColTy::Text) => handle_op!(cmp, op, p, db.text(p.add(l_off as _), &Unzipped::default()), db.text(p.add(r_off as _), &Unzipped::default())),
+          (ColTy::Blob, ColTy::Blob) => handle_op!(cmp, op, p, db.blob(p.add(l_off as _), &Unzipped::default()), db.blob(p.add(r_off as _), &Unzipped::default())),
           (l, r) => return Err(ColMismatch { l, r })
         }
       }
@@ -77,7 +92,8 @@ pub unsafe fn one_predicate<'a, 'b>(db: &'a Db, e: &Cond<'b>, tp: &TablePage) ->
       let re = db::like2re(like)?;
       match l.ty {
         char!() => Ok(box move |p| !is_null(p, l_id as u32) && re.is_match(str_from_db(p.add(l_off as _)))),
-        varchar!() => Ok(box move |p| !is_null(p, l_id as u32) && re.is_match(db.varchar(p.add(l_off as _)))),
+        varchar!() => Ok(box move |p| !is_null(p, l_id as u32) && re.is_match(db.varchar(p.add(l_off as _), &Unzipped::default()))),
+        ColTy::Text => Ok(box move |p| !is_null(p, l_id as u32) && re.is_match(db.text(p.add(l_off as _), &Unzipped::default()))),
         _ => Err(InvalidLikeTy(l.ty))
       }
     }
@@ -100,9 +116,15 @@ pub unsafe fn cross_predicate<'a, 'b>(db: &'a Db, op: CmpOp, col: (&ColInfo, &Co
     (float!(), int!()) => handle_op!(cmp, op, p, *(p.0.add(l_off as _) as *const f32), *(p.1.add(r_off as _) as *const i32) as f32),
     (date!(), date!()) => handle_op!(cmp, op, p, *(p.0.add(l_off as _) as *const NaiveDate), *(p.1.add(r_off as _) as *const NaiveDate)),
     (char!(), char!()) => handle_op!(cmp, op, p, str_from_db(p.0.add(l_off as _)), str_from_db(p.1.add(r_off as _))),
-    (char!(), varchar!()) => handle_op!(cmp, op, p, str_from_db(p.0.add(l_off as _)), db.varchar(p.1.add(r_off as _))),
-    (varchar!(), char!()) => handle_op!(cmp, op, p, db.varchar(p.0.add(l_off as _)), str_from_db(p.1.add(r_off as _))),
-    (varchar!(), varchar!()) => handle_op!(cmp, op, p, db.varchar(p.0.add(l_off as _)), db.varchar(p.1.add(r_off as _))),
+    (char!(), varchar!()) => handle_op!(cmp, op, p, str_from_db(p.0.add(l_off as _)), db.varchar(p.1.add(r_off as _), &Unzipped::default())),
+    (varchar!(), char!()) => handle_op!(cmp, op, p, db.varchar(p.0.add(l_off as _), &Unzipped::default()), str_from_db(p.1.add(r_off as _))),
+    (varchar!(), varchar!()) => handle_op!(cmp, op, p, db.varchar(p.0.add(l_off as _), &Unzipped::default()), db.varchar(p.1.add(r_off as _), &Unzipped::default())),
+    (char!(), ColTy::Text) => handle_op!(cmp, op, p, str_from_db(p.0.add(l_off as _)), db.text(p.1.add(r_off as _), &Unzipped::default())),
+    (ColTy::Text, char!()) => handle_op!(cmp, op, p, db.text(p.0.add(l_off as _), &Unzipped::default()), str_from_db(p.1.add(r_off as _))),
+    (varchar!(), ColTy::Text) => handle_op!(cmp, op, p, db.varchar(p.0.add(l_off as _), &Unzipped::default()), db.text(p.1.add(r_off as _), &Unzipped::default())),
+    (ColTy::Text, varchar!()) => handle_op!(cmp, op, p, db.text(p.0.add(l_off as _), &Unzipped::default()), db.varchar(p.1.add(r_off as _), &Unzipped::default())),
+    (ColTy::Text, ColTy::Text) => handle_op!(cmp, op, p, db.text(p.0.add(l_off as _), &Unzipped::default()), db.text(p.1.add(r_off as _), &Unzipped::default())),
+    (ColTy::Blob, ColTy::Blob) => handle_op!(cmp, op, p, db.blob(p.0.add(l_off as _), &Unzipped::default()), db.blob(p.1.add(r_off as _), &Unzipped::default())),
     (l, r) => return Err(ColMismatch { l, r })
   }
 }
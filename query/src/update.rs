@@ -5,9 +5,9 @@ use std::cmp::Ordering::*;
 use common::{*, Error::*, BinOp::*, CmpOp::*, BareTy::*};
 use syntax::ast::*;
 use physics::*;
-use db::{Db, is_null, hash_pks};
-use index::{Index, handle_all};
-use crate::{predicate::one_where, filter::filter, check_foreign_link, InsertCtx};
+use db::{Db, Unzipped, is_null};
+use index::{Index, MultiIndex, update_multi, handle_all};
+use crate::{predicate::one_where, filter::filter, check_foreign_link, rolled_back, InsertCtx};
 
 unsafe fn check<'a>(e: &Expr<'a>, tp: &mut TablePage, re_cache: &mut HashMap<&'a str, Regex>) -> Result<'a, LitTy> {
   match e {
@@ -18,7 +18,8 @@ unsafe fn check<'a>(e: &Expr<'a>, tp: &mut TablePage, re_cache: &mut HashMap<&'a
         let ci = tp.get_ci(col.col)?;
         match ci.ty {
           ColTy::FixTy(ty) => match ty.ty { Bool => LitTy::Bool, Int | Float => LitTy::Number, Date => LitTy::Date, Char => LitTy::Str },
-          varchar!() => LitTy::Str,
+          varchar!() | ColTy::Text => LitTy::Str,
+          ColTy::Blob => LitTy::Blob,
         }
       }
     }),
@@ -48,51 +49,53 @@ unsafe fn check<'a>(e: &Expr<'a>, tp: &mut TablePage, re_cache: &mut HashMap<&'a
 // if one of the operand is null, the result is null (including comparison, e.g., (null = null) evaluates to null, instead of false in select)
 // the only exception is "is (not) null" check, it always return bool
 // if arithmetic result is NaN, the result is null
-unsafe fn eval<'a>(db: &Db, e: &Expr<'a>, tp: &mut TablePage, data: *const u8, re_cache: &HashMap<&'a str, Regex>) -> Lit<'a> {
+unsafe fn eval<'a>(db: &Db, e: &Expr<'a>, tp: &mut TablePage, data: *const u8, re_cache: &HashMap<&'a str, Regex>, z: &Unzipped) -> Lit<'a> {
   match e {
     Expr::Atom(x) => match x {
       Atom::Lit(x) => *x,
       Atom::ColRef(col) => {
         let ci = tp.get_ci(col.col).unchecked_unwrap();
-        let ci_id = ci.idx(&tp.cols);
-        db.data2lit(data, ci_id, ci)
+        let ci_id = ci.idx(tp.cols());
+        db.data2lit(data, ci_id, ci, z)
       }
     }.lit(),
-    Expr::Null(x, null) => Lit::Bool(eval(db, x, tp, data, re_cache).is_null() == *null),
+    Expr::Null(x, null) => Lit::Bool(eval(db, x, tp, data, re_cache, z).is_null() == *null),
     Expr::Like(x, like) => {
       let re = re_cache.get(like).unchecked_unwrap();
-      let x = match eval(db, x, tp, data, re_cache) { Lit::Str(x) => x, _ => return Lit::Null };
+      let x = match eval(db, x, tp, data, re_cache, z) { Lit::Str(x) => x, _ => return Lit::Null };
       Lit::Bool(re.is_match(x))
     }
     Expr::And(box (l, r)) | Expr::Or(box (l, r)) => {
       let or = if let Expr::Or(_) = e { true } else { false };
-      let l = match eval(db, l, tp, data, re_cache) { Lit::Bool(x) => x, _ => return Lit::Null };
+      let l = match eval(db, l, tp, data, re_cache, z) { Lit::Bool(x) => x, _ => return Lit::Null };
       if or == l { return Lit::Bool(l); } // short circuit, true or _ / false and _
       // now it is false or _ / true and _, the result only depends on `r`
-      let r = match eval(db, r, tp, data, re_cache) { Lit::Bool(x) => x, _ => return Lit::Null };
+      let r = match eval(db, r, tp, data, re_cache, z) { Lit::Bool(x) => x, _ => return Lit::Null };
       Lit::Bool(r)
     }
     Expr::Cmp(op, box (l, r)) => {
-      let l = eval(db, l, tp, data, re_cache);
-      let r = eval(db, r, tp, data, re_cache);
+      let l = eval(db, l, tp, data, re_cache, z);
+      let r = eval(db, r, tp, data, re_cache, z);
       if l.is_null() || r.is_null() { return Lit::Null; };
       let cmp = l.cmp(&r); // `check` and null check above guarantees they have the same type
       Lit::Bool(match op { Lt => cmp == Less, Le => cmp != Greater, Ge => cmp != Less, Gt => cmp == Greater, Eq => cmp == Equal, Ne => cmp != Equal })
     }
     Expr::Bin(op, box (l, r)) => {
       // since we cannot have type mismatch here, if it is not Number, it can only be Null
-      let l = match eval(db, l, tp, data, re_cache) { Lit::Number(x) => x, _ => return Lit::Null };
-      let r = match eval(db, r, tp, data, re_cache) { Lit::Number(x) => x, _ => return Lit::Null };
+      let l = match eval(db, l, tp, data, re_cache, z) { Lit::Number(x) => x, _ => return Lit::Null };
+      let r = match eval(db, r, tp, data, re_cache, z) { Lit::Number(x) => x, _ => return Lit::Null };
       let res = match op { Add => l + r, Sub => l - r, Mul => l * r, Div => l / r, Mod => l % r, };
       if res.is_nan() { Lit::Null } else { Lit::Number(res) }
     }
   }
 }
 
-pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
+// like `insert`, it relies on the caller's `Db::atomic` to undo the rows updated before a failed one
+pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> Result<'a, u32> {
   unsafe {
     let mut ctx = InsertCtx::new(db, u.table, None)?;
     let f_links = db.foreign_links_to(ctx.tp_id).collect::<Vec<_>>();
+    for &(tp_id1, _, _) in &f_links { db.verify_table(tp_id1)?; }
     let pred = one_where(db.pr(), &u.where_, ctx.tp)?;
     let mut re_cache = HashMap::default();
     let mut cols = Vec::with_capacity(u.sets.len());
@@ -103,14 +106,15 @@ pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
     }
     let slot_size = ctx.tp.size as usize;
     let buf = Align4U8::new(slot_size); // update to buf, then copy to db
-    let mut cnt = 0;
-    if let Err(e) = filter(db.pr(), &u.where_, ctx.tp_id, pred, |data, rid| {
+    let (mut cnt, mut z) = (0, Unzipped::default());
+    filter(db.pr(), &u.where_, ctx.tp_id, pred, |data, rid| {
+      z.reset(); // values decompressed for the previous row are already written back
       check_foreign_link(db, ctx.tp, data, &f_links)?;
       buf.ptr.copy_from_nonoverlapping(data, slot_size);
       for (idx, (_, e)) in u.sets.iter().enumerate() {
         let ci = *cols.get_unchecked(idx);
-        let ci_id = ci.idx(&ctx.tp.cols);
-        let val = CLit::new(eval(db, e, ctx.tp, data, &re_cache));
+        let ci_id = ci.idx(ctx.tp.cols());
+        let val = CLit::new(eval(db, e, ctx.tp, data, &re_cache, &z));
         *vals.get_unchecked_mut(idx) = val;
         if val.is_null() {
           if ci.flags.intersects(ColFlags::NOTNULL1) { return Err(PutNullOnNotNull); }
@@ -121,9 +125,8 @@ pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
         }
         ctx.check_col(buf.ptr, ci_id, val, Some(rid))?; // it won't conflict with the old value (`data`)
       }
-      if ctx.pks.len() > 1 {
-        ctx.pk_set.remove(&hash_pks(data, &ctx.pks));
-        if !ctx.pk_set.insert(hash_pks(buf.ptr, &ctx.pks)) { return Err(PutDupOnPrimary); }
+      if let Some(idx) = ctx.pk_index {
+        if MultiIndex::new_ref(db, ctx.tp_id, idx).has_dup(buf.ptr, Some(rid)) { return Err(PutDupOnPrimary); }
       }
       for (idx, &val) in vals.iter().enumerate() {
         if !val.is_null() { Db::varchar_ck(cols.get_unchecked(idx).ty, val)?; }
@@ -131,20 +134,19 @@ pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
       // now no error can occur
       for (idx, &val) in vals.iter().enumerate() {
         let ci = *cols.get_unchecked(idx);
-        let ci_id = ci.idx(&ctx.tp.cols);
+        let ci_id = ci.idx(ctx.tp.cols());
         if ci.ty.is_varchar() {
           let ptr = buf.ptr.add(ci.off as usize);
           let initialized = !is_null(data, ci_id); // this is the old value, null-bitset in new value (buf.ptr) is already set
           match val.lit() {
-            Lit::Null => if initialized { db.free_varchar(ptr); }
-            Lit::Str(s) => db.lit2varchar(ptr, s, true),
-            _ => impossible!()
+            Lit::Null => if initialized { db.free_varchar(ptr, ci.ty); }
+            val => db.lit2varchar(ptr, ci, val.bytes(), initialized),
           }
         }
       }
       for (col, _) in &u.sets {
         let ci = ctx.tp.get_ci(col).unchecked_unwrap();
-        let ci_id = ci.idx(&ctx.tp.cols);
+        let ci_id = ci.idx(ctx.tp.cols());
         if ci.index != !0 && !is_null(buf.ptr, ci_id) {
           let old = data.add(ci.off as usize);
           let new = buf.ptr.add(ci.off as usize);
@@ -158,9 +160,11 @@ pub fn update<'a>(u: &Update<'a>, db: &mut Db) -> ModifyResult<'a, u32> {
           handle_all!(ci.ty.fix_ty().ty, handle);
         }
       }
-      data.copy_from_nonoverlapping(buf.ptr, slot_size);
+      update_multi(db, ctx.tp_id, data, buf.ptr, rid);
+      db.get_data_slot(ctx.tp, rid).copy_from_nonoverlapping(buf.ptr, slot_size);
       cnt += 1;
       Ok(())
-    }, false) { Err(ModifyError(cnt, e)) } else { Ok(cnt) }
+    }, false).map_err(|e| rolled_back(e, cnt))?;
+    Ok(cnt)
   }
 }
\ No newline at end of file
@@ -16,6 +16,8 @@ pub enum Stmt<'a> {
   DropTable(&'a str),
   ShowTable(&'a str),
   ShowTables,
+  // free space statistics of the lob file of the database in use
+  ShowLob,
   #[from] CreateIndex(CreateIndex<'a>),
   DropIndex {
     index: &'a str,
@@ -30,6 +32,22 @@ pub enum Stmt<'a> {
   DropPrimary { table: &'a str, cols: Vec<&'a str> },
   AddCol { table: &'a str, col: ColDecl<'a> },
   DropCol { table: &'a str, col: &'a str },
+  Begin,
+  Commit,
+  Rollback,
+  Checkpoint,
+  // None for all tables
+  Vacuum(Option<&'a str>),
+  // move all varchars to the beginning of the lob file and truncate it, `Vacuum(None)` also does this
+  CompactLob,
+  // check the database in use, and repair it if `.0` is true
+  CheckDb(bool),
+  // copy the database in use to the path
+  Backup(&'a str),
+  // a sql script which rebuilds the database in use
+  Dump,
+  #[from] CopyFrom(CopyFrom<'a>),
+  #[from] CopyTo(CopyTo<'a>),
 }
 
 #[derive(Debug)]
@@ -39,6 +57,40 @@ pub struct Insert<'a> {
   pub vals: Vec<Vec<CLit<'a>>>,
 }
 
+// insert the records in a csv file
+#[derive(Debug)]
+pub struct CopyFrom<'a> {
+  pub table: &'a str,
+  pub cols: Option<Vec<&'a str>>,
+  pub path: &'a str,
+  pub opts: CopyOpts<'a>,
+}
+
+#[derive(Debug)]
+pub struct CopyOpts<'a> {
+  // the first record is skipped
+  pub header: bool,
+  // always an ascii character other than '"', '\r' and '\n'
+  pub delimiter: u8,
+  // an unquoted field equal to it is null, a quoted one is never null
+  pub null: &'a str,
+}
+
+impl Default for CopyOpts<'_> {
+  fn default() -> Self { CopyOpts { header: false, delimiter: b',', null: "" } }
+}
+
+// write the result of a select to a file
+#[derive(Debug)]
+pub struct CopyTo<'a> {
+  pub select: Select<'a>,
+  pub path: &'a str,
+  pub format: CopyFormat,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CopyFormat { Csv, Jsonl, Tsv }
+
 #[derive(Debug)]
 pub struct Update<'a> {
   pub table: &'a str,
@@ -83,7 +135,8 @@ pub struct CreateTable<'a> {
 pub struct CreateIndex<'a> {
   pub index: &'a str,
   pub table: &'a str,
-  pub col: &'a str,
+  // an index on more than one col is a composite index, see `IndexListPage`
+  pub cols: Vec<&'a str>,
 }
 
 #[derive(Debug)]
@@ -100,6 +153,8 @@ pub struct ColDecl<'a> {
   pub ty: ColTy,
   pub notnull: bool,
   pub dft: Option<CLit<'a>>,
+  // only for varchar
+  pub compressed: bool,
 }
 
 // Cons for Constraint
@@ -1,7 +1,7 @@
 use std::str::{self, FromStr};
 use typed_arena::Arena;
 
-use common::{BareTy::{*, self}, FixTy, ColTy, ParserError as PE, ParserErrorKind::*, Lit, CLit, AggOp::*, BinOp::*, CmpOp::*};
+use common::{BareTy::{*, self}, FixTy, IN_MEMORY, ColTy, ParserError as PE, ParserErrorKind::*, Lit, CLit, AggOp::*, BinOp::*, CmpOp::*};
 use crate::ast::*;
 use crate::Stmt::AddPrimary;
 
@@ -20,6 +20,13 @@ impl<'p> Parser<'p> {
       unsafe { str::from_utf8_unchecked(s) }
     } else { s }
   }
+
+  // x'0aFF' => [0x0a, 0xff]
+  fn hex(&mut self, t: &Token<'p>) -> &'p [u8] {
+    let s = unsafe { str::from_utf8_unchecked(t.piece.get_unchecked(2..t.piece.len() - 1)) };
+    if s.len() % 2 != 0 { return (self.pe.push(PE { line: t.line, col: t.col, kind: InvalidHex(t.str()) }), &[][..]).1; }
+    self.alloc.alloc_extend((0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()))
+  }
 }
 
 impl<'p> Token<'p> {
@@ -32,6 +39,19 @@ impl<'p> Token<'p> {
 }
 
 type FieldList<'p> = (Vec<ColDecl<'p>>, Vec<ColCons<'p>>);
+// (type, compressed)
+type FieldTy = (ColTy, bool);
+
+enum CopyOpt<'p> { Header, Delimiter(u8), Null(&'p str) }
+
+fn copy_opt<'p>(mut opts: CopyOpts<'p>, o: CopyOpt<'p>) -> CopyOpts<'p> {
+  match o {
+    CopyOpt::Header => opts.header = true,
+    CopyOpt::Delimiter(d) => opts.delimiter = d,
+    CopyOpt::Null(null) => opts.null = null,
+  }
+  opts
+}
 
 #[parser_macros::lalr1(Program)]
 #[use_unsafe]
@@ -51,12 +71,28 @@ priority = [
 [lexical] # I hate sql...
 '(c|C)(r|R)(e|E)(a|A)(t|T)(e|E)' = 'Create'
 '(d|D)(r|R)(o|O)(p|P)' = 'Drop'
+'(c|C)(o|O)(p|P)(y|Y)' = 'Copy'
+'(w|W)(i|I)(t|T)(h|H)' = 'With'
+'(t|T)(o|O)' = 'To'
+'(f|F)(o|O)(r|R)(m|M)(a|A)(t|T)' = 'Format'
+'(h|H)(e|E)(a|A)(d|D)(e|E)(r|R)' = 'Header'
+'(d|D)(e|E)(l|L)(i|I)(m|M)(i|I)(t|T)(e|E)(r|R)' = 'Delimiter'
 '(u|U)(s|S)(e|E)' = 'Use'
 '(s|S)(h|H)(o|O)(w|W)' = 'Show'
+'(s|S)(h|H)(o|O)(w|W)\s+(l|L)(o|O)(b|B)' = 'ShowLob'
+'(b|B)(e|E)(g|G)(i|I)(n|N)' = 'Begin'
+'(c|C)(o|O)(m|M)(m|M)(i|I)(t|T)' = 'Commit'
+'(r|R)(o|O)(l|L)(l|L)(b|B)(a|A)(c|C)(k|K)' = 'Rollback'
+'(c|C)(h|H)(e|E)(c|C)(k|K)(p|P)(o|O)(i|I)(n|N)(t|T)' = 'Checkpoint'
+'(v|V)(a|A)(c|C)(u|U)(u|U)(m|M)' = 'Vacuum'
+'(c|C)(o|O)(m|M)(p|P)(a|A)(c|C)(t|T)\s+(l|L)(o|O)(b|B)' = 'CompactLob'
+'(r|R)(e|E)(p|P)(a|A)(i|I)(r|R)' = 'Repair'
 '(d|D)(e|E)(s|S)(c|C)' = 'Desc'
 '(a|A)(l|L)(t|T)(e|E)(r|R)\s+(t|T)(a|A)(b|B)(l|L)(e|E)' = 'AlterTable'
 '(a|A)(d|D)(d|D)' = 'Add1'
 '(r|R)(e|E)(n|N)(a|A)(m|M)(e|E)\s+(t|T)(o|O)' = 'RenameTo'
+'(b|B)(a|A)(c|C)(k|K)(u|U)(p|P)\s+(d|D)(a|A)(t|T)(a|A)(b|B)(a|A)(s|S)(e|E)\s+(t|T)(o|O)' = 'BackupTo'
+'(d|D)(u|U)(m|M)(p|P)\s+(d|D)(a|A)(t|T)(a|A)(b|B)(a|A)(s|S)(e|E)' = 'DumpDb'
 '(d|D)(a|A)(t|T)(a|A)(b|B)(a|A)(s|S)(e|E)(s|S)' = 'DataBases'
 '(d|D)(a|A)(t|T)(a|A)(b|B)(a|A)(s|S)(e|E)' = 'DataBase'
 '(t|T)(a|A)(b|B)(l|L)(e|E)(s|S)' = 'Tables'
@@ -92,6 +128,9 @@ priority = [
 '(b|B)(o|O)(o|O)(l|L)' = 'Bool'
 '(c|C)(h|H)(a|A)(r|R)' = 'Char'
 '(v|V)(a|A)(r|R)(c|C)(h|H)(a|A)(r|R)' = 'Varchar'
+'(t|T)(e|E)(x|X)(t|T)' = 'Text'
+'(b|B)(l|L)(o|O)(b|B)' = 'Blob'
+'(c|C)(o|O)(m|M)(p|P)(r|R)(e|E)(s|S)(s|S)(e|E)(d|D)' = 'Compressed'
 '(d|D)(e|E)(c|C)(i|I)(m|M)(a|A)(l|L)' = 'Float'
 '(f|F)(l|L)(o|O)(a|A)(t|T)' = 'Float'
 '(d|D)(a|A)(t|T)(e|E)' = 'Date'
@@ -121,6 +160,8 @@ priority = [
 '-?\d+\.\d*' = 'FloatLit'
 '-?\d+' = 'IntLit'
 "'(('')|[^'])*'" = 'StrLit'
+"(x|X)'[0-9a-fA-F]*'" = 'HexLit'
+':(m|M)(e|E)(m|M)(o|O)(r|R)(y|Y):' = 'Memory'
 '[A-Za-z]\w*' = 'Id1'
 '.' = '_Err'
 "##]
@@ -139,6 +180,8 @@ impl<'p> Parser<'p> {
   fn stmt_show_db(_: Token, _: Token, db: &'p str) -> Stmt<'p> { Stmt::ShowDb(db) }
   #[rule = "Stmt -> Create DataBase Id"]
   fn stmt_create_db(_: Token, _: Token, db: &'p str) -> Stmt<'p> { Stmt::CreateDb(db) }
+  #[rule = "Stmt -> Create DataBase Memory"]
+  fn stmt_create_memory_db(_: Token, _: Token, _: Token) -> Stmt<'p> { Stmt::CreateDb(IN_MEMORY) }
   #[rule = "Stmt -> Drop DataBase Id"]
   fn stmt_drop_db(_: Token, _: Token, db: &'p str) -> Stmt<'p> { Stmt::DropDb(db) }
   #[rule = "Stmt -> Use Id"]
@@ -147,8 +190,8 @@ impl<'p> Parser<'p> {
   fn stmt_use_db1(_: Token, _: Token, db: &'p str) -> Stmt<'p> { Stmt::UseDb(db) }
   #[rule = "Stmt -> Drop Table Id"]
   fn stmt_drop_table(_: Token, _: Token, table: &'p str) -> Stmt<'p> { Stmt::DropTable(table) }
-  #[rule = "Stmt -> Create Index Id On Id LPar Id RPar"]
-  fn stmt_create_index(_: Token, _: Token, index: &'p str, _: Token, table: &'p str, _: Token, col: &'p str, _: Token) -> Stmt<'p> { CreateIndex { index, table, col }.into() }
+  #[rule = "Stmt -> Create Index Id On Id LPar IdList RPar"]
+  fn stmt_create_index(_: Token, _: Token, index: &'p str, _: Token, table: &'p str, _: Token, cols: Vec<&'p str>, _: Token) -> Stmt<'p> { CreateIndex { index, table, cols }.into() }
   #[rule = "Stmt -> Drop Index Id"]
   fn stmt_drop_index(_: Token, _: Token, index: &'p str) -> Stmt<'p> { Stmt::DropIndex { index, table: None } }
   #[rule = "Stmt -> Create Table Id LPar FieldList RPar"]
@@ -159,10 +202,10 @@ impl<'p> Parser<'p> {
   fn stmt_show_table0(_: Token, table: &'p str) -> Stmt<'p> { Stmt::ShowTable(table) }
   #[rule = "Stmt -> Show Table Id"]
   fn stmt_show_table1(_: Token, _: Token, table: &'p str) -> Stmt<'p> { Stmt::ShowTable(table) }
-  #[rule = "Stmt -> Select Mul From IdList WhereM"]
-  fn stmt_select0(_: Token, _: Token, _: Token, tables: Vec<&'p str>, where_: Vec<Cond<'p>>) -> Stmt<'p> { Select { ops: None, tables, where_ }.into() }
-  #[rule = "Stmt -> Select AggList From IdList WhereM"]
-  fn stmt_select1(_: Token, ops: Vec<Agg<'p>>, _: Token, tables: Vec<&'p str>, where_: Vec<Cond<'p>>) -> Stmt<'p> { Select { ops: Some(ops), tables, where_ }.into() }
+  #[rule = "Stmt -> ShowLob"]
+  fn stmt_show_lob(_: Token) -> Stmt<'p> { Stmt::ShowLob }
+  #[rule = "Stmt -> Query"]
+  fn stmt_select(q: Select<'p>) -> Stmt<'p> { q.into() }
   #[rule = "Stmt -> InsertInto Id Values LitListList"]
   fn stmt_insert0(_: Token, table: &'p str, _: Token, vals: Vec<Vec<CLit<'p>>>) -> Stmt<'p> { Insert { table, cols: None, vals }.into() }
   #[rule = "Stmt -> InsertInto Id LPar IdList RPar Values LitListList"]
@@ -172,8 +215,8 @@ impl<'p> Parser<'p> {
   #[rule = "Stmt -> Delete From Id WhereM"]
   fn stmt_delete(_: Token, _: Token, table: &'p str, where_: Vec<Cond<'p>>) -> Stmt<'p> { Delete { table, where_ }.into() }
 
-  #[rule = "Stmt -> AlterTable Id Add1 Index Id On LPar Id RPar"]
-  fn alter_create_index1(_: Token, table: &'p str, _: Token, _: Token, index: &'p str, _: Token, _: Token, col: &'p str, _: Token) -> Stmt<'p> { CreateIndex { index, table, col }.into() }
+  #[rule = "Stmt -> AlterTable Id Add1 Index Id On LPar IdList RPar"]
+  fn alter_create_index1(_: Token, table: &'p str, _: Token, _: Token, index: &'p str, _: Token, _: Token, cols: Vec<&'p str>, _: Token) -> Stmt<'p> { CreateIndex { index, table, cols }.into() }
   #[rule = "Stmt -> AlterTable Id Drop Index Id"]
   fn alter_drop_index1(_: Token, table: &'p str, _: Token, _: Token, index: &'p str) -> Stmt<'p> { Stmt::DropIndex { index, table: Some(table) } }
   #[rule = "Stmt -> AlterTable Id RenameTo Id"]
@@ -191,6 +234,41 @@ impl<'p> Parser<'p> {
   #[rule = "Stmt -> AlterTable Id Drop Id"]
   fn alter_drop_col(_: Token, table: &'p str, _: Token, col: &'p str) -> Stmt<'p> { Stmt::DropCol { table, col } }
 
+  #[rule = "Stmt -> Begin"]
+  fn stmt_begin(_: Token) -> Stmt<'p> { Stmt::Begin }
+  #[rule = "Stmt -> Commit"]
+  fn stmt_commit(_: Token) -> Stmt<'p> { Stmt::Commit }
+  #[rule = "Stmt -> Rollback"]
+  fn stmt_rollback(_: Token) -> Stmt<'p> { Stmt::Rollback }
+  #[rule = "Stmt -> Checkpoint"]
+  fn stmt_checkpoint(_: Token) -> Stmt<'p> { Stmt::Checkpoint }
+  #[rule = "Stmt -> Vacuum"]
+  fn stmt_vacuum0(_: Token) -> Stmt<'p> { Stmt::Vacuum(None) }
+  #[rule = "Stmt -> Vacuum Id"]
+  fn stmt_vacuum1(_: Token, table: &'p str) -> Stmt<'p> { Stmt::Vacuum(Some(table)) }
+  #[rule = "Stmt -> CompactLob"]
+  fn stmt_compact_lob(_: Token) -> Stmt<'p> { Stmt::CompactLob }
+  #[rule = "Stmt -> Check DataBase"]
+  fn stmt_check_db0(_: Token, _: Token) -> Stmt<'p> { Stmt::CheckDb(false) }
+  #[rule = "Stmt -> Check DataBase Repair"]
+  fn stmt_check_db1(_: Token, _: Token, _: Token) -> Stmt<'p> { Stmt::CheckDb(true) }
+  #[rule = "Stmt -> BackupTo StrLit"]
+  fn stmt_backup(&self, _: Token, path: Token) -> Stmt<'p> { Stmt::Backup(self.escape(path.str_trim())) }
+  #[rule = "Stmt -> DumpDb"]
+  fn stmt_dump(_: Token) -> Stmt<'p> { Stmt::Dump }
+  #[rule = "Stmt -> Copy Id From StrLit CopyOpts"]
+  fn stmt_copy_from0(&self, _: Token, table: &'p str, _: Token, path: Token, opts: CopyOpts<'p>) -> Stmt<'p> { CopyFrom { table, cols: None, path: self.escape(path.str_trim()), opts }.into() }
+  #[rule = "Stmt -> Copy Id LPar IdList RPar From StrLit CopyOpts"]
+  fn stmt_copy_from1(&self, _: Token, table: &'p str, _: Token, cols: Vec<&'p str>, _: Token, _: Token, path: Token, opts: CopyOpts<'p>) -> Stmt<'p> { CopyFrom { table, cols: Some(cols), path: self.escape(path.str_trim()), opts }.into() }
+  #[rule = "Stmt -> Copy LPar Query RPar To StrLit Format Id1"]
+  fn stmt_copy_to(&mut self, _: Token, _: Token, select: Select<'p>, _: Token, _: Token, path: Token, _: Token, f: Token) -> Stmt<'p> {
+    let format = match f.str().to_ascii_lowercase().as_str() {
+      "csv" => CopyFormat::Csv, "jsonl" => CopyFormat::Jsonl, "tsv" => CopyFormat::Tsv,
+      _ => (self.pe.push(PE { line: f.line, col: f.col, kind: InvalidFormat(f.str()) }), CopyFormat::Csv).1,
+    };
+    CopyTo { select, path: self.escape(path.str_trim()), format }.into()
+  }
+
   #[rule = "WhereM -> Where CondList"]
   fn where_m1(_: Token, where_: Vec<Cond<'p>>) -> Vec<Cond<'p>> { where_ }
   #[rule = "WhereM ->"]
@@ -201,6 +279,33 @@ impl<'p> Parser<'p> {
   #[rule = "IdList -> IdList Comma Id"]
   fn id_list1(mut il: Vec<&'p str>, _: Token, i: &'p str) -> Vec<&'p str> { (il.push(i), il).1 }
 
+  #[rule = "Query -> Select Mul From IdList WhereM"]
+  fn query0(_: Token, _: Token, _: Token, tables: Vec<&'p str>, where_: Vec<Cond<'p>>) -> Select<'p> { Select { ops: None, tables, where_ } }
+  #[rule = "Query -> Select AggList From IdList WhereM"]
+  fn query1(_: Token, ops: Vec<Agg<'p>>, _: Token, tables: Vec<&'p str>, where_: Vec<Cond<'p>>) -> Select<'p> { Select { ops: Some(ops), tables, where_ } }
+
+  #[rule = "CopyOpts ->"]
+  fn copy_opts0() -> CopyOpts<'p> { CopyOpts::default() }
+  #[rule = "CopyOpts -> With CopyOptList"]
+  fn copy_opts1(_: Token, opts: CopyOpts<'p>) -> CopyOpts<'p> { opts }
+  #[rule = "CopyOptList -> CopyOpt"]
+  fn copy_opt_list0(o: CopyOpt<'p>) -> CopyOpts<'p> { copy_opt(CopyOpts::default(), o) }
+  #[rule = "CopyOptList -> CopyOptList Comma CopyOpt"]
+  fn copy_opt_list1(opts: CopyOpts<'p>, _: Token, o: CopyOpt<'p>) -> CopyOpts<'p> { copy_opt(opts, o) }
+  #[rule = "CopyOpt -> Header"]
+  fn copy_opt_header(_: Token) -> CopyOpt<'p> { CopyOpt::Header }
+  // sql has no escape characters, so '\t' is accepted as tab
+  #[rule = "CopyOpt -> Delimiter StrLit"]
+  fn copy_opt_delimiter(&mut self, _: Token, t: Token) -> CopyOpt<'p> {
+    match self.escape(t.str_trim()) {
+      "\\t" => CopyOpt::Delimiter(b'\t'),
+      d if d.len() == 1 && d != "\"" && d != "\r" && d != "\n" => CopyOpt::Delimiter(d.as_bytes()[0]),
+      _ => (self.pe.push(PE { line: t.line, col: t.col, kind: InvalidDelimiter(t.str()) }), CopyOpt::Delimiter(b',')).1,
+    }
+  }
+  #[rule = "CopyOpt -> Null StrLit"]
+  fn copy_opt_null(&self, _: Token, t: Token) -> CopyOpt<'p> { CopyOpt::Null(self.escape(t.str_trim())) }
+
   #[rule = "AggList -> Agg"]
   fn agg_list0(a: Agg<'p>) -> Vec<Agg<'p>> { vec![a] }
   #[rule = "AggList -> AggList Comma Agg"]
@@ -270,14 +375,14 @@ impl<'p> Parser<'p> {
   #[rule = "FieldList -> FieldList Comma ColCons"]
   fn field_list3(mut fl: FieldList<'p>, _: Token, c: ColCons<'p>) -> FieldList<'p> { (fl.1.push(c), fl).1 }
 
-  #[rule = "ColDecl -> Id ColTy"]
-  fn field0(col: &'p str, ty: ColTy) -> ColDecl<'p> { ColDecl { col, ty, notnull: false, dft: None } }
-  #[rule = "ColDecl -> Id ColTy NotNull"]
-  fn field1(col: &'p str, ty: ColTy, _: Token) -> ColDecl<'p> { ColDecl { col, ty, notnull: true, dft: None } }
-  #[rule = "ColDecl -> Id ColTy Default Lit"]
-  fn field2(col: &'p str, ty: ColTy, _: Token, dft: CLit<'p>) -> ColDecl<'p> { ColDecl { col, ty, notnull: false, dft: Some(dft) } }
-  #[rule = "ColDecl -> Id ColTy NotNull Default Lit"]
-  fn field3(col: &'p str, ty: ColTy, _: Token, _: Token, dft: CLit<'p>) -> ColDecl<'p> { ColDecl { col, ty, notnull: true, dft: Some(dft) } }
+  #[rule = "ColDecl -> Id FieldTy"]
+  fn field0(col: &'p str, (ty, compressed): FieldTy) -> ColDecl<'p> { ColDecl { col, ty, notnull: false, dft: None, compressed } }
+  #[rule = "ColDecl -> Id FieldTy NotNull"]
+  fn field1(col: &'p str, (ty, compressed): FieldTy, _: Token) -> ColDecl<'p> { ColDecl { col, ty, notnull: true, dft: None, compressed } }
+  #[rule = "ColDecl -> Id FieldTy Default Lit"]
+  fn field2(col: &'p str, (ty, compressed): FieldTy, _: Token, dft: CLit<'p>) -> ColDecl<'p> { ColDecl { col, ty, notnull: false, dft: Some(dft), compressed } }
+  #[rule = "ColDecl -> Id FieldTy NotNull Default Lit"]
+  fn field3(col: &'p str, (ty, compressed): FieldTy, _: Token, _: Token, dft: CLit<'p>) -> ColDecl<'p> { ColDecl { col, ty, notnull: true, dft: Some(dft), compressed } }
   #[rule = "ColCons -> ForeignKey LPar Id RPar References Id LPar Id RPar"]
   fn field5(_: Token, _: Token, col: &'p str, _: Token, _: Token, f_table: &'p str, _: Token, f_col: &'p str, _: Token) -> ColCons<'p> { ColCons::Foreign { col, f_table, f_col } }
   #[rule = "ColCons -> PrimaryKey LPar IdList RPar"]
@@ -331,7 +436,7 @@ impl<'p> Parser<'p> {
   #[rule = "Cond -> ColRef Is NotNull"]
   fn cond_is_not_null(c: ColRef<'p>, _: Token, _: Token) -> Cond<'p> { Cond::Null(c, false) }
   #[rule = "Cond -> ColRef Like StrLit"]
-  fn cond_like(c: ColRef<'p>, _: Token, s: Token) -> Cond<'p> { Cond::Like(c, s.str_trim()) }
+  fn cond_like(&self, c: ColRef<'p>, _: Token, s: Token) -> Cond<'p> { Cond::Like(c, self.escape(s.str_trim())) }
 
   #[rule = "Atom -> ColRef"]
   fn atom_col_ref(c: ColRef<'p>) -> Atom<'p> { Atom::ColRef(c) }
@@ -349,7 +454,9 @@ impl<'p> Parser<'p> {
   #[rule = "Lit -> FloatLit"]
   fn lit_float(&mut self, t: Token) -> CLit<'p> { t.parse(|x: f32| CLit::new(Lit::Number(x as f64)), |line, col, s| self.pe.push(PE { line, col, kind: InvalidFloat(s) })) }
   #[rule = "Lit -> StrLit"]
-  fn lit_str(t: Token) -> CLit<'p> { CLit::new(Lit::Str(t.str_trim())) }
+  fn lit_str(&self, t: Token) -> CLit<'p> { CLit::new(Lit::Str(self.escape(t.str_trim()))) }
+  #[rule = "Lit -> HexLit"]
+  fn lit_hex(&mut self, t: Token) -> CLit<'p> { CLit::new(Lit::Blob(self.hex(&t))) }
 
   #[rule = "BareTy -> Bool"]
   fn bare_ty_bool(_: Token) -> BareTy { Bool }
@@ -366,6 +473,14 @@ impl<'p> Parser<'p> {
   fn col_ty(&mut self, ty: BareTy, _: Token, t: Token, _: Token) -> ColTy { t.parse(|size| ColTy::FixTy(FixTy { size, ty }), |line, col, s| self.pe.push(PE { line, col, kind: InvalidTypeSize(s) })) }
   #[rule = "ColTy -> Varchar LPar IntLit RPar"]
   fn col_ty_varchar(&mut self, _: Token, _: Token, t: Token, _: Token) -> ColTy { t.parse(|size| ColTy::Varchar(size), |line, col, s| self.pe.push(PE { line, col, kind: InvalidTypeSize(s) })) }
+  #[rule = "FieldTy -> ColTy"]
+  fn field_ty0(ty: ColTy) -> FieldTy { (ty, false) }
+  #[rule = "FieldTy -> Varchar LPar IntLit RPar Compressed"]
+  fn field_ty1(&mut self, v: Token, l: Token, t: Token, r: Token, _: Token) -> FieldTy { (self.col_ty_varchar(v, l, t, r), true) }
+  #[rule = "ColTy -> Text"]
+  fn col_ty_text(_: Token) -> ColTy { ColTy::Text }
+  #[rule = "ColTy -> Blob"]
+  fn col_ty_blob(_: Token) -> ColTy { ColTy::Blob }
   #[rule = "ColTy -> Bool"]
   fn col_ty_bool(_: Token) -> ColTy { ColTy::FixTy(FixTy { size: 0, ty: Bool }) }
   #[rule = "ColTy -> Int"]
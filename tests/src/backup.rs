@@ -0,0 +1,60 @@
+use std::{fs::{self, OpenOptions}, io::{Read, Write, Seek, SeekFrom}, path::Path};
+use typed_arena::Arena;
+
+use driver::Eval;
+use common::{*, Error::*};
+use crate::query;
+
+#[test]
+fn backup() {
+  let mut e = Eval::default();
+  ok!(e, "create database backup_src; use backup_src;");
+  ok!(e, "create table t (id int, name varchar(100), primary key (id)); create index tn on t (id);");
+  for i in 0..200 { ok!(e, &format!("insert into t values ({}, 'name of the record {}');", i, i)); }
+  ok!(e, "delete from t where id >= 150;");
+  // its pages are at the end of the page file, they are truncated in the copy
+  ok!(e, "create table tmp (id int, c char(200));");
+  for i in 0..500 { ok!(e, &format!("insert into tmp values ({}, 'c');", i)); }
+  ok!(e, "drop table tmp;");
+
+  ok!(e, "begin;");
+  match e.exec_all("backup database to 'backup_dst';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { BackupInTransaction => {} e => panic!("{:?}", e) }
+  ok!(e, "rollback;");
+  // the database in use is locked, so it is not overwritten
+  match e.exec_all("backup database to 'backup_src';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { DatabaseLocked => {} e => panic!("{:?}", e) }
+  ok!(e, "backup database to 'backup_dst';");
+  assert!(fs::metadata("backup_dst").unwrap().len() < fs::metadata("backup_src").unwrap().len());
+  let expect = query(&mut e, "select * from t where id < 160;");
+  let data = unsafe { e.db().unwrap().get_tp("t").unwrap().1.first };
+
+  ok!(e, "use backup_dst;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  assert_eq!(query(&mut e, "select * from t where id < 160;"), expect);
+  ok!(e, "insert into t values (200, 'new');");
+
+  // an in-memory database can be saved this way
+  ok!(e, "create database :memory:; create table m (id int, v varchar(10)); insert into m values (1, 'one'), (2, null);");
+  ok!(e, "backup database to 'backup_dst';");
+  ok!(e, "use backup_dst;");
+  assert_eq!(query(&mut e, "select * from m;").lines().count(), 3);
+  match e.exec_all("select * from t;", &Arena::default(), |_| {}, |_| {}).err().unwrap() { NoSuchTable(_) => {} e => panic!("{:?}", e) }
+  ok!(e, "drop database backup_dst;");
+  drop(e);
+
+  // a corrupted page fails the backup before any file of the target is created
+  let flip = || {
+    let mut f = OpenOptions::new().read(true).write(true).open("backup_src").unwrap();
+    let (pos, mut b) = (SeekFrom::Start((data as usize * PAGE_SIZE + 100) as u64), [0]);
+    (f.seek(pos).unwrap(), f.read_exact(&mut b).unwrap(), b[0] ^= 1);
+    (f.seek(pos).unwrap(), f.write_all(&b).unwrap());
+  };
+  flip();
+  let mut e = Eval::default();
+  ok!(e, "use backup_src;");
+  match e.exec_all("backup database to 'backup_dst';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { PageCorrupted { page, .. } => assert_eq!(page, data), e => panic!("{:?}", e) }
+  for &suffix in &["", LOB_SUFFIX, SUM_SUFFIX] { assert!(!Path::new("backup_dst").with_extension(suffix).exists()); }
+  drop(e);
+  flip();
+  let mut e = Eval::default();
+  ok!(e, "drop database backup_src;");
+}
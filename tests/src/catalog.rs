@@ -0,0 +1,89 @@
+use driver::Eval;
+use crate::query;
+
+#[test]
+fn catalog() {
+  let mut e = Eval::default();
+  ok!(e, "create database catalog; use catalog;");
+  for i in 0..300 {
+    ok!(e, &format!("create table t{} (id int, v int, primary key (id));", i));
+  }
+  ok!(e, "create table f (id int, foreign key (id) references t7(id));");
+  ok!(e, "create index i on t3 (v);");
+  err!(e, "create index i on t4 (v);");
+  err!(e, "drop table t7;");
+  err!(e, "alter table t1 rename to t2;");
+
+  ok!(e, "alter table t3 rename to x; drop table t5; drop table t0;");
+  err!(e, "select * from t3;");
+  err!(e, "alter table t3 drop index i;");
+  ok!(e, "select * from x; select * from t299; insert into t7 values (1, 1);");
+  ok!(e, "alter table f drop foreign key id; drop table t7; alter table x drop index i; create index i on t4 (v);");
+
+  // the catalog is rebuilt after rollback and reopen
+  ok!(e, "begin; drop table t1; alter table t2 rename to t1; drop index i; rollback;");
+  ok!(e, "select * from t1; select * from t2;");
+  err!(e, "create index i on t6 (v);");
+  ok!(e, "alter table f add foreign key (id) references t8(id);");
+  drop(e);
+  let mut e = Eval::default();
+  ok!(e, "use catalog;");
+  err!(e, "drop table t8;");
+  err!(e, "create index i on t6 (v);");
+  ok!(e, "select * from x; select * from f;");
+  err!(e, "select * from t0;");
+  // col ids are changed by `drop col`
+  ok!(e, "create table y (a int, b int, c int); create index yc on y (c); alter table y drop b; alter table y drop index yc;");
+  ok!(e, "drop database catalog;");
+}
+
+#[test]
+fn limits() {
+  use physics::{MAX_TABLE, TABLE_LIST_CAP};
+  let long = |prefix: &str| format!("{}_{}", prefix, "x".repeat(300));
+  let mut e = Eval::default();
+  ok!(e, "create database limits; use limits;");
+  // more tables than DbPage can hold, so that TableListPages are used
+  let n = MAX_TABLE + TABLE_LIST_CAP + 10;
+  let mut sql = "begin;".to_owned();
+  for i in 0..n { sql += &format!("create table t{} (id int);", i); }
+  ok!(e, &(sql + "commit;"));
+  ok!(e, &format!("create table {} ({} int, {} int);", long("t"), long("a"), long("b")));
+  ok!(e, &format!("create index {} on {} ({});", long("i"), long("t"), long("a")));
+  ok!(e, &format!("insert into {} values (1, 2);", long("t")));
+  ok!(e, &format!("create table {} (id int);", "t".repeat(10000)));
+  err!(e, &format!("create table {} (id int);", long("t")));
+  // more cols and names than one page can hold, so that the TablePage takes more pages
+  let cols = (0..1000).map(|i| long(&format!("c{}", i))).collect::<Vec<_>>();
+  ok!(e, &format!("create table w ({} int);", cols.join(" int, ")));
+  ok!(e, &format!("create index {} on w ({});", long("i0"), cols[999]));
+  ok!(e, &format!("create index {} on w ({}, {});", long("m"), cols[0], cols[500]));
+  ok!(e, &format!("alter table w add {} int;", long("c1000")));
+  ok!(e, &format!("insert into w ({}, {}, {}) values (1, 2, 3);", cols[0], cols[999], long("c1000")));
+  drop(e);
+
+  let mut e = Eval::default();
+  ok!(e, "use limits;");
+  ok!(e, &format!("select * from t0; select * from t{}; select {} from {};", n - 1, long("b"), long("t")));
+  let mut sql = "begin;".to_owned();
+  for i in 0..n - 5 { sql += &format!("drop table t{};", i); }
+  ok!(e, &(sql + "commit;"));
+  err!(e, "select * from t0;");
+  ok!(e, &format!("select * from t{};", n - 1));
+  ok!(e, &format!("alter table {} rename to {};", long("t"), long("u")));
+  ok!(e, &format!("alter table {} drop {};", long("u"), long("a")));
+  ok!(e, &format!("alter table {} add {} int;", long("u"), long("c")));
+  drop(e);
+
+  let mut e = Eval::default();
+  ok!(e, "use limits;");
+  err!(e, &format!("select * from {};", long("t")));
+  ok!(e, &format!("select {}, {} from {};", long("b"), long("c"), long("u")));
+  ok!(e, &format!("select * from {};", "t".repeat(10000)));
+  assert_eq!(query(&mut e, &format!("select {} from w where {} = 2 and {} = 3;", long("c1000"), cols[999], long("c1000"))).lines().count(), 2);
+  ok!(e, &format!("drop index {}; alter table w drop {};", long("m"), cols[500]));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop table w;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop database limits;");
+}
@@ -0,0 +1,45 @@
+use driver::Eval;
+use physics::*;
+use crate::query;
+
+#[test]
+fn check() {
+  let mut e = Eval::default();
+  ok!(e, "create database integrity; use integrity;");
+  ok!(e, "create table p (id int, name varchar(100), primary key (id));");
+  ok!(e, "create table c (id int, pid int not null, grade char(1), foreign key (pid) references p(id), check (grade in ('a', 'b')));");
+  ok!(e, "create index ci on c (id);");
+  for i in 0..50 {
+    ok!(e, &format!("insert into p values ({}, 'name of {}'); insert into c values ({}, {}, 'a');", i, i, i, i % 10));
+  }
+  // some free pages and free lob slots
+  ok!(e, "create table tmp (id int); insert into tmp values (1); drop table tmp;");
+  ok!(e, "update p set name = 'a name that is longer than the thirty two bytes of a slot' where id >= 40;");
+  ok!(e, "update p set name = 'short' where id >= 40 and id < 45;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  unsafe {
+    let db = e.db().unwrap();
+    let tp = db.get_tp("c").unwrap().1;
+    tp.count += 1;
+    db.get_page::<IndexPage>(tp.get_ci("id").unwrap().index).count = 0;
+    db.dp().first_free = !0;
+    (*(db.get_lob_mut(0, 1) as *mut FreeLobSlot)).init_nil();
+  }
+  let problems = ["table `c`: there are 50 records, but its count is 51", "column `c.id`: 50 record(s) are missing from the index",
+    "page(s) are neither used nor free", "lob: "];
+  let res = query(&mut e, "check database;");
+  for p in &problems { assert!(res.contains(p), "{}", res); }
+  assert!(res.ends_with("4 problem(s) found"), "{}", res);
+  let res = query(&mut e, "check database repair;");
+  for p in &problems { assert!(res.contains(p), "{}", res); }
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  ok!(e, "insert into c values (50, 1, 'b'); delete from c where id = 3; update p set name = 'x' where id = 45;");
+  assert_eq!(query(&mut e, "select id from c where id = 50;").lines().count(), 2);
+  drop(e);
+  let mut e = Eval::default();
+  ok!(e, "use integrity;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop database integrity;");
+}
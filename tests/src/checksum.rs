@@ -0,0 +1,111 @@
+use std::{fs::{self, OpenOptions}, io::{Read, Write, Seek, SeekFrom}, path::Path};
+use typed_arena::Arena;
+
+use driver::Eval;
+use db::Db;
+use physics::DataPage;
+use syntax::ast::*;
+use common::{*, Error::*};
+
+fn flip(page: u32, off: usize) {
+  let mut f = OpenOptions::new().read(true).write(true).open("checksum").unwrap();
+  let pos = SeekFrom::Start((page as usize * PAGE_SIZE + off) as u64);
+  let mut b = [0];
+  (f.seek(pos).unwrap(), f.read_exact(&mut b).unwrap());
+  b[0] ^= 1;
+  (f.seek(pos).unwrap(), f.write_all(&b).unwrap());
+}
+
+#[test]
+fn checksum() {
+  let mut e = Eval::default();
+  e.exec_all("create database checksum; use checksum; create table t (id int, v varchar(20)); create index i on t (id);", &Arena::default(), |_| {}, |_| {}).unwrap();
+  for i in 0..100 {
+    e.exec_all(&format!("insert into t values ({}, '{}');", i, i), &Arena::default(), |_| {}, |_| {}).unwrap();
+  }
+  let (tp_id, data, index) = unsafe {
+    let (tp_id, tp) = e.db().unwrap().get_tp("t").unwrap();
+    (tp_id, tp.first, tp.get_ci("id").unwrap().index)
+  };
+  drop(e);
+  let select = Select { ops: None, tables: vec!["t"], where_: vec![] };
+
+  for &(page, kind) in &[(data, "DataPage"), (index, "IndexPage")] {
+    flip(page, 100);
+    let mut e = Eval::default();
+    e.exec(&Stmt::UseDb("checksum")).unwrap();
+    match e.select(&select) {
+      Err(PageCorrupted { page: page1, kind: kind1 }) => assert_eq!((page1, kind1), (page, kind)),
+      _ => panic!("corruption not detected"),
+    }
+    drop(e);
+    flip(page, 100);
+  }
+
+  // a page modified without being verified (which is not expected) fails the transaction, and the page is still regarded as corrupted
+  flip(data, 100);
+  unsafe {
+    let mut db = Db::open("checksum").unwrap();
+    match db.transaction(|db| (db.get_page::<DataPage>(data).count += 1, Ok::<_, Error>(())).1) {
+      Err(PageCorrupted { page, kind }) => assert_eq!((page, kind), (data, "DataPage")),
+      _ => panic!("corruption not detected"),
+    }
+    match db.verify_table(tp_id) {
+      Err(PageCorrupted { page, .. }) => assert_eq!(page, data),
+      _ => panic!("corruption not detected"),
+    }
+  }
+  flip(data, 100);
+
+  flip(tp_id, 100);
+  match Eval::default().exec(&Stmt::UseDb("checksum")) {
+    Err(PageCorrupted { page, kind }) => assert_eq!((page, kind), (tp_id, "TablePage")),
+    _ => panic!("corruption not detected"),
+  }
+  flip(tp_id, 100);
+
+  // free pages are verified when the database is opened, not when they are allocated
+  let mut e = Eval::default();
+  e.exec_all("use checksum; create table u (id int); insert into u values (1); drop table u;", &Arena::default(), |_| {}, |_| {}).unwrap();
+  let free = unsafe { e.db().unwrap().page::<physics::DbPage>(0).first_free };
+  drop(e);
+  flip(free, 100);
+  match Eval::default().exec(&Stmt::UseDb("checksum")) {
+    Err(PageCorrupted { page, kind }) => assert_eq!((page, kind), (free, "free page")),
+    _ => panic!("corruption not detected"),
+  }
+  flip(free, 100);
+
+  // a sum file of a wrong size is refused, and a missing one makes all pages trusted
+  let sum = Path::new("checksum").with_extension(SUM_SUFFIX);
+  OpenOptions::new().write(true).open(&sum).unwrap().set_len(4).unwrap();
+  match Eval::default().exec(&Stmt::UseDb("checksum")) {
+    Err(InvalidSumSize { size, .. }) => assert_eq!(size, 4),
+    _ => panic!("wrong sum file size not detected"),
+  }
+  fs::remove_file(&sum).unwrap();
+
+  let mut e = Eval::default();
+  e.exec(&Stmt::UseDb("checksum")).unwrap();
+  assert_eq!(e.select(&select).unwrap().row_count(), 100);
+  e.exec(&Stmt::DropDb("checksum")).unwrap();
+}
+
+#[test]
+fn checksum_crash() {
+  let mut e = Eval::default();
+  e.exec_all("create database checksum_crash; use checksum_crash; create table t (id int); insert into t values (1);", &Arena::default(), |_| {}, |_| {}).unwrap();
+  drop(e);
+  // the checksums of pages modified outside a transaction are written when they are committed, a crash before that
+  // undoes the modification by the journal, so no page looks corrupted
+  let mut db = Db::open("checksum_crash").unwrap();
+  db.rename_table("t", "u").unwrap();
+  for &suffix in &["", LOB_SUFFIX, JOURNAL_SUFFIX, SUM_SUFFIX] {
+    fs::copy(Path::new("checksum_crash").with_extension(suffix), Path::new("checksum_crashed").with_extension(suffix)).unwrap();
+  }
+  drop(db);
+  let mut e = Eval::default();
+  e.exec(&Stmt::UseDb("checksum_crashed")).unwrap();
+  assert_eq!(e.select(&Select { ops: None, tables: vec!["t"], where_: vec![] }).unwrap().row_count(), 1);
+  e.exec_all("drop database checksum_crashed; use checksum_crash; select * from u; drop database checksum_crash;", &Arena::default(), |_| {}, |_| {}).unwrap();
+}
@@ -0,0 +1,60 @@
+use typed_arena::Arena;
+
+use driver::Eval;
+use common::Error::*;
+use syntax::ast::*;
+use crate::query;
+
+fn used_lob_slots(e: &mut Eval) -> usize {
+  let lob = query(e, "show lob;");
+  let s = &lob[lob.find("used slot count = ").unwrap() + 18..];
+  s[..s.find(',').unwrap()].parse().unwrap()
+}
+
+#[test]
+fn compress() {
+  let mut e = Eval::default();
+  ok!(e, "create database compress; use compress;");
+  ok!(e, "create table log (id int, msg varchar(4000) compressed, dup varchar(4000) compressed not null);");
+  assert!(query(&mut e, "show table log;").contains("- attr: compressed"));
+  let log = |i: usize| format!("{}: {}", i, "error: connection refused, retrying; ".repeat(30));
+  for i in 0..10 { ok!(e, &format!("insert into log values ({}, '{}', '{}');", i, log(i), log(i))); }
+  assert!(query(&mut e, "show database compress;").contains("COMPRESSED_VARCHAR"));
+  // each value is longer than 1000 bytes, which takes at least 32 slots if not compressed
+  assert!(used_lob_slots(&mut e) <= 20 * 4, "{}", query(&mut e, "show lob;"));
+
+  assert_eq!(query(&mut e, &format!("select id from log where msg = '{}';", log(3))), "id\n3");
+  assert_eq!(query(&mut e, "select id from log where msg like '5: error%';"), "id\n5");
+  // two compressed varchars are used at the same time
+  assert_eq!(query(&mut e, "select count(*) from log where msg = dup;"), "count(*)\n10");
+  assert_eq!(query(&mut e, "select msg from log where id = 7;"), format!("msg\n\"{}\"", log(7)));
+  assert_eq!(query(&mut e, "select max(msg), min(dup) from log;"), format!("max(msg),min(dup)\n\"{}\",\"{}\"", log(9), log(0)));
+  {
+    // a result of the library `select` owns its decompressed values, they are not affected by later selects
+    let alloc = Arena::default();
+    let s = match syntax::work("select msg from log where id = 4;", &alloc).unwrap().pop() { Some(Stmt::Select(s)) => s, _ => unreachable!() };
+    let db = &*e.db().unwrap();
+    let res = query::select(&s, db).unwrap();
+    for _ in 0..10 { assert_eq!(query::select(&s, db).unwrap().row_count(), 1); }
+    assert_eq!(res.csv(), format!("msg\n\"{}\"", log(4)));
+  }
+
+  // short values are inlined, values which cannot be compressed are stored as they are
+  let raw = (0..40).map(|i| (b'0' + i) as char).collect::<String>();
+  ok!(e, "update log set msg = 'ok' where id = 1;");
+  ok!(e, &format!("update log set msg = '{}' where id = 2;", raw));
+  ok!(e, &format!("update log set msg = '{}', dup = 'short' where id = 3;", log(30)));
+  ok!(e, "begin;");
+  ok!(e, &format!("update log set msg = '{}';", raw));
+  ok!(e, "rollback;");
+  assert_eq!(query(&mut e, "select id, msg from log where id < 3;"), format!("id,msg\n0,\"{}\"\n1,\"ok\"\n2,\"{}\"", log(0), raw));
+  assert_eq!(query(&mut e, "select * from log where id = 3;"), format!("id,msg,dup\n3,\"{}\",\"short\"", log(30)));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  ok!(e, "delete from log where id > 2; compact lob;");
+  assert_eq!(query(&mut e, "select id from log where msg = dup;"), "id\n0");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  match e.exec_all("create table t (v text compressed);", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ParserErrors(_) => {} e => panic!("{:?}", e) }
+  ok!(e, "drop database compress;");
+}
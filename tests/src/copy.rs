@@ -0,0 +1,83 @@
+use std::fs;
+use typed_arena::Arena;
+
+use driver::Eval;
+use common::Error::*;
+use crate::query;
+
+#[test]
+fn copy_from() {
+  let mut e = Eval::default();
+  ok!(e, "create database :memory:;");
+  ok!(e, "create table t (id int, name char(40) not null, score float, ok bool, d date, data blob, primary key (id), unique (name));");
+  fs::write("copy_from_1.csv", "id,name,score,ok,d,data\r\n1,plain,1.5,true,2020-02-29,00ff\r\n\
+    2,\"with, comma\",,FALSE,,\r\n\n3,\"two\nlines and \"\"quotes\"\"\",-2,,2021-01-01,\r\n4,\"\"").unwrap();
+  assert_eq!(query(&mut e, "copy t from 'copy_from_1.csv' with header;"), "4 column(s) affected");
+  assert_eq!(query(&mut e, "select * from t;"), "id,name,score,ok,d,data\n1,\"plain\",1.5,true,2020-02-29,x'00FF'\n\
+    2,\"with, comma\",,false,,\n3,\"two\nlines and \"\"quotes\"\"\",-2,,2021-01-01,\n4,\"\",,,,");
+
+  // `null` only applies to unquoted fields
+  fs::write("copy_from_2.csv", "5\tNULL\t\"NULL\"\n6\t2.5\tn6").unwrap();
+  ok!(e, "copy t (id, score, name) from 'copy_from_2.csv' with null 'NULL', delimiter '\\t';");
+  assert_eq!(query(&mut e, "select id, name, score, ok from t where id > 4;"), "id,name,score,ok\n5,\"NULL\",,\n6,\"n6\",2.5,");
+
+  // the line number is the line where the failed record starts, and no record in the file is inserted
+  let fail = |e: &mut Eval, content: &str| {
+    fs::write("copy_from_3.csv", content).unwrap();
+    match e.exec_all("copy t (id, name) from 'copy_from_3.csv';", &Arena::default(), |_| {}, |_| {}).err().unwrap() {
+      CopyFailed { line, reason } => (line, reason),
+      e => panic!("{:?}", e)
+    }
+  };
+  let (line, reason) = fail(&mut e, "7,a\n8,\"b\nc\"\n1,dup");
+  assert_eq!(line, 4);
+  assert!(reason.contains("PutDupOnPrimary") || reason.contains("PutDupOnUnique"), "{}", reason);
+  assert_eq!(fail(&mut e, "7,a\n8,plain").0, 2);
+  // empty lines before the record are counted
+  assert_eq!(fail(&mut e, "\n7,a\n\r\n\n1,dup").0, 5);
+  assert_eq!(fail(&mut e, "7,a\n\n8,\"b\nc").0, 3);
+  assert_eq!(fail(&mut e, "7,a\n\n8,\n").0, 3);
+  let (line, reason) = fail(&mut e, "7,a\nx,b");
+  assert_eq!(line, 2);
+  assert!(reason.contains("ColLitMismatch"), "{}", reason);
+  assert_eq!(fail(&mut e, "7,a,3").1, "InsertTooLong { max: 2, actual: 3 }");
+  assert_eq!(fail(&mut e, "7,\"a\"b").1, "unexpected character after a quoted field");
+  assert_eq!(fail(&mut e, "7,a\n8,\"b").1, "unterminated quoted field");
+  assert_eq!(fail(&mut e, &format!("7,{}", "a".repeat(41))).0, 1);
+  assert_eq!(query(&mut e, "select count(*) from t;"), "count(*)\n6");
+
+  match e.exec_all("copy t from 'copy_from_none.csv';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { IO(_) => {} e => panic!("{:?}", e) }
+  match e.exec_all("copy t from 'copy_from_1.csv' with delimiter ';;';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ParserErrors(_) => {} e => panic!("{:?}", e) }
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  for i in 1..4 { fs::remove_file(format!("copy_from_{}.csv", i)).unwrap(); }
+}
+
+#[test]
+fn copy_to() {
+  let mut e = Eval::default();
+  ok!(e, "create database :memory:;");
+  ok!(e, "create table t (id int, name varchar(40), score float, ok bool, d date, data blob);");
+  ok!(e, "insert into t values (1, 'a,b', 1.5, true, '2020-02-29', x'00ff'), (2, '', null, false, null, null), (3, 'say \"hi\"\nbye\t\\', -2.0, null, '2021-01-01', x'ab');");
+  assert_eq!(query(&mut e, "copy (select * from t) to 'copy_to_1.csv' format csv;"), "3 row(s) written");
+  assert_eq!(fs::read_to_string("copy_to_1.csv").unwrap(), "id,name,score,ok,d,data\n1,\"a,b\",1.5,true,2020-02-29,00FF\n\
+    2,\"\",,false,,\n3,\"say \"\"hi\"\"\nbye\t\\\",-2,,2021-01-01,AB\n");
+  ok!(e, "copy (select * from t) to 'copy_to_2.tsv' format TSV;");
+  assert_eq!(fs::read_to_string("copy_to_2.tsv").unwrap(), "id\tname\tscore\tok\td\tdata\n1\ta,b\t1.5\ttrue\t2020-02-29\t00FF\n\
+    2\t\t\tfalse\t\t\n3\tsay \"hi\"\\nbye\\t\\\\\t-2\t\t2021-01-01\tAB\n");
+  ok!(e, "copy (select id, name, d from t where id > 1) to 'copy_to_3.jsonl' format jsonl;");
+  assert_eq!(fs::read_to_string("copy_to_3.jsonl").unwrap(), r#"{"id":2,"name":"","d":null}
+{"id":3,"name":"say \"hi\"\nbye\t\\","d":"2021-01-01"}
+"#);
+  assert_eq!(query(&mut e, "copy (select count(*), max(score) from t) to 'copy_to_3.jsonl' format jsonl;"), "1 row(s) written");
+  assert_eq!(fs::read_to_string("copy_to_3.jsonl").unwrap(), "{\"count(*)\":3,\"max(score)\":1.5}\n");
+
+  // a csv file written by `copy to` can be read by `copy from`
+  ok!(e, "create table t1 (id int, name varchar(40), score float, ok bool, d date, data blob);");
+  ok!(e, "copy t1 from 'copy_to_1.csv' with header;");
+  assert_eq!(query(&mut e, "select * from t1;"), query(&mut e, "select * from t;"));
+
+  match e.exec_all("copy (select * from t2) to 'copy_to_4.csv' format csv;", &Arena::default(), |_| {}, |_| {}).err().unwrap() { NoSuchTable(_) => {} e => panic!("{:?}", e) }
+  assert!(fs::metadata("copy_to_4.csv").is_err());
+  match e.exec_all("copy (select * from t) to 'copy_to_4.csv' format xml;", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ParserErrors(_) => {} e => panic!("{:?}", e) }
+  for f in &["copy_to_1.csv", "copy_to_2.tsv", "copy_to_3.jsonl"] { fs::remove_file(f).unwrap(); }
+}
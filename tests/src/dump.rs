@@ -0,0 +1,43 @@
+use driver::Eval;
+use crate::query;
+
+#[test]
+fn dump() {
+  let mut e = Eval::default();
+  ok!(e, "create database :memory:;");
+  // `c` is created first, but it has a foreign link to `p`
+  ok!(e, "create table c (id int, pid int not null, note varchar(100) compressed, d date, primary key (id));");
+  ok!(e, "create table p (id int, boss int, name char(20) not null default 'nobody', score float, ok bool, kind int, body text, data blob,
+    primary key (id), unique (name), check (kind in (1, 2, 3)));");
+  ok!(e, "create table r (a int, b int, primary key (a, b));");
+  ok!(e, "create index p_score on p (score);");
+  ok!(e, "alter table c add foreign key (pid) references p(id); alter table p add foreign key (boss) references p(id);");
+  ok!(e, "insert into p (id, score, kind) values (3, 30000000000.0, 3);");
+  ok!(e, "insert into p values (1, 3, 'O''Brien', 1.5, true, 1, 'two\nlines', x'00ff'), (2, 3, 'b', -0.25, false, 2, null, null);");
+  ok!(e, &format!("insert into c values (1, 2, '{}', '2020-02-29'), (2, 3, null, null), (3, 2, 'short', null);", "note ".repeat(20)));
+  for i in 0..250 { ok!(e, &format!("insert into r values ({}, {});", i / 10, i % 10)); }
+  ok!(e, "delete from r where b = 5;");
+
+  let script = query(&mut e, "dump database;");
+  let pos = |s: &str| script.find(s).unwrap();
+  assert!(pos("create table p (") < pos("create table c ("), "{}", script);
+  assert!(script.contains("name char(20) not null default 'nobody'"));
+  assert!(script.contains("'O''Brien'"), "{}", script);
+  assert!(script.contains("note varchar(100) compressed,"));
+  assert!(script.contains("primary key (a, b)"));
+  assert!(script.contains("check (kind in (1, 2, 3))"));
+  assert!(script.contains("foreign key (pid) references p(id)"));
+  assert!(script.ends_with("alter table p add foreign key (boss) references p(id);"), "{}", script);
+  // 225 records of `r` are inserted by 3 statements
+  assert_eq!(script.matches("insert into r values").count(), 3);
+  let sels = ["select * from p;", "select * from c;", "select * from r;", "select id from p where score > 0.0;"];
+  let expect = sels.iter().map(|s| query(&mut e, s)).collect::<Vec<_>>();
+
+  ok!(e, "create database :memory:;");
+  ok!(e, &script);
+  assert_eq!(sels.iter().map(|s| query(&mut e, s)).collect::<Vec<_>>(), expect);
+  assert_eq!(query(&mut e, "dump database;"), script);
+  assert!(query(&mut e, "show table p;").contains("index: `p_score`"));
+  assert_eq!(query(&mut e, "select name from p where name = 'O''Brien';"), "name\n\"O'Brien\"");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+}
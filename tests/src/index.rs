@@ -4,10 +4,20 @@ use rand::prelude::*;
 use syntax::ast::*;
 use common::{*, BareTy::*};
 use physics::*;
-use index::Index;
+use index::{Index, MultiIndex, multi_index_num, primary_multi_index};
+use typed_arena::Arena;
+use crate::query;
 
 fn lit<'a>(x: i32) -> CLit<'a> { CLit::new(Lit::Number(x as f64)) }
 
+// the rows (without the header) of a select statement, sorted
+fn rows(e: &mut Eval, sql: &str) -> Vec<String> {
+  let alloc = Arena::default();
+  let s = match syntax::work(sql, &alloc).unwrap().pop() { Some(Stmt::Select(s)) => s, _ => unreachable!() };
+  let mut rows = e.select(&s).unwrap().csv().lines().skip(1).map(|x| x.to_owned()).collect::<Vec<_>>();
+  (rows.sort(), rows).1
+}
+
 #[test]
 fn index() {
   const N: usize = 10000;
@@ -53,14 +63,14 @@ fn index() {
     (test.copy_from_slice(&ins), test.shuffle(&mut rng));
     e.exec(&Stmt::CreateDb("index")).unwrap();
     e.exec(&Stmt::UseDb("index")).unwrap();
-    e.exec(&CreateTable { table: "index", cols: vec![ColDecl { col: "id", ty: ColTy::FixTy(FixTy { size: 0, ty: Int }), notnull: true, dft: None }], cons: vec![] }.into()).unwrap();
-    e.exec(&CreateIndex { index: "id_index", table: "index", col: "id" }.into()).unwrap();
+    e.exec(&CreateTable { table: "index", cols: vec![ColDecl { col: "id", ty: ColTy::FixTy(FixTy { size: 0, ty: Int }), notnull: true, dft: None, compressed: false }], cons: vec![] }.into()).unwrap();
+    e.exec(&CreateIndex { index: "id_index", table: "index", cols: vec!["id"] }.into()).unwrap();
     unsafe { // modify IndexPage's cap to generate more splits
       let db = e.db().unwrap();
       let (tp_id, tp) = db.get_tp("index").unwrap();
       let ci = tp.get_ci("id").unwrap();
       table = tp_id;
-      col = ci.idx(&tp.cols);
+      col = ci.idx(tp.cols());
       db.get_page::<IndexPage>(ci.index).cap = 8;
     }
     ins!();
@@ -73,4 +83,137 @@ fn index() {
     test!();
     e.exec(&Stmt::DropDb("index")).unwrap();
   }
-}
\ No newline at end of file
+}
+
+#[test]
+fn bulk_build() {
+  const N: i32 = 5000;
+  let mut e = Eval::default();
+  e.exec_all("create database :memory:; create table t (id int, v int);", &Arena::default(), |_| {}, |_| {}).unwrap();
+  // a lot of duplicate keys, and some nulls which are not in the index
+  let vals = (0..N).map(|i| format!("({}, {})", i, if i % 7 == 0 { "null".into() } else { (i * 37 % 1000).to_string() })).collect::<Vec<_>>();
+  e.exec_all(&format!("insert into t values {};", vals.join(", ")), &Arena::default(), |_| {}, |_| {}).unwrap();
+  let count = |e: &mut Eval, v: i32| e.select(&Select {
+    ops: None,
+    tables: vec!["t"],
+    where_: vec![Cond::Cmp(CmpOp::Eq, ColRef { table: None, col: "v" }, Atom::Lit(lit(v)))],
+  }).unwrap().row_count();
+  let expect = (0..1000).map(|v| (0..N).filter(|&i| i % 7 != 0 && i * 37 % 1000 == v).count()).collect::<Vec<_>>();
+  // 0 and 255 are clamped to 50 and 100
+  for &ff in &[0, 50, 75, 90, 100, 255] {
+    e.db().unwrap().set_fill_factor(ff);
+    e.exec_all("create index v_index on t (v);", &Arena::default(), |_| {}, |_| {}).unwrap();
+    unsafe {
+      let db = e.db().unwrap();
+      let (tp_id, tp) = db.get_tp("t").unwrap();
+      Index::<{ Int }>::new(db, tp_id, tp.get_ci("v").unwrap().idx(tp.cols())).debug_check_all();
+    }
+    for v in 0..1000 { assert_eq!(count(&mut e, v), expect[v as usize]); }
+    // later insertions and deletions work on the bulk built index
+    e.exec_all("insert into t values (-1, 500), (-2, 500); delete from t where v < 100;", &Arena::default(), |_| {}, |_| {}).unwrap();
+    assert_eq!(count(&mut e, 500), expect[500] + 2);
+    assert_eq!(count(&mut e, 50), 0);
+    e.exec_all("delete from t where id < 0; drop index v_index;", &Arena::default(), |_| {}, |_| {}).unwrap();
+    let vals = (0..N).filter(|&i| i % 7 != 0 && i * 37 % 1000 < 100).map(|i| format!("({}, {})", i, i * 37 % 1000)).collect::<Vec<_>>();
+    e.exec_all(&format!("insert into t values {};", vals.join(", ")), &Arena::default(), |_| {}, |_| {}).unwrap();
+  }
+}
+#[test]
+fn multi_index() {
+  const N: i32 = 3000;
+  let mut e = Eval::default();
+  ok!(e, "create database :memory:; create table t (id int, tenant int, created int, note varchar(8)); create table u (id int, tenant int, created int, note varchar(8));");
+  // `u` has the same data as `t` but no index, so it gives the expected results
+  let vals = (0..N).map(|i| format!("({}, {}, {}, 'n{}')", i, if i % 11 == 0 { "null".into() } else { (i * 7 % 10).to_string() },
+    if i % 13 == 0 { "null".into() } else { (i * 31 % 50).to_string() }, i % 5)).collect::<Vec<_>>().join(", ");
+  ok!(e, &format!("insert into t values {}; insert into u values {};", vals, vals));
+  ok!(e, "create index i on t (tenant, created);");
+  let conds = ["tenant = 3", "tenant = 3 and created = 17", "created = 17 and tenant = 3", "tenant = 3 and created < 20", "tenant = 3 and created <= 20",
+    "tenant = 3 and created > 20", "tenant = 3 and created >= 20", "tenant = 3 and created >= 20 and created < 30", "tenant = 3 and created <> 17",
+    "tenant = 3 and created is null", "tenant < 4", "tenant >= 8 and created = 2", "tenant is null", "tenant = 42"];
+  let check = |e: &mut Eval| {
+    for cond in &conds {
+      assert_eq!(rows(e, &format!("select * from t where {};", cond)), rows(e, &format!("select * from u where {};", cond)), "{}", cond);
+    }
+    unsafe {
+      let db = e.db().unwrap();
+      let tp_id = db.get_tp("t").unwrap().0;
+      MultiIndex::new(db, tp_id, 0).debug_check_all();
+    }
+    assert_eq!(query(e, "check database;"), "0 problem(s) found");
+  };
+  check(&mut e);
+  for table in &["t", "u"] {
+    ok!(e, &format!("update {0} set created = 7 where tenant = 3 and created > 40; update {0} set tenant = null where id < 100; \
+      update {0} set tenant = 3 where tenant is null and id > 2000; delete from {0} where tenant = 4 and created < 10;", table));
+  }
+  check(&mut e);
+  assert!(query(&mut e, "show table t;").contains("- index `i`: (tenant, created)"));
+  assert!(query(&mut e, "dump database;").contains("create index i on t (tenant, created);"));
+
+  let alloc = Arena::default();
+  let err = |e: &mut Eval, sql| e.exec_all(sql, &alloc, |_| {}, |_| {}).unwrap_err();
+  assert!(match err(&mut e, "create index i on t (id, note);") { Error::DupIndex("i") => true, _ => false });
+  assert!(match err(&mut e, "create index j on t (id, tenant, id);") { Error::DupCol("id") => true, _ => false });
+  assert!(match err(&mut e, "create index j on t (id, note);") { Error::UnsupportedVarcharOp("note") => true, _ => false });
+  assert!(match err(&mut e, "create index j on t (id, id, id, id, id, id, id, id, id);") { Error::IndexColTooMany(9) => true, _ => false });
+
+  // dropping a col of the index drops the index, and other indexes on the table keep working
+  ok!(e, "create index j on t (created, id); alter table t drop tenant;");
+  unsafe {
+    let db = e.db().unwrap();
+    let tp_id = db.get_tp("t").unwrap().0;
+    assert_eq!(multi_index_num(db, tp_id), 1);
+  }
+  assert!(!query(&mut e, "show table t;").contains("index `i`"));
+  assert_eq!(rows(&mut e, "select * from t where created = 7 and id < 1000;").len(), rows(&mut e, "select * from u where created = 7 and id < 1000;").len());
+  ok!(e, "drop index j;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+}
+
+#[test]
+fn primary_index() {
+  const N: i32 = 2000;
+  let mut e = Eval::default();
+  let alloc = Arena::default();
+  let err = |e: &mut Eval, sql| e.exec_all(sql, &alloc, |_| {}, |_| {}).unwrap_err();
+  ok!(e, "create database :memory:; create table p (a int, b char(4), c int, primary key (b, a));");
+  let vals = (0..N).map(|i| format!("({}, 'b{}', {})", i, i % 3, i % 7)).collect::<Vec<_>>().join(", ");
+  ok!(e, &format!("insert into p values {};", vals));
+  let check = |e: &mut Eval, cols: Option<&[u16]>| unsafe {
+    let db = e.db().unwrap();
+    let tp_id = db.get_tp("p").unwrap().0;
+    match (primary_multi_index(db, tp_id), cols) {
+      (Some(idx), Some(cols)) => {
+        assert_eq!(db.page::<IndexListPage>(db.page::<TablePage>(tp_id).indexes).indexes[idx as usize].cols(), cols);
+        MultiIndex::new(db, tp_id, idx).debug_check_all();
+      }
+      (None, None) => {}
+      x => panic!("{:?}", x.0),
+    }
+    assert_eq!(query(e, "check database;"), "0 problem(s) found");
+  };
+  // the key is in the order of cols, not in the order of the constraint
+  check(&mut e, Some(&[0, 1]));
+  assert!(query(&mut e, "show table p;").contains("- index <internal>: (a, b)"));
+  assert!(match err(&mut e, "insert into p values (5, 'b2', 0);") { Error::PutDupOnPrimary => true, _ => false });
+  ok!(e, &format!("insert into p values ({0}, 'b0', 0), ({0}, 'b1', 1);", N));
+  let sql = format!("update p set b = 'b1' where a = {} and b = 'b0';", N);
+  assert!(match e.exec_all(&sql, &Arena::default(), |_| {}, |_| {}).unwrap_err() { Error::PutDupOnPrimary => true, _ => false });
+  ok!(e, "update p set c = c + 1 where a < 100; update p set b = 'b9' where a = 3; update p set a = -1 where a = 4;");
+  assert_eq!(rows(&mut e, "select * from p where a = 3 and b = 'b9';").len(), 1);
+  check(&mut e, Some(&[0, 1]));
+
+  // the index follows the primary cols
+  ok!(e, &format!("delete from p where a = {}; alter table p drop primary key (b);", N));
+  check(&mut e, None);
+  ok!(e, "alter table p add primary key (c);");
+  check(&mut e, Some(&[0, 2]));
+  ok!(e, "alter table p drop b;");
+  check(&mut e, Some(&[0, 1]));
+  assert!(match err(&mut e, "insert into p values (0, 1);") { Error::PutDupOnPrimary => true, _ => false });
+
+  let cols = (0..9).map(|i| format!("c{}", i)).collect::<Vec<_>>();
+  let sql = format!("create table q ({} int, primary key ({}));", cols.join(" int, "), cols.join(", "));
+  assert!(match e.exec_all(&sql, &Arena::default(), |_| {}, |_| {}).unwrap_err() { Error::IndexColTooMany(9) => true, _ => false });
+}
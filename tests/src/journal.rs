@@ -0,0 +1,140 @@
+use std::{fs, path::Path};
+
+use driver::Eval;
+use db::Db;
+use syntax::ast::*;
+use common::*;
+use crate::query;
+
+#[test]
+fn journal() {
+  let mut e = Eval::default();
+  ok!(e, "create database journal; use journal;");
+  ok!(e, "create table t (id int, v varchar(1000), primary key (id));");
+  for i in 0..200 {
+    ok!(e, &format!("insert into t values ({}, '{}');", i, "x".repeat(i * 5)));
+  }
+  let old = query(&mut e, "select * from t;");
+  { // simulate a crash in the middle of a transaction: modify without commit, and drop the db
+    let db = e.db().unwrap();
+    db.begin().unwrap();
+    query::update(&Update { table: "t", sets: vec![("v", Expr::Atom(Atom::Lit(CLit::new(Lit::Str(&"y".repeat(999))))))], where_: vec![] }, db).unwrap();
+    query::delete(&Delete { table: "t", where_: vec![Cond::Cmp(CmpOp::Lt, ColRef { table: None, col: "id" }, Atom::Lit(CLit::new(Lit::Number(100.0))))] }, db).unwrap();
+    db.drop_table("t").unwrap();
+  }
+  drop(e);
+  let mut e = Eval::default();
+  ok!(e, "use journal;");
+  assert_eq!(query(&mut e, "select * from t;"), old);
+  // rollback in the same session also restores the database
+  {
+    let db = e.db().unwrap();
+    db.begin().unwrap();
+    db.drop_table("t").unwrap();
+    db.rollback().unwrap();
+  }
+  assert_eq!(query(&mut e, "select * from t;"), old);
+  ok!(e, "drop database journal;");
+}
+
+#[test]
+fn implicit() {
+  let mut e = Eval::default();
+  ok!(e, "create database implicit; use implicit;");
+  ok!(e, "create table t (id int, v varchar(100), primary key (id)); insert into t values (0, 'a'), (1, 'b');");
+  let old = query(&mut e, "select * from t;");
+  drop(e);
+  // a modification outside a transaction is journaled, copying the files before it is committed simulates a crash
+  let mut db = Db::open("implicit").unwrap();
+  db.drop_table("t").unwrap();
+  assert!(!db.in_transaction());
+  for &suffix in &["", LOB_SUFFIX, JOURNAL_SUFFIX, SUM_SUFFIX] {
+    fs::copy(Path::new("implicit").with_extension(suffix), Path::new("implicit_crash").with_extension(suffix)).unwrap();
+  }
+  drop(db);
+  let mut e = Eval::default();
+  ok!(e, "use implicit_crash;");
+  assert_eq!(query(&mut e, "select * from t;"), old);
+  // it is committed when the database is closed
+  ok!(e, "drop database implicit_crash; use implicit;");
+  err!(e, "select * from t;");
+  ok!(e, "drop database implicit;");
+}
+
+#[test]
+fn transaction() {
+  let mut e = Eval::default();
+  ok!(e, "create database transaction; use transaction;");
+  ok!(e, "create table t (id int, v varchar(100), primary key (id));");
+  ok!(e, "insert into t values (0, 'a');");
+  err!(e, "commit;");
+  err!(e, "rollback;");
+  let old = query(&mut e, "select * from t;");
+
+  ok!(e, "begin; insert into t values (1, 'b'); create table u (id int, foreign key (id) references t(id)); insert into u values (1);");
+  err!(e, "begin;");
+  ok!(e, "update t set v = 'ccccccccccccccccccccccccccccccccccccccccccccc' where id = 0; drop table u; delete from t where id = 0; rollback;");
+  assert_eq!(query(&mut e, "select * from t;"), old);
+  err!(e, "select * from u;");
+
+  ok!(e, "begin; insert into t values (1, 'b'); create table u (id int, foreign key (id) references t(id)); insert into u values (1); commit;");
+  ok!(e, "select * from u;");
+  let new = query(&mut e, "select * from t;");
+  assert_ne!(new, old);
+
+  // `Db::transaction` rollbacks all changes if an error occurs
+  let db = e.db().unwrap();
+  let res = db.transaction(|db| {
+    db.drop_table("u")?;
+    query::delete(&Delete { table: "t", where_: vec![] }, db)
+  });
+  assert!(res.is_ok());
+  let res: Result<()> = db.transaction(|db| {
+    query::insert(&Insert { table: "t", cols: None, vals: vec![vec![CLit::new(Lit::Number(2.0)), CLit::new(Lit::Str("d"))]] }, db)?;
+    db.drop_table("no_such_table")
+  });
+  assert!(res.is_err());
+  assert_eq!(query(&mut e, "select * from t;"), "id,v");
+  ok!(e, "drop database transaction;");
+}
+
+#[test]
+fn atomic() {
+  let mut e = Eval::default();
+  ok!(e, "create database atomic; use atomic;");
+  ok!(e, "create table t (id int, v varchar(100), primary key (id)); create index t_v on t (id);");
+  ok!(e, "insert into t values (0, 'a'), (1, 'b');");
+  let old = query(&mut e, "select * from t;");
+  // the error says that the statement is rolled back
+  match e.exec_all("insert into t values (2, 'c'), (3, 'dddddddddddddddddddddddddddddddddddddddddddd'), (0, 'e');", &typed_arena::Arena::default(), |_| {}, |_| {}).err().unwrap() {
+    Error::RolledBack(_) => {}
+    e => panic!("{:?}", e),
+  }
+  assert_eq!(query(&mut e, "select * from t;"), old);
+  err!(e, "update t set id = 1 - id, v = 'ffffffffffffffffffffffffffffffffffffffffff' where id = 1;");
+  assert_eq!(query(&mut e, "select * from t;"), old);
+
+  // in a transaction, a failed statement doesn't affect the statements before it
+  ok!(e, "begin; insert into t values (2, 'c');");
+  let new = query(&mut e, "select * from t;");
+  err!(e, "insert into t values (3, 'd'), (2, 'c');");
+  assert_eq!(query(&mut e, "select * from t;"), new);
+  ok!(e, "commit;");
+  assert_eq!(query(&mut e, "select * from t;"), new);
+  ok!(e, "drop database atomic;");
+}
+
+#[test]
+fn sync() {
+  use db::SyncMode::*;
+  let mut e = Eval::default();
+  ok!(e, "create database sync; use sync; create table t (id int, v varchar(100));");
+  for &mode in &[Statement, Transaction, Off] {
+    e.set_sync_mode(mode);
+    assert_eq!(e.db().unwrap().sync_mode(), mode);
+    ok!(e, "insert into t values (1, 'a'); begin; insert into t values (2, 'b'); update t set v = 'c'; commit;");
+  }
+  ok!(e, "checkpoint; begin; delete from t where id = 1; checkpoint; rollback;");
+  assert_eq!(e.select(&Select { ops: None, tables: vec!["t"], where_: vec![] }).unwrap().row_count(), 6);
+  ok!(e, "drop database sync;");
+}
@@ -1,6 +1,54 @@
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use driver::Eval;
+
+// helpers shared by the test modules, they are defined before the modules so that the macros are visible there
+
+#[cfg(test)]
+macro_rules! ok { ($e: expr, $sql: expr) => { $e.exec_all($sql, &typed_arena::Arena::default(), |_| {}, |_| {}).unwrap(); }; }
+#[cfg(test)]
+macro_rules! err { ($e: expr, $sql: expr) => { $e.exec_all($sql, &typed_arena::Arena::default(), |_| {}, |_| {}).unwrap_err(); }; }
+
+// the output of executing `sql`
+#[cfg(test)]
+fn query(e: &mut Eval, sql: &str) -> String {
+  let res = RefCell::new(String::new());
+  e.exec_all(sql, &typed_arena::Arena::default(), |_| {}, |x| res.borrow_mut().push_str(x)).unwrap();
+  res.into_inner()
+}
+
 #[cfg(test)]
 mod integrate;
 #[cfg(test)]
 mod index;
 #[cfg(test)]
-mod lob;
\ No newline at end of file
+mod lob;
+#[cfg(test)]
+mod journal;
+#[cfg(test)]
+mod checksum;
+#[cfg(test)]
+mod vacuum;
+#[cfg(test)]
+mod memory;
+#[cfg(test)]
+mod catalog;
+#[cfg(test)]
+mod version;
+#[cfg(test)]
+mod check;
+#[cfg(test)]
+mod lock;
+#[cfg(test)]
+mod shared;
+#[cfg(test)]
+mod backup;
+#[cfg(test)]
+mod text;
+#[cfg(test)]
+mod compress;
+#[cfg(test)]
+mod dump;
+#[cfg(test)]
+mod copy;
\ No newline at end of file
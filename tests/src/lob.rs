@@ -1,8 +1,10 @@
+use std::{fs, path::Path};
 use driver::Eval;
 use rand::prelude::*;
 use syntax::ast::*;
 use common::{*, BareTy::*};
 use physics::*;
+use crate::query;
 
 fn lit<'a>(x: usize) -> CLit<'a> { CLit::new(Lit::Number(x as f64)) }
 
@@ -18,12 +20,12 @@ fn lob() {
   e.exec(&CreateTable {
     table: "lob",
     cols: vec![
-      ColDecl { col: "id", ty: ColTy::FixTy(FixTy { size: 0, ty: Int }), notnull: true, dft: None },
-      ColDecl { col: "v", ty: ColTy::Varchar((MAX_LEN * LOB_SLOT_SIZE) as u16), notnull: true, dft: None }
+      ColDecl { col: "id", ty: ColTy::FixTy(FixTy { size: 0, ty: Int }), notnull: true, dft: None, compressed: false },
+      ColDecl { col: "v", ty: ColTy::Varchar((MAX_LEN * LOB_SLOT_SIZE) as u16), notnull: true, dft: None, compressed: false }
     ],
     cons: vec![],
   }.into()).unwrap();
-  e.exec(&CreateIndex { index: "id_index", table: "lob", col: "id" }.into()).unwrap();
+  e.exec(&CreateIndex { index: "id_index", table: "lob", cols: vec!["id"] }.into()).unwrap();
   let mut result = Vec::new();
   for i in 0..N {
     if rng.gen_bool(ALLOC_RATE) {
@@ -58,4 +60,99 @@ fn lob() {
     }
   }
   e.exec(&Stmt::DropDb("lob")).unwrap();
-}
\ No newline at end of file
+}
+
+#[test]
+fn lob_coalesce() {
+  let mut e = Eval::default();
+  ok!(e, "create database lob_coalesce; use lob_coalesce;");
+  ok!(e, "create table t (id int, v varchar(1000));");
+  // each value takes 2 slots
+  for i in 0..10 { ok!(e, &format!("insert into t values ({}, '{}');", i, "x".repeat(64))); }
+  ok!(e, "delete from t where id = 1; delete from t where id = 3; delete from t where id = 5;");
+  assert_eq!(query(&mut e, "show lob;"), "lob: slot count = 21, used slot count = 14, free slot count = 6, free extent count = 3, largest free extent = 2, fragmentation = 66.7%\n  - extent size [2, 4): extent count = 3, slot count = 6");
+  // the index of free extents is rebuilt after rollback
+  ok!(e, "begin; delete from t where id = 2; rollback;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 21, used slot count = 14, free slot count = 6, free extent count = 3,"));
+  // merged with both neighbors
+  ok!(e, "delete from t where id = 2;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 21, used slot count = 12, free slot count = 8, free extent count = 2, largest free extent = 6, fragmentation = 25.0%"));
+  // best fit: the 2-slot extent is used instead of splitting the 6-slot one, and the file doesn't grow
+  ok!(e, &format!("insert into t values (10, '{}');", "y".repeat(40)));
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 21, used slot count = 14, free slot count = 6, free extent count = 1, largest free extent = 6, fragmentation = 0.0%"));
+  ok!(e, &format!("insert into t values (11, '{}');", "z".repeat(100)));
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 21, used slot count = 18, free slot count = 2, free extent count = 1,"));
+  // no extent fits, the free extent at the end of the file is extended
+  ok!(e, "delete from t where id = 9;");
+  ok!(e, &format!("insert into t values (12, '{}');", "w".repeat(128)));
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 23, used slot count = 20, free slot count = 2, free extent count = 1,"));
+  ok!(e, "delete from t;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 23, used slot count = 0, free slot count = 22, free extent count = 1, largest free extent = 22, fragmentation = 0.0%"));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop database lob_coalesce;");
+}
+
+#[test]
+fn lob_compact() {
+  fn lob_size() -> u64 { fs::metadata(Path::new("lob_compact").with_extension(LOB_SUFFIX)).unwrap().len() }
+  let mut e = Eval::default();
+  ok!(e, "create database lob_compact; use lob_compact;");
+  ok!(e, "create table t (id int, v varchar(1000)); create table u (id int, v varchar(100));");
+  // each value takes 2 slots, the varchars of two tables are interleaved
+  for i in 0..20 {
+    ok!(e, &format!("insert into t values ({}, '{:0>64}');", i, i));
+    if i % 2 == 0 { ok!(e, &format!("insert into u values ({}, '{:0>40}');", i, i)); }
+  }
+  ok!(e, "insert into t values (20, null);");
+  ok!(e, "delete from t where id < 10;");
+  let (sel1, sel2) = ("select * from t;", "select * from u;");
+  let (old1, old2) = (query(&mut e, sel1), query(&mut e, sel2));
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 61, used slot count = 40, free slot count = 20,"));
+
+  ok!(e, "begin; compact lob;");
+  assert_eq!(lob_size(), 41 * LOB_SLOT_SIZE as u64);
+  ok!(e, "rollback;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 61, used slot count = 40, free slot count = 20,"));
+  assert_eq!((query(&mut e, sel1), query(&mut e, sel2)), (old1.clone(), old2.clone()));
+
+  ok!(e, "compact lob;");
+  assert_eq!(query(&mut e, "show lob;"), "lob: slot count = 41, used slot count = 40, free slot count = 0, free extent count = 0, largest free extent = 0, fragmentation = 0.0%");
+  assert_eq!(lob_size(), 41 * LOB_SLOT_SIZE as u64);
+  assert_eq!((query(&mut e, sel1), query(&mut e, sel2)), (old1, old2));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  // the compacted file is still used as usual
+  ok!(e, &format!("update t set v = '{:0>100}' where id = 15;", 15));
+  ok!(e, "delete from u where id = 0;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  ok!(e, "delete from t; delete from u; vacuum;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 1, used slot count = 0, free slot count = 0,"));
+  assert_eq!(lob_size(), LOB_SLOT_SIZE as u64);
+  ok!(e, "drop database lob_compact;");
+}
+
+#[test]
+fn inline_varchar() {
+  let mut e = Eval::default();
+  ok!(e, "create database inline_varchar; use inline_varchar;");
+  ok!(e, "create table t (id int, code varchar(100));");
+  ok!(e, "insert into t values (1, ''), (2, 'ab'), (3, 'abcdef'), (4, 'abcdefg'), (5, null);");
+  assert!(query(&mut e, "show database inline_varchar;").contains("INLINE_VARCHAR"));
+  // only the 7-byte one is in lob
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 2, used slot count = 1,"));
+  assert_eq!(query(&mut e, "select * from t where code like 'ab%';"), "id,code\n2,\"ab\"\n3,\"abcdef\"\n4,\"abcdefg\"");
+  assert_eq!(query(&mut e, "select id from t where code = 'abcdef';"), "id\n3");
+  assert_eq!(query(&mut e, "select id from t where code < 'a';"), "id\n1");
+
+  // inline => lob => inline
+  ok!(e, "update t set code = 'x' where id = 4;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 2, used slot count = 0,"));
+  ok!(e, "update t set code = 'a longer code' where id = 2; update t set code = 'abc' where id = 5;");
+  assert!(query(&mut e, "show lob;").starts_with("lob: slot count = 2, used slot count = 1,"));
+  assert_eq!(query(&mut e, "select * from t;"), "id,code\n1,\"\"\n2,\"a longer code\"\n3,\"abcdef\"\n4,\"x\"\n5,\"abc\"");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "compact lob; delete from t where id < 4;");
+  assert_eq!(query(&mut e, "select * from t;"), "id,code\n4,\"x\"\n5,\"abc\"");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop database inline_varchar;");
+}
@@ -0,0 +1,28 @@
+use std::{fs, path::Path};
+use driver::Eval;
+use db::Db;
+use common::{JOURNAL_SUFFIX, Error::*};
+
+#[test]
+fn lock() {
+  let mut e = Eval::default();
+  ok!(e, "create database lock; use lock; create table t (id int); insert into t values (1);");
+  match Db::open("lock").err().unwrap() { DatabaseLocked => {} e => panic!("{:?}", e) }
+  match Db::open_read_only("lock").err().unwrap() { DatabaseLocked => {} e => panic!("{:?}", e) }
+  err!(Eval::default(), "create database lock;");
+  // the database in use is not opened again
+  ok!(e, "show database lock; show databases; use lock;");
+  drop(e);
+
+  // a read-only session doesn't create the journal
+  let journal = Path::new("lock").with_extension(JOURNAL_SUFFIX);
+  fs::remove_file(&journal).unwrap();
+  let (mut r1, r2) = (Db::open_read_only("lock").unwrap(), Db::open_read_only("lock").unwrap());
+  match Db::open("lock").err().unwrap() { DatabaseLocked => {} e => panic!("{:?}", e) }
+  match r1.transaction(|_| Ok::<_, common::Error>(())).err().unwrap() { ReadOnlyDatabase => {} e => panic!("{:?}", e) }
+  (drop(r1), drop(r2));
+  assert!(!journal.exists());
+
+  let mut e = Eval::default();
+  ok!(e, "use lock; insert into t values (2); drop database lock;");
+}
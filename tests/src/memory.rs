@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use driver::Eval;
+use db::Db;
+use common::{BareTy::*, Ref2PtrMut};
+use index::Index;
+use crate::query;
+
+#[test]
+fn memory() {
+  const N: usize = 2000;
+  let mut e = Eval::default();
+  ok!(e, "create database :MEMORY:;");
+  ok!(e, "create table t (id int, v varchar(300), primary key (id));");
+  // long varchars make the lob buffer grow (and move) many times
+  for i in 0..N {
+    ok!(e, &format!("insert into t values ({}, '{}');", i, i.to_string().repeat(i % 50 + 1)));
+  }
+  assert_eq!(query(&mut e, "select v from t where id = 123;"), format!("v\n\"{}\"", "123".repeat(24)));
+  ok!(e, "update t set v = 'x' where id < 1000;");
+  assert_eq!(query(&mut e, "select id from t where v = 'x';").lines().count(), 1000 + 1);
+
+  let old = query(&mut e, "select * from t;");
+  ok!(e, "begin; delete from t where id >= 500; update t set v = 'yyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyy'; rollback;");
+  assert_eq!(query(&mut e, "select * from t;"), old);
+
+  ok!(e, "delete from t where id >= 100; vacuum;");
+  assert_eq!(query(&mut e, "select id from t;").lines().count(), 100 + 1);
+  unsafe {
+    let db = e.db().unwrap();
+    let (tp_id, tp) = db.get_tp("t").unwrap();
+    Index::<{ Int }>::new(db, tp_id, tp.get_ci("id").unwrap().idx(tp.cols())).debug_check_all();
+  }
+  assert!(!Path::new(":memory:").exists());
+}
+
+#[test]
+fn in_memory() {
+  let mut db = Db::in_memory();
+  let mut e = Eval::default();
+  ok!(e, "create database :memory:; create table t (id int);");
+  // each in-memory database is independent
+  assert!(unsafe { db.get_tp("t") }.is_err());
+  db.transaction(|db| db.begin()).unwrap_err();
+  db.sync().unwrap();
+}
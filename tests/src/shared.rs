@@ -0,0 +1,60 @@
+use std::{sync::Arc, thread};
+use typed_arena::Arena;
+
+use driver::Eval;
+use db::{Db, SharedDb};
+use syntax::ast::*;
+
+fn stmt<'a>(sql: &'a str, alloc: &'a Arena<u8>) -> Stmt<'a> { syntax::work(sql, alloc).unwrap().pop().unwrap() }
+
+fn count(db: &SharedDb, sql: &str) -> usize {
+  let alloc = Arena::default();
+  match stmt(sql, &alloc) {
+    Stmt::Select(s) => query::select(&s, &db.read()).unwrap().row_count(),
+    _ => unreachable!(),
+  }
+}
+
+#[test]
+fn shared() {
+  fn send_sync<T: Send + Sync>() {}
+  send_sync::<SharedDb>();
+
+  let mut e = Eval::default();
+  e.exec_all("create database shared; use shared; create table t (id int, v varchar(20)); create index ti on t (id);", &Arena::default(), |_| {}, |_| {}).unwrap();
+  for i in 0..100 {
+    e.exec_all(&format!("insert into t values ({}, 'value{}');", i, i), &Arena::default(), |_| {}, |_| {}).unwrap();
+  }
+  drop(e);
+
+  let db = Arc::new(SharedDb::new(Db::open("shared").unwrap()));
+  let readers = (0..4).map(|_| {
+    let db = db.clone();
+    thread::spawn(move || for _ in 0..100 {
+      let all = count(&db, "select * from t;");
+      assert!(100 <= all && all <= 300, "{}", all);
+      assert_eq!(count(&db, "select id from t where id = 42;"), 1);
+      assert_eq!(count(&db, "select v from t where id < 50 and v like 'value%';"), 50);
+      // `show` only reads pages, like `select`
+      assert!(db.read().show_table("t").unwrap().starts_with("table `t`"));
+      assert!(db.read().show_tables().unwrap().contains("index: `ti`"));
+    })
+  }).collect::<Vec<_>>();
+  let writer = {
+    let db = db.clone();
+    thread::spawn(move || for i in 100..300 {
+      let (sql, alloc) = (format!("insert into t values ({}, 'value{}');", i, i), Arena::default());
+      match stmt(&sql, &alloc) {
+        Stmt::Insert(ins) => { query::insert(&ins, &mut db.write()).unwrap(); }
+        _ => unreachable!(),
+      }
+    })
+  };
+  for r in readers { r.join().unwrap(); }
+  writer.join().unwrap();
+  assert_eq!(count(&db, "select * from t;"), 300);
+  assert_eq!(count(&db, "select id from t where id >= 200;"), 100);
+
+  drop(Arc::try_unwrap(db).ok().unwrap().into_inner());
+  Eval::default().exec(&Stmt::DropDb("shared")).unwrap();
+}
@@ -0,0 +1,76 @@
+use typed_arena::Arena;
+
+use driver::Eval;
+use common::{Error::*, Lit, CLit};
+use crate::query;
+
+fn lob_slots(e: &mut Eval) -> usize {
+  let lob = query(e, "show lob;");
+  let s = &lob[lob.find("slot count = ").unwrap() + 13..];
+  s[..s.find(',').unwrap()].parse().unwrap()
+}
+
+#[test]
+fn text_blob() {
+  let mut e = Eval::default();
+  ok!(e, "create database text_blob; use text_blob;");
+  ok!(e, "create table d (id int, body text, data blob);");
+  assert!(query(&mut e, "show database text_blob;").contains("LOB_TYPES"));
+  ok!(e, "insert into d values (1, 'hello', x'DEADbeef'), (2, '', x''), (3, null, null);");
+  assert_eq!(query(&mut e, "select * from d where id < 3;"), "id,body,data\n1,\"hello\",x'DEADBEEF'\n2,\"\",x''");
+  // the pointer of an empty slice may dangle, it must not be decoded as another kind of literal
+  assert_eq!(format!("{:?}", CLit::new(Lit::Blob(&Vec::new()))), "x''");
+  assert_eq!(format!("{:?}", CLit::new(Lit::Str(&String::new()))), "''");
+  std::fs::write("text_blob.csv", "5,\"\",\"\"\n").unwrap();
+  ok!(e, "copy d from 'text_blob.csv';");
+  assert_eq!(query(&mut e, "select * from d where id = 5;"), "id,body,data\n5,\"\",x''");
+  ok!(e, "delete from d where id = 5;");
+  std::fs::remove_file("text_blob.csv").unwrap();
+
+  // longer than any varchar
+  let long = format!("{{\"k\": \"{}\"}}", "v".repeat(100000));
+  ok!(e, &format!("insert into d values (4, '{}', x'{}');", long, "0a".repeat(70000)));
+  assert_eq!(query(&mut e, &format!("select id from d where body = '{}';", long)), "id\n4");
+  assert_eq!(query(&mut e, "select id from d where body like '{\"k\": \"vvv%';"), "id\n4");
+  assert_eq!(query(&mut e, "select id from d where data = x'deadbeef';"), "id\n1");
+  assert_eq!(query(&mut e, "select id from d where data > x'0a';"), "id\n1\n4");
+  assert_eq!(query(&mut e, "select max(data) from d;"), "max(data)\nx'DEADBEEF'");
+
+  match e.exec_all("select id from d where data = 'deadbeef';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ColLitMismatch { .. } => {} e => panic!("{:?}", e) }
+  match e.exec_all("insert into d values (5, x'00', null);", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ColLitMismatch { .. } => {} e => panic!("{:?}", e) }
+  match e.exec_all("select id from d where data like 'a%';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { InvalidLikeTy(_) => {} e => panic!("{:?}", e) }
+  match e.exec_all("select id from d where data = x'abc';", &Arena::default(), |_| {}, |_| {}).err().unwrap() { ParserErrors(_) => {} e => panic!("{:?}", e) }
+  match e.exec_all("create index di on d (body);", &Arena::default(), |_| {}, |_| {}).err().unwrap() { UnsupportedVarcharOp(_) => {} e => panic!("{:?}", e) }
+
+  // the lob is reused, grown, and allocated for a null value
+  ok!(e, "update d set body = 'world' where id = 1;");
+  ok!(e, &format!("update d set body = '{}', data = x'{}' where id = 2;", "w".repeat(1000), "ff".repeat(1000)));
+  ok!(e, "update d set body = 'was null', data = x'01' where id = 3;");
+  ok!(e, "update d set body = null where id = 4;");
+  assert_eq!(query(&mut e, "select id, body from d where id <> 2 and id <> 4;"), "id,body\n1,\"world\"\n3,\"was null\"");
+  assert_eq!(query(&mut e, "select id from d where body like 'w%';"), "id\n1\n2\n3");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  ok!(e, "compact lob;");
+  assert_eq!(query(&mut e, "select id, data from d where id <> 2 and id <> 4;"), "id,data\n1,x'DEADBEEF'\n3,x'01'");
+  ok!(e, "delete from d;");
+  assert!(query(&mut e, "show lob;").contains("used slot count = 0,"));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+
+  // a long value is split into the free extents between other values, instead of growing the lob file
+  ok!(e, "compact lob;");
+  for i in 0..10 { ok!(e, &format!("insert into d values ({}, '{}', null);", i, i.to_string().repeat(2000))); }
+  for i in (0..10).step_by(2) { ok!(e, &format!("delete from d where id = {};", i)); }
+  let slots = lob_slots(&mut e);
+  let long = "0123456789".repeat(800);
+  ok!(e, &format!("insert into d values (10, '{}', x'ab');", long));
+  assert_eq!(lob_slots(&mut e), slots);
+  assert_eq!(query(&mut e, "select id, body from d where body like '0123%';"), format!("id,body\n10,\"{}\"", long));
+  assert_eq!(query(&mut e, &format!("select id from d where body = '{}';", long)), "id\n10");
+  ok!(e, "update d set body = body where id = 10;");
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "compact lob;");
+  assert_eq!(query(&mut e, "select id, body, data from d where id = 10;"), format!("id,body,data\n10,\"{}\",x'AB'", long));
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  ok!(e, "drop database text_blob;");
+}
@@ -0,0 +1,71 @@
+use typed_arena::Arena;
+
+use driver::Eval;
+use common::{BareTy::*, Ref2PtrMut};
+use physics::DataPage;
+use index::Index;
+use crate::query;
+
+fn file_size() -> u64 { std::fs::metadata("vacuum_db").unwrap().len() }
+
+#[test]
+fn vacuum() {
+  const N: usize = 5000;
+  let mut e = Eval::default();
+  ok!(e, "create database vacuum_db; use vacuum_db;");
+  ok!(e, "create table t (id int, v varchar(100), w char(200), primary key (id)); create index t_w on t (w);");
+  ok!(e, "create table u (id int, foreign key (id) references t(id));");
+  for i in 0..N {
+    ok!(e, &format!("insert into t values ({}, '{}', '{}');", i, i, i));
+    // the pages of u are not moved by `vacuum t`, so keep them away from the end of the file
+    if i == 4000 { ok!(e, "insert into u values (1), (4000);"); }
+  }
+  let full = file_size();
+  ok!(e, "delete from t where id > 1 and id < 3000;");
+  ok!(e, "delete from t where id > 4000;");
+  let (sel1, sel2) = ("select * from t where id = 4000;", "select * from t where w = '1';");
+  let (old1, old2, count) = (query(&mut e, sel1), query(&mut e, sel2), query(&mut e, "select count(*) from t;"));
+  assert_eq!(file_size(), full);
+
+  ok!(e, "vacuum t;");
+  let t = file_size();
+  assert!(t < full);
+  assert_eq!((query(&mut e, sel1), query(&mut e, sel2), query(&mut e, "select count(*) from t;")), (old1, old2, count));
+  unsafe {
+    let db = e.db().unwrap();
+    let (tp_id, tp) = db.get_tp("t").unwrap();
+    let (id, w) = (tp.get_ci("id").unwrap().idx(tp.cols()), tp.get_ci("w").unwrap().idx(tp.cols()));
+    Index::<{ Int }>::new(db, tp_id, id).debug_check_all();
+    Index::<{ Char }>::new(db, tp_id, w).debug_check_all();
+  }
+  // foreign key check still works
+  e.exec_all("delete from t where id = 4000;", &Arena::default(), |_| {}, |_| {}).unwrap_err();
+
+  ok!(e, "delete from u; delete from t; vacuum;");
+  assert!(file_size() < t);
+  assert_eq!(query(&mut e, "select * from t;"), "id,v,w");
+  ok!(e, "drop database vacuum_db;");
+}
+
+#[test]
+fn vacuum_trailing_empty() {
+  let mut e = Eval::default();
+  ok!(e, "create database vacuum_empty_db; use vacuum_empty_db;");
+  ok!(e, "create table t (id int, w char(200));");
+  let cap = unsafe { e.db().unwrap().get_tp("t").unwrap().1.cap as usize };
+  // [full, full, one record], then the record in the last page is deleted, leaving [full, full, empty]
+  for i in 0..cap * 2 + 1 {
+    ok!(e, &format!("insert into t values ({}, '{}');", i, i));
+  }
+  ok!(e, &format!("delete from t where id = {};", cap * 2));
+  ok!(e, "vacuum t;");
+  unsafe {
+    let db = e.db().unwrap();
+    let tp = db.get_tp("t").unwrap().1;
+    let (mut pages, mut cur) = (0, tp.first);
+    while cur != !0 { (pages += 1, cur = db.get_page::<DataPage>(cur).next); }
+    assert_eq!((pages, tp.first_free), (2, !0));
+  }
+  assert_eq!(query(&mut e, "select count(*) from t;"), format!("count(*)\n{}", cap * 2));
+  ok!(e, "drop database vacuum_empty_db;");
+}
@@ -0,0 +1,76 @@
+use std::{fs::{self, OpenOptions}, io::{Write, Seek, SeekFrom}, path::Path};
+use typed_arena::Arena;
+
+use driver::Eval;
+use db::Db;
+use syntax::ast::*;
+use physics::*;
+use common::{*, Error::*};
+use index::primary_multi_index;
+use crate::query;
+
+// overwrite the bytes at `off` of the file, the checksums are removed so that the modification is not regarded as corruption
+fn write(off: usize, bytes: &[u8]) {
+  let mut f = OpenOptions::new().write(true).open("version").unwrap();
+  (f.seek(SeekFrom::Start(off as u64)).unwrap(), f.write_all(bytes).unwrap());
+  let _ = fs::remove_file(Path::new("version").with_extension(SUM_SUFFIX));
+}
+
+fn set(off: usize, val: u8) { write(off, &[val]); }
+
+#[test]
+fn version() {
+  let long = "t".repeat(40);
+  let mut e = Eval::default();
+  e.exec_all(&format!("create database version; use version; create table {} (id int, v int); insert into {} values (1, 2), (1, 3);", long, long), &Arena::default(), |_| {}, |_| {}).unwrap();
+  let tp_id = unsafe {
+    let db = e.db().unwrap();
+    let dp = db.dp();
+    assert_eq!((dp.version, dp.features), (FORMAT_VERSION, DbFeatures::LONG_NAME));
+    db.get_tp(&long).unwrap().0
+  };
+  drop(e);
+
+  set(MAGIC_LEN, FORMAT_VERSION + 1);
+  match Db::open("version").err().unwrap() {
+    UnsupportedVersion { version, current } => assert_eq!((version, current), (FORMAT_VERSION + 1, FORMAT_VERSION)),
+    e => panic!("{:?}", e),
+  }
+  set(MAGIC_LEN, FORMAT_VERSION);
+  set(MAGIC_LEN + 1, 0x80 | DbFeatures::LONG_NAME.bits());
+  match Db::open("version").err().unwrap() {
+    UnsupportedFeatures(bits) => assert_eq!(bits, 0x80),
+    e => panic!("{:?}", e),
+  }
+
+  // a file without version and features has the TablePage layout of version 0, where all names are inline and the table name
+  // can take 46 bytes; it is upgraded, and the table name too long for the current inline field goes to the name area
+  // a primary key on more than one col is not indexed in version 0, the index is built when upgrading
+  let tp = tp_id as usize * PAGE_SIZE;
+  write(tp + 16, &[long.len() as u8]);
+  write(tp + 17, long.as_bytes());
+  write(tp + 63, &[2]); // col_num
+  for (i, name) in ["id", "v"].iter().enumerate() {
+    let ci = tp + 64 * (i + 1);
+    write(ci + 16, &[0, (ColFlags::PRIMARY | ColFlags::NOTNULL).bits()]); // f_col and flags of the ColInfo, `off` is at the same place
+    write(ci + 20, &[0]); // idx_name_len
+    write(ci + 36, &[name.len() as u8]); // name_len and name
+    write(ci + 37, name.as_bytes());
+  }
+  (set(MAGIC_LEN, 0), set(MAGIC_LEN + 1, 0));
+  unsafe {
+    let mut db = Db::open("version").unwrap();
+    let dp = db.dp();
+    assert_eq!((dp.version, dp.features), (FORMAT_VERSION, DbFeatures::LONG_NAME));
+    assert!(primary_multi_index(&db, tp_id).is_some());
+  }
+  let mut e = Eval::default();
+  e.exec(&Stmt::UseDb("version")).unwrap();
+  let select = Select { ops: None, tables: vec![long.as_str()], where_: vec![] };
+  assert_eq!(e.select(&select).unwrap().row_count(), 2);
+  unsafe { assert_eq!(e.db().unwrap().dp().version, FORMAT_VERSION); }
+  assert_eq!(query(&mut e, "check database;"), "0 problem(s) found");
+  err!(e, &format!("insert into {} values (1, 3);", long));
+  ok!(e, &format!("insert into {} values (2, 3);", long));
+  e.exec(&Stmt::DropDb("version")).unwrap();
+}